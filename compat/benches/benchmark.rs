@@ -2,7 +2,9 @@ use arbitrary::{Arbitrary, Unstructured};
 use bencher::{benchmark_group, benchmark_main, Bencher};
 use libmudtelnet::bytes::Bytes;
 use libmudtelnet::compatibility::CompatibilityEntry;
-use libmudtelnet::events::{TelnetIAC, TelnetNegotiation, TelnetSubnegotiation};
+use libmudtelnet::events::{TelnetHandler, TelnetIAC, TelnetNegotiation, TelnetSubnegotiation};
+use libmudtelnet::telnet::op_command::{GA, IAC};
+use libmudtelnet::telnet::op_option::GMCP;
 use rand::rngs::StdRng;
 use rand::{RngCore, SeedableRng};
 use std::hint::black_box;
@@ -59,6 +61,117 @@ fn bench_receive(b: &mut Bencher) {
   });
 }
 
+/// Consumes events without keeping them, like a client rendering received text as it arrives.
+#[derive(Default)]
+struct NullHandler {
+  bytes: usize,
+}
+
+impl TelnetHandler for NullHandler {
+  fn on_data(&mut self, data: &[u8]) {
+    self.bytes += data.len();
+  }
+
+  fn on_iac(&mut self, iac: TelnetIAC) {
+    black_box(iac);
+  }
+
+  fn on_negotiation(&mut self, negotiation: TelnetNegotiation) {
+    black_box(negotiation);
+  }
+
+  fn on_subnegotiation(&mut self, _option: u8, data: &[u8]) {
+    self.bytes += data.len();
+  }
+
+  fn on_send(&mut self, data: Bytes) {
+    black_box(data);
+  }
+}
+
+fn bench_receive_with(b: &mut Bencher) {
+  let mut data_buf = [0; 1024 * 1024];
+  StdRng::from_seed(SEED).fill_bytes(&mut data_buf);
+
+  b.iter(|| {
+    let mut parser = libmudtelnet::Parser::default();
+    for i in 0..255 {
+      parser.options.set_option(
+        i,
+        CompatibilityEntry {
+          local: true,
+          remote: true,
+          local_state: false,
+          remote_state: false,
+        },
+      );
+    }
+
+    const INPUT_EVENTS: usize = 10_000;
+    let mut handler = NullHandler::default();
+    for _ in 0..INPUT_EVENTS {
+      let input_event = SimulationEvents::arbitrary(&mut Unstructured::new(&data_buf)).unwrap();
+      parser.receive_with(&input_event.to_bytes(), &mut handler);
+    }
+    black_box(handler.bytes);
+  });
+}
+
+fn bench_subnegotiation_byte_at_a_time(b: &mut Bencher) {
+  let payload = Bytes::from(vec![b'a'; 1024 * 1024]);
+  let data = TelnetSubnegotiation::new(GMCP, payload).to_bytes();
+
+  b.iter(|| {
+    let mut parser = libmudtelnet::Parser::default();
+    parser.options.set_option(
+      GMCP,
+      CompatibilityEntry {
+        local: true,
+        remote: true,
+        local_state: true,
+        remote_state: false,
+      },
+    );
+
+    let mut handler = NullHandler::default();
+    for byte in data.iter() {
+      parser.receive_with(&[*byte], &mut handler);
+    }
+    assert_eq!(handler.bytes, 1024 * 1024);
+  });
+}
+
+/// A corpus of mostly text, like a busy combat log, with a prompt ending in `IAC GA` every few
+/// lines.
+fn text_corpus() -> Vec<u8> {
+  let mut corpus = Vec::with_capacity(1024 * 1024);
+  let mut line = 0;
+  while corpus.len() < 1024 * 1024 {
+    corpus
+      .extend_from_slice(b"\x1b[31mA goblin warrior slashes you with a rusty scimitar!\x1b[0m\r\n");
+    line += 1;
+    if line % 20 == 0 {
+      corpus.extend_from_slice(b"<100hp 80m 120mv> ");
+      corpus.extend_from_slice(&[IAC, GA]);
+    }
+  }
+  corpus
+}
+
+fn bench_text_corpus(b: &mut Bencher) {
+  let corpus = text_corpus();
+  b.bytes = corpus.len() as u64;
+
+  b.iter(|| {
+    let mut parser = libmudtelnet::Parser::default();
+    let mut handler = NullHandler::default();
+    for chunk in corpus.chunks(4096) {
+      parser.receive_with(chunk, &mut handler);
+    }
+    black_box(handler.bytes);
+  });
+}
+
 fn bench_og_receive(b: &mut Bencher) {
   let mut data_buf = [0; 1024 * 1024];
   StdRng::from_seed(SEED).fill_bytes(&mut data_buf);
@@ -85,6 +198,13 @@ fn bench_og_receive(b: &mut Bencher) {
   });
 }
 
-benchmark_group!(parser_benches, bench_receive, bench_og_receive);
+benchmark_group!(
+  parser_benches,
+  bench_receive,
+  bench_receive_with,
+  bench_subnegotiation_byte_at_a_time,
+  bench_text_corpus,
+  bench_og_receive
+);
 
 benchmark_main!(parser_benches);
@@ -1,4 +1,6 @@
 use libmudtelnet::compatibility::CompatibilityTable;
+use libmudtelnet::config::{OverflowPolicy, ParserConfig};
+use libmudtelnet::events::{TelnetError, TelnetEvents};
 use libmudtelnet::Parser;
 
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -15,6 +17,56 @@ pub fn test_app(app: &TelnetApplication) {
   }
 }
 
+#[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct LimitedApplication {
+  pub app: TelnetApplication,
+  pub max_subnegotiation_len: u16,
+  pub max_buffered_bytes: u16,
+  pub overflow_policy: u8,
+}
+
+pub fn test_limited_app(limited: &LimitedApplication) {
+  let options = CompatibilityTable::from_options(&limited.app.options);
+  let mut parser = Parser::with_support(options.clone());
+  let max_subnegotiation_len = usize::from(limited.max_subnegotiation_len);
+  let max_buffered_bytes = usize::from(limited.max_buffered_bytes);
+  parser.set_config(ParserConfig {
+    max_subnegotiation_len,
+    max_buffered_bytes,
+    overflow_policy: match limited.overflow_policy % 3 {
+      0 => OverflowPolicy::TruncateAndError,
+      1 => OverflowPolicy::DiscardUntilSe,
+      _ => OverflowPolicy::Reset,
+    },
+    report_protocol_errors: true,
+  });
+  // Until an overflow is reported, the limits must not change what is received.
+  let mut unlimited = Parser::with_support(options);
+  unlimited.set_config(ParserConfig {
+    report_protocol_errors: true,
+    ..ParserConfig::default()
+  });
+  let mut overflowed = false;
+  for data in &limited.app.received_data {
+    let events = parser.receive(data);
+    for event in &events {
+      match event {
+        TelnetEvents::Subnegotiation(sub) => {
+          assert!(sub.buffer.len() <= max_subnegotiation_len);
+        }
+        TelnetEvents::ProtocolError(TelnetError::BufferOverflow(_)) => overflowed = true,
+        _ => {}
+      }
+    }
+    assert!(parser.buffered_len() <= max_buffered_bytes);
+    if !overflowed {
+      assert_eq!(events, unlimited.receive(data));
+      assert_eq!(parser.buffered_len(), unlimited.buffered_len());
+    }
+  }
+}
+
 pub fn test_escape(data: Vec<u8>) {
   let escaped = Parser::escape_iac(data.clone());
   let unescaped = Parser::unescape_iac(escaped);
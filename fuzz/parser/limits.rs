@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use compat::{test_limited_app, LimitedApplication};
+
+fuzz_target!(|app: LimitedApplication| {
+  test_limited_app(&app);
+});
@@ -0,0 +1,202 @@
+//! ATCP (Achaea Telnet Client Protocol) message codec.
+//!
+//! An ATCP message is carried in an `IAC SB ATCP ... IAC SE` subnegotiation and consists of a
+//! dotted message name, optionally followed by a space or newline and a plain text body, e.g.
+//! `Char.Vitals\nH:100/100 M:80/80`.
+//!
+//! The client starts the session with `hello <client> <version>` followed by the modules it
+//! supports, one per line. The server may then challenge it with `Auth.Request CH <seed>`, which
+//! is answered with `auth <key> <client> <version>`.
+
+use alloc::{
+  format,
+  string::{String, ToString},
+};
+use core::convert::TryFrom;
+use core::fmt::{self, Write};
+use core::str;
+
+use bytes::Bytes;
+
+use crate::events::TelnetSubnegotiation;
+use crate::telnet::op_option::ATCP;
+
+/// An error parsing an ATCP message from a subnegotiation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+  /// The subnegotiation is not for the ATCP option.
+  WrongOption(u8),
+  /// The message is not valid UTF-8.
+  InvalidUtf8,
+  /// The message name is empty, or has no package part.
+  InvalidName,
+}
+
+impl fmt::Display for ParseError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ParseError::WrongOption(option) => {
+        write!(f, "subnegotiation for option {option} is not ATCP")
+      }
+      ParseError::InvalidUtf8 => write!(f, "ATCP message is not valid UTF-8"),
+      ParseError::InvalidName => write!(f, "ATCP message name is not of the form Package.Message"),
+    }
+  }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseError {}
+
+/// An ATCP message, e.g. `Room.Num 1234`.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct AtcpMessage {
+  /// The package name, e.g. `Char` or `Room`.
+  pub package: String,
+  /// The message name within the package, e.g. `Vitals` or `Num`.
+  pub message: String,
+  /// The text body, if any.
+  pub body: Option<String>,
+}
+
+impl AtcpMessage {
+  /// Create a message with an optional body.
+  pub fn new<P, M>(package: P, message: M, body: Option<String>) -> Self
+  where
+    P: Into<String>,
+    M: Into<String>,
+  {
+    Self {
+      package: package.into(),
+      message: message.into(),
+      body,
+    }
+  }
+
+  /// Parse a message from the text of an ATCP subnegotiation.
+  ///
+  /// # Errors
+  ///
+  /// Returns `ParseError::InvalidName` if the message name does not contain a package.
+  pub fn parse(text: &str) -> Result<Self, ParseError> {
+    let (name, body) = match text.split_once([' ', '\n']) {
+      Some((name, body)) => (name, Some(body)),
+      None => (text, None),
+    };
+    let (package, message) = name.split_once('.').ok_or(ParseError::InvalidName)?;
+    if package.is_empty() || message.is_empty() {
+      return Err(ParseError::InvalidName);
+    }
+    Ok(Self::new(
+      package,
+      message,
+      body.filter(|b| !b.is_empty()).map(String::from),
+    ))
+  }
+
+  /// The full message name, e.g. `Char.Vitals`.
+  #[must_use]
+  pub fn name(&self) -> String {
+    format!("{}.{}", self.package, self.message)
+  }
+
+  /// Encode the message as the content of an ATCP subnegotiation.
+  #[must_use]
+  pub fn to_bytes(&self) -> Bytes {
+    Bytes::from(self.to_string().into_bytes())
+  }
+
+  /// The seed of an `Auth.Request CH <seed>` challenge.
+  #[must_use]
+  pub fn auth_challenge(&self) -> Option<&str> {
+    if self.package != "Auth" || self.message != "Request" {
+      return None;
+    }
+    self.body.as_deref()?.strip_prefix("CH ").map(str::trim)
+  }
+}
+
+impl fmt::Display for AtcpMessage {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}.{}", self.package, self.message)?;
+    if let Some(body) = &self.body {
+      write!(f, " {body}")?;
+    }
+    Ok(())
+  }
+}
+
+impl TryFrom<&TelnetSubnegotiation> for AtcpMessage {
+  type Error = ParseError;
+
+  fn try_from(sub: &TelnetSubnegotiation) -> Result<Self, Self::Error> {
+    if sub.option != ATCP {
+      return Err(ParseError::WrongOption(sub.option));
+    }
+    let text = str::from_utf8(&sub.buffer).map_err(|_| ParseError::InvalidUtf8)?;
+    Self::parse(text)
+  }
+}
+
+impl From<AtcpMessage> for TelnetSubnegotiation {
+  fn from(message: AtcpMessage) -> Self {
+    TelnetSubnegotiation::new(ATCP, message.to_bytes())
+  }
+}
+
+/// Encode the `hello` handshake, enabling the given modules, e.g. `("room_brief", 1)`.
+#[must_use]
+pub fn hello(client: &str, version: &str, modules: &[(&str, u32)]) -> Bytes {
+  let mut text = format!("hello {client} {version}");
+  for (module, value) in modules {
+    let _ = write!(text, "\n{module} {value}");
+  }
+  Bytes::from(text.into_bytes())
+}
+
+/// Encode the `auth` answer to an `Auth.Request` challenge.
+#[must_use]
+pub fn auth(key: &str, client: &str, version: &str) -> Bytes {
+  Bytes::from(format!("auth {key} {client} {version}").into_bytes())
+}
+
+#[cfg(test)]
+mod test_atcp {
+  use super::*;
+
+  #[test]
+  fn test_parse() {
+    let msg = AtcpMessage::parse("Char.Vitals\nH:100/100 M:80/80").unwrap();
+    assert_eq!(msg.package, "Char");
+    assert_eq!(msg.message, "Vitals");
+    assert_eq!(msg.body.as_deref(), Some("H:100/100 M:80/80"));
+
+    let msg = AtcpMessage::parse("Room.Num 1234").unwrap();
+    assert_eq!(msg.name(), "Room.Num");
+    assert_eq!(msg.to_string(), "Room.Num 1234");
+    assert_eq!(
+      AtcpMessage::parse("Client.Compose"),
+      Ok(AtcpMessage::new("Client", "Compose", None))
+    );
+    assert_eq!(AtcpMessage::parse("hello"), Err(ParseError::InvalidName));
+  }
+
+  #[test]
+  fn test_handshake() {
+    assert_eq!(
+      &hello("Blightmud", "5.3", &[("auth", 1), ("char_vitals", 1)])[..],
+      b"hello Blightmud 5.3\nauth 1\nchar_vitals 1"
+    );
+    let challenge = AtcpMessage::parse("Auth.Request CH abcdef").unwrap();
+    assert_eq!(challenge.auth_challenge(), Some("abcdef"));
+    assert_eq!(
+      AtcpMessage::parse("Auth.Request ON")
+        .unwrap()
+        .auth_challenge(),
+      None
+    );
+    assert_eq!(
+      &auth("42", "Blightmud", "5.3")[..],
+      b"auth 42 Blightmud 5.3"
+    );
+  }
+}
@@ -0,0 +1,306 @@
+//! CHARSET (RFC 2066) codec.
+//!
+//! Once CHARSET is enabled either end may send `REQUEST` with a list of character sets it is
+//! willing to use, e.g. `IAC SB CHARSET REQUEST " UTF-8 ISO-8859-1" IAC SE` where the first byte
+//! after `REQUEST` is the separator. The other end answers with `ACCEPTED <charset>` or
+//! `REJECTED`.
+//!
+//! The requester may also offer to receive a translation table (`[TTABLE]`), which is answered
+//! with `TTABLE-IS` and acknowledged with `TTABLE-ACK`, `TTABLE-NAK` or `TTABLE-REJECTED`.
+
+use alloc::{borrow::ToOwned, string::String, vec::Vec};
+use core::convert::TryFrom;
+use core::fmt;
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::events::TelnetSubnegotiation;
+use crate::telnet::op_option::CHARSET;
+
+/// Offers a list of character sets.
+pub const REQUEST: u8 = 1;
+/// Accepts one of the offered character sets.
+pub const ACCEPTED: u8 = 2;
+/// Rejects all of the offered character sets.
+pub const REJECTED: u8 = 3;
+/// Sends a translation table.
+pub const TTABLE_IS: u8 = 4;
+/// Rejects a translation table.
+pub const TTABLE_REJECTED: u8 = 5;
+/// Acknowledges a translation table.
+pub const TTABLE_ACK: u8 = 6;
+/// Asks for a translation table to be sent again.
+pub const TTABLE_NAK: u8 = 7;
+
+/// Marks a `REQUEST` offering to receive a translation table.
+pub const TTABLE_MARKER: &[u8] = b"[TTABLE]";
+
+/// The separator used when encoding a `REQUEST`.
+pub const SEPARATOR: u8 = b' ';
+
+/// An error decoding a CHARSET subnegotiation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+  /// The subnegotiation is not for the CHARSET option.
+  WrongOption(u8),
+  /// The subnegotiation is empty.
+  Empty,
+  /// The subnegotiation has an unknown command.
+  UnknownCommand(u8),
+  /// The subnegotiation ends before a required field.
+  Truncated,
+}
+
+impl fmt::Display for DecodeError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      DecodeError::WrongOption(option) => {
+        write!(f, "subnegotiation for option {option} is not CHARSET")
+      }
+      DecodeError::Empty => write!(f, "CHARSET subnegotiation is empty"),
+      DecodeError::UnknownCommand(command) => write!(f, "unknown CHARSET command {command}"),
+      DecodeError::Truncated => write!(f, "CHARSET subnegotiation is truncated"),
+    }
+  }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DecodeError {}
+
+/// A CHARSET subnegotiation.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum CharsetMessage {
+  /// `REQUEST`, offering character sets in order of preference.
+  Request {
+    /// The translation table version the requester can receive, if any.
+    ttable: Option<u8>,
+    charsets: Vec<String>,
+  },
+  /// `ACCEPTED <charset>`.
+  Accepted(String),
+  /// `REJECTED`.
+  Rejected,
+  /// `TTABLE-IS`, with the raw translation table starting with its version.
+  TtableIs(Bytes),
+  /// `TTABLE-REJECTED`.
+  TtableRejected,
+  /// `TTABLE-ACK`.
+  TtableAck,
+  /// `TTABLE-NAK`.
+  TtableNak,
+}
+
+impl CharsetMessage {
+  /// Create a `REQUEST` without a translation table.
+  pub fn request<I, S>(charsets: I) -> Self
+  where
+    I: IntoIterator<Item = S>,
+    S: Into<String>,
+  {
+    CharsetMessage::Request {
+      ttable: None,
+      charsets: charsets.into_iter().map(Into::into).collect(),
+    }
+  }
+
+  /// Decode the content of a CHARSET subnegotiation.
+  ///
+  /// Character set names that are not valid UTF-8 are decoded lossily.
+  ///
+  /// # Errors
+  ///
+  /// Returns a `DecodeError` if the command is missing or unknown, or a `REQUEST` offering a
+  /// translation table has no version.
+  pub fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+    let (command, rest) = data.split_first().ok_or(DecodeError::Empty)?;
+    match *command {
+      REQUEST => {
+        let (ttable, list) = match rest.strip_prefix(TTABLE_MARKER) {
+          Some(rest) => {
+            let (version, list) = rest.split_first().ok_or(DecodeError::Truncated)?;
+            (Some(*version), list)
+          }
+          None => (None, rest),
+        };
+        let charsets = match list.split_first() {
+          Some((sep, names)) => names
+            .split(|b| b == sep)
+            .filter(|name| !name.is_empty())
+            .map(|name| String::from_utf8_lossy(name).into_owned())
+            .collect(),
+          None => Vec::new(),
+        };
+        Ok(CharsetMessage::Request { ttable, charsets })
+      }
+      ACCEPTED => Ok(CharsetMessage::Accepted(
+        String::from_utf8_lossy(rest).into_owned(),
+      )),
+      REJECTED => Ok(CharsetMessage::Rejected),
+      TTABLE_IS => Ok(CharsetMessage::TtableIs(Bytes::copy_from_slice(rest))),
+      TTABLE_REJECTED => Ok(CharsetMessage::TtableRejected),
+      TTABLE_ACK => Ok(CharsetMessage::TtableAck),
+      TTABLE_NAK => Ok(CharsetMessage::TtableNak),
+      command => Err(DecodeError::UnknownCommand(command)),
+    }
+  }
+
+  /// Encode the message as the content of a CHARSET subnegotiation.
+  #[must_use]
+  pub fn encode(&self) -> Bytes {
+    let mut buf = BytesMut::new();
+    match self {
+      CharsetMessage::Request { ttable, charsets } => {
+        buf.put_u8(REQUEST);
+        if let Some(version) = ttable {
+          buf.put(TTABLE_MARKER);
+          buf.put_u8(*version);
+        }
+        for charset in charsets {
+          buf.put_u8(SEPARATOR);
+          buf.put(charset.as_bytes());
+        }
+      }
+      CharsetMessage::Accepted(charset) => {
+        buf.put_u8(ACCEPTED);
+        buf.put(charset.as_bytes());
+      }
+      CharsetMessage::Rejected => buf.put_u8(REJECTED),
+      CharsetMessage::TtableIs(table) => {
+        buf.put_u8(TTABLE_IS);
+        buf.put(&table[..]);
+      }
+      CharsetMessage::TtableRejected => buf.put_u8(TTABLE_REJECTED),
+      CharsetMessage::TtableAck => buf.put_u8(TTABLE_ACK),
+      CharsetMessage::TtableNak => buf.put_u8(TTABLE_NAK),
+    }
+    buf.freeze()
+  }
+}
+
+impl TryFrom<&TelnetSubnegotiation> for CharsetMessage {
+  type Error = DecodeError;
+
+  fn try_from(sub: &TelnetSubnegotiation) -> Result<Self, Self::Error> {
+    if sub.option != CHARSET {
+      return Err(DecodeError::WrongOption(sub.option));
+    }
+    Self::decode(&sub.buffer)
+  }
+}
+
+impl From<CharsetMessage> for TelnetSubnegotiation {
+  fn from(message: CharsetMessage) -> Self {
+    TelnetSubnegotiation::new(CHARSET, message.encode())
+  }
+}
+
+/// Answers `REQUEST`s with the first offered character set found in a preference list.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CharsetResponder {
+  preferences: Vec<String>,
+}
+
+impl CharsetResponder {
+  /// Create a responder accepting the given character sets, most preferred first.
+  pub fn new<I, S>(preferences: I) -> Self
+  where
+    I: IntoIterator<Item = S>,
+    S: Into<String>,
+  {
+    Self {
+      preferences: preferences.into_iter().map(Into::into).collect(),
+    }
+  }
+
+  /// The acceptable character sets, most preferred first.
+  #[must_use]
+  pub fn preferences(&self) -> &[String] {
+    &self.preferences
+  }
+
+  /// Pick the most preferred of the `offered` character sets, compared case-insensitively.
+  ///
+  /// The name is returned as it was offered.
+  #[must_use]
+  pub fn choose<'a>(&self, offered: &'a [String]) -> Option<&'a str> {
+    self.preferences.iter().find_map(|preferred| {
+      offered
+        .iter()
+        .find(|charset| charset.eq_ignore_ascii_case(preferred))
+        .map(String::as_str)
+    })
+  }
+
+  /// Answer a `REQUEST` for the `offered` character sets with `ACCEPTED` or `REJECTED`.
+  ///
+  /// Translation tables are never asked for.
+  #[must_use]
+  pub fn respond(&self, offered: &[String]) -> CharsetMessage {
+    match self.choose(offered) {
+      Some(charset) => CharsetMessage::Accepted(charset.to_owned()),
+      None => CharsetMessage::Rejected,
+    }
+  }
+}
+
+#[cfg(test)]
+mod test_charset {
+  use super::*;
+  use alloc::vec;
+
+  #[test]
+  fn test_request() {
+    let request = CharsetMessage::request(vec!["UTF-8", "ISO-8859-1"]);
+    assert_eq!(&request.encode()[..], b"\x01 UTF-8 ISO-8859-1");
+    assert_eq!(CharsetMessage::decode(&request.encode()), Ok(request));
+
+    assert_eq!(
+      CharsetMessage::decode(b"\x01[TTABLE]\x01;UTF-8;;US-ASCII"),
+      Ok(CharsetMessage::Request {
+        ttable: Some(1),
+        charsets: vec![String::from("UTF-8"), String::from("US-ASCII")],
+      })
+    );
+    assert_eq!(
+      CharsetMessage::decode(b"\x01[TTABLE]"),
+      Err(DecodeError::Truncated)
+    );
+    assert_eq!(
+      CharsetMessage::decode(&[REQUEST]),
+      Ok(CharsetMessage::request(Vec::<String>::new()))
+    );
+  }
+
+  #[test]
+  fn test_replies() {
+    for message in [
+      CharsetMessage::Accepted(String::from("UTF-8")),
+      CharsetMessage::Rejected,
+      CharsetMessage::TtableIs(Bytes::from_static(b"\x01abc")),
+      CharsetMessage::TtableRejected,
+      CharsetMessage::TtableAck,
+      CharsetMessage::TtableNak,
+    ] {
+      assert_eq!(CharsetMessage::decode(&message.encode()), Ok(message));
+    }
+    assert_eq!(CharsetMessage::decode(&[]), Err(DecodeError::Empty));
+    assert_eq!(
+      CharsetMessage::decode(&[8]),
+      Err(DecodeError::UnknownCommand(8))
+    );
+  }
+
+  #[test]
+  fn test_responder() {
+    let responder = CharsetResponder::new(vec!["UTF-8", "ISO-8859-1"]);
+    let offered = vec![String::from("iso-8859-1"), String::from("utf-8")];
+    assert_eq!(
+      responder.respond(&offered),
+      CharsetMessage::Accepted(String::from("utf-8"))
+    );
+    assert_eq!(
+      responder.respond(&[String::from("CP437")]),
+      CharsetMessage::Rejected
+    );
+  }
+}
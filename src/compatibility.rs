@@ -1,3 +1,113 @@
+/// RFC 1143 "Q method" state for one side (local or remote) of an option.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum QState {
+  /// The option is disabled.
+  No,
+  /// The option is enabled.
+  Yes,
+  /// The option is enabled, and we have asked for it to be disabled.
+  WantNo,
+  /// The option is disabled, and we have asked for it to be enabled.
+  WantYes,
+}
+
+/// RFC 1143 "Q method" queue bit for one side (local or remote) of an option.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum QQueue {
+  /// No request is queued.
+  Empty,
+  /// A request for the opposite of the current `WantNo`/`WantYes` is queued.
+  Opposite,
+}
+
+/// The negotiation state of one side (local or remote) of an option.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SideState {
+  pub state: QState,
+  pub queue: QQueue,
+}
+
+impl SideState {
+  #[must_use]
+  pub fn new(state: QState, queue: QQueue) -> Self {
+    Self { state, queue }
+  }
+
+  /// Whether the option is currently enabled on this side.
+  ///
+  /// An option that we have asked to disable (`WantNo`) is still enabled until the other side
+  /// confirms.
+  #[must_use]
+  pub fn is_enabled(self) -> bool {
+    matches!(self.state, QState::Yes | QState::WantNo)
+  }
+
+  fn from_bits(enabled: bool, pending: bool, opposite: bool) -> Self {
+    let state = match (enabled, pending) {
+      (false, false) => QState::No,
+      (true, false) => QState::Yes,
+      (true, true) => QState::WantNo,
+      (false, true) => QState::WantYes,
+    };
+    let queue = if opposite {
+      QQueue::Opposite
+    } else {
+      QQueue::Empty
+    };
+    Self { state, queue }
+  }
+
+  fn into_bits(self, enabled_bit: u8, pending_bit: u8, opposite_bit: u8) -> u8 {
+    let mut res = 0;
+    if self.is_enabled() {
+      res |= enabled_bit;
+    }
+    if matches!(self.state, QState::WantNo | QState::WantYes) {
+      res |= pending_bit;
+    }
+    if self.queue == QQueue::Opposite {
+      res |= opposite_bit;
+    }
+    res
+  }
+}
+
+impl Default for SideState {
+  fn default() -> Self {
+    Self::new(QState::No, QQueue::Empty)
+  }
+}
+
+/// The negotiation state of both sides of an option.
+///
+/// The local side is negotiated with `WILL`/`WONT` from us and `DO`/`DONT` from the other side,
+/// the remote side with `DO`/`DONT` from us and `WILL`/`WONT` from the other side. The two are
+/// independent state machines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct OptionState {
+  pub local: SideState,
+  pub remote: SideState,
+}
+
+impl OptionState {
+  #[must_use]
+  pub fn new(local: SideState, remote: SideState) -> Self {
+    Self { local, remote }
+  }
+
+  /// Whether the option is enabled locally (us -> them).
+  #[must_use]
+  pub fn local_enabled(self) -> bool {
+    self.local.is_enabled()
+  }
+
+  /// Whether the option is enabled remotely (them -> us).
+  #[must_use]
+  pub fn remote_enabled(self) -> bool {
+    self.remote.is_enabled()
+  }
+}
+
 /// An expansion of a bitmask contained in `CompatibilityTable`.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct CompatibilityEntry {
@@ -74,6 +184,17 @@ impl CompatibilityTable {
   pub const LOCAL_STATE: u8 = 1 << 2;
   /// Option is currently enabled remotely.
   pub const REMOTE_STATE: u8 = 1 << 3;
+  /// A local negotiation (WILL/WONT) is awaiting a reply.
+  pub const LOCAL_PENDING: u8 = 1 << 4;
+  /// A local request for the opposite state is queued behind the pending negotiation.
+  pub const LOCAL_OPPOSITE: u8 = 1 << 5;
+  /// A remote negotiation (DO/DONT) is awaiting a reply.
+  pub const REMOTE_PENDING: u8 = 1 << 6;
+  /// A remote request for the opposite state is queued behind the pending negotiation.
+  pub const REMOTE_OPPOSITE: u8 = 1 << 7;
+
+  const LOCAL_BITS: u8 = Self::LOCAL_STATE | Self::LOCAL_PENDING | Self::LOCAL_OPPOSITE;
+  const REMOTE_BITS: u8 = Self::REMOTE_STATE | Self::REMOTE_PENDING | Self::REMOTE_OPPOSITE;
 
   #[must_use]
   pub fn new() -> Self {
@@ -100,24 +221,17 @@ impl CompatibilityTable {
 
   /// Enable local support for an option.
   pub fn support_local(&mut self, option: u8) {
-    let mut opt = CompatibilityEntry::from(self.options[option as usize]);
-    opt.local = true;
-    self.set_option(option, opt);
+    self.options[option as usize] |= Self::ENABLED_LOCAL;
   }
 
   /// Enable remote support for an option.
   pub fn support_remote(&mut self, option: u8) {
-    let mut opt = CompatibilityEntry::from(self.options[option as usize]);
-    opt.remote = true;
-    self.set_option(option, opt);
+    self.options[option as usize] |= Self::ENABLED_REMOTE;
   }
 
   /// Enable both remote and local support for an option.
   pub fn support(&mut self, option: u8) {
-    let mut opt = CompatibilityEntry::from(self.options[option as usize]);
-    opt.local = true;
-    opt.remote = true;
-    self.set_option(option, opt);
+    self.options[option as usize] |= Self::ENABLED_LOCAL | Self::ENABLED_REMOTE;
   }
 
   /// Retrieve a `CompatbilityEntry` generated from the current state of the option value.
@@ -127,17 +241,62 @@ impl CompatibilityTable {
   }
 
   /// Set an option value by getting the bitmask from a `CompatibilityEntry`.
+  ///
+  /// This discards any in-progress negotiation for the option.
   pub fn set_option(&mut self, option: u8, entry: CompatibilityEntry) {
     self.options[option as usize] = entry.into_u8();
   }
 
+  /// Retrieve the RFC 1143 negotiation state of the local side of an option.
+  #[must_use]
+  pub fn local_side(&self, option: u8) -> SideState {
+    let value = self.options[option as usize];
+    SideState::from_bits(
+      value & Self::LOCAL_STATE != 0,
+      value & Self::LOCAL_PENDING != 0,
+      value & Self::LOCAL_OPPOSITE != 0,
+    )
+  }
+
+  /// Set the RFC 1143 negotiation state of the local side of an option.
+  pub fn set_local_side(&mut self, option: u8, side: SideState) {
+    let value = &mut self.options[option as usize];
+    *value = (*value & !Self::LOCAL_BITS)
+      | side.into_bits(Self::LOCAL_STATE, Self::LOCAL_PENDING, Self::LOCAL_OPPOSITE);
+  }
+
+  /// Retrieve the RFC 1143 negotiation state of the remote side of an option.
+  #[must_use]
+  pub fn remote_side(&self, option: u8) -> SideState {
+    let value = self.options[option as usize];
+    SideState::from_bits(
+      value & Self::REMOTE_STATE != 0,
+      value & Self::REMOTE_PENDING != 0,
+      value & Self::REMOTE_OPPOSITE != 0,
+    )
+  }
+
+  /// Set the RFC 1143 negotiation state of the remote side of an option.
+  pub fn set_remote_side(&mut self, option: u8, side: SideState) {
+    let value = &mut self.options[option as usize];
+    *value = (*value & !Self::REMOTE_BITS)
+      | side.into_bits(
+        Self::REMOTE_STATE,
+        Self::REMOTE_PENDING,
+        Self::REMOTE_OPPOSITE,
+      );
+  }
+
+  /// Retrieve the negotiation state of both sides of an option.
+  #[must_use]
+  pub fn option_state(&self, option: u8) -> OptionState {
+    OptionState::new(self.local_side(option), self.remote_side(option))
+  }
+
   /// Reset all negotiated states
   pub fn reset_states(&mut self) {
     for opt in &mut self.options {
-      let mut entry = CompatibilityEntry::from(*opt);
-      entry.local_state = false;
-      entry.remote_state = false;
-      *opt = entry.into_u8();
+      *opt &= Self::ENABLED_LOCAL | Self::ENABLED_REMOTE;
     }
   }
 }
@@ -163,4 +322,24 @@ mod test_compat {
     assert!(!entry.remote_state);
     assert!(!entry.local_state);
   }
+
+  #[test]
+  fn test_side_roundtrip() {
+    let mut table = CompatibilityTable::default();
+    table.support(GMCP);
+    for state in [QState::No, QState::Yes, QState::WantNo, QState::WantYes] {
+      for queue in [QQueue::Empty, QQueue::Opposite] {
+        let side = SideState::new(state, queue);
+        table.set_local_side(GMCP, side);
+        table.set_remote_side(GMCP, SideState::default());
+        assert_eq!(table.local_side(GMCP), side);
+        assert_eq!(table.remote_side(GMCP), SideState::default());
+        assert_eq!(table.get_option(GMCP).local_state, side.is_enabled());
+        assert!(table.get_option(GMCP).local);
+        assert!(table.get_option(GMCP).remote);
+      }
+    }
+    table.reset_states();
+    assert_eq!(table.local_side(GMCP), SideState::default());
+  }
 }
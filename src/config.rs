@@ -0,0 +1,99 @@
+//! The limits of a `Parser` on buffered data, its handling of malformed input, and the
+//! subnegotiations it accepts.
+//!
+//! A subnegotiation is only complete once `IAC SE` is received, so a buggy or malicious remote
+//! end sending `IAC SB` and never ending it would make the parser buffer everything after it.
+//! `ParserConfig` bounds the length of a subnegotiation, and `OverflowPolicy` decides what the
+//! parser does with one exceeding it. Once `report_protocol_errors` is set, every overflow is
+//! reported with a `TelnetEvents::ProtocolError(TelnetError::BufferOverflow)` event.
+
+use crate::telnet::op_option::{MCCP2, MCCP3, MSSP, NAWS};
+
+/// What the parser does with a subnegotiation exceeding the limits of its `ParserConfig`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum OverflowPolicy {
+  /// Keep the content up to the limit, skip the rest, and receive the truncated subnegotiation
+  /// once `IAC SE` arrives.
+  TruncateAndError,
+  /// Drop the subnegotiation, skipping everything up to `IAC SE`.
+  DiscardUntilSe,
+  /// Drop the subnegotiation, skipping everything up to the next command, e.g. `IAC SE` or
+  /// `IAC GA`, which is processed as if the subnegotiation had ended. This recovers from a
+  /// missing `IAC SE`.
+  Reset,
+}
+
+/// Limits on the data buffered by a `Parser`, set with `Parser::set_config`.
+///
+/// The default config has no limits, and truncates subnegotiations once limits are set.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct ParserConfig {
+  /// The longest subnegotiation content received, with IAC bytes still escaped.
+  pub max_subnegotiation_len: usize,
+  /// The most subnegotiation content kept in the parser between calls to `receive`. Content
+  /// received in a single call is not copied, so only `max_subnegotiation_len` applies to it.
+  pub max_buffered_bytes: usize,
+  /// What to do with a subnegotiation exceeding either limit.
+  pub overflow_policy: OverflowPolicy,
+  /// Whether malformed input is reported with `TelnetEvents::ProtocolError` events, instead of
+  /// being silently ignored.
+  pub report_protocol_errors: bool,
+}
+
+impl Default for ParserConfig {
+  fn default() -> Self {
+    Self {
+      max_subnegotiation_len: usize::MAX,
+      max_buffered_bytes: usize::MAX,
+      overflow_policy: OverflowPolicy::TruncateAndError,
+      report_protocol_errors: false,
+    }
+  }
+}
+
+impl ParserConfig {
+  /// Create a config limiting subnegotiations, and the content buffered for them, to `max_len`
+  /// bytes.
+  #[must_use]
+  pub fn with_limit(max_len: usize, overflow_policy: OverflowPolicy) -> Self {
+    Self {
+      max_subnegotiation_len: max_len,
+      max_buffered_bytes: max_len,
+      overflow_policy,
+      ..Self::default()
+    }
+  }
+}
+
+/// Which side of an option must be enabled for a received subnegotiation for it to be accepted,
+/// set with `Parser::set_subnegotiation_policy`.
+///
+/// Subnegotiations that are not accepted are dropped.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum SubnegotiationPolicy {
+  /// The option is enabled locally, i.e. we sent `WILL` and the remote end agreed.
+  Local,
+  /// The option is enabled remotely, i.e. the remote end sent `WILL` and we agreed.
+  Remote,
+  /// The option is enabled on either side.
+  Either,
+  /// Accept subnegotiations for the option even while it is disabled.
+  Always,
+}
+
+impl SubnegotiationPolicy {
+  /// The policy for an option following its specification.
+  ///
+  /// Options whose subnegotiations are only sent by the end that enabled them, such as NAWS,
+  /// MSSP and the start of an MCCP2 stream, are accepted if enabled remotely, and the start of
+  /// an MCCP3 stream sent in reply to our `WILL` is accepted if enabled locally. Subnegotiations
+  /// for other options may be sent by either end.
+  #[must_use]
+  pub fn default_for(option: u8) -> Self {
+    match option {
+      NAWS | MSSP | MCCP2 => SubnegotiationPolicy::Remote,
+      MCCP3 => SubnegotiationPolicy::Local,
+      _ => SubnegotiationPolicy::Either,
+    }
+  }
+}
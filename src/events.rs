@@ -1,8 +1,11 @@
-use alloc::vec::Vec;
+use alloc::{collections::VecDeque, string::ToString, vec::Vec};
+use core::fmt;
 
 use bytes::{BufMut, Bytes, BytesMut};
 
+use crate::msp::MspTrigger;
 use crate::telnet::op_command::{IAC, SB, SE};
+use crate::telnet::{TelnetCommand, TelnetOption};
 use crate::Parser;
 
 /// A struct representing a 2 byte IAC sequence.
@@ -18,6 +21,12 @@ impl TelnetIAC {
     Self { command }
   }
 
+  /// The command of the sequence, e.g. `TelnetCommand::Ayt` for `IAC AYT`.
+  #[must_use]
+  pub fn kind(self) -> TelnetCommand {
+    TelnetCommand::from(self.command)
+  }
+
   /// Consume the sequence struct and return the bytes.
   #[must_use]
   pub fn to_bytes(self) -> Bytes {
@@ -31,6 +40,18 @@ impl TelnetIAC {
   }
 }
 
+impl fmt::Display for TelnetIAC {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.kind())
+  }
+}
+
+impl From<TelnetCommand> for TelnetIAC {
+  fn from(command: TelnetCommand) -> Self {
+    Self::new(command.into())
+  }
+}
+
 /// A struct representing a 3 byte IAC sequence.
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
@@ -45,6 +66,18 @@ impl TelnetNegotiation {
     Self { command, option }
   }
 
+  /// The command of the sequence, e.g. `TelnetCommand::Will`.
+  #[must_use]
+  pub fn kind(self) -> TelnetCommand {
+    TelnetCommand::from(self.command)
+  }
+
+  /// The option being negotiated.
+  #[must_use]
+  pub fn option_kind(self) -> TelnetOption {
+    TelnetOption::from(self.option)
+  }
+
   /// Consume the sequence struct and return the bytes.
   #[must_use]
   pub fn to_bytes(self) -> Bytes {
@@ -58,6 +91,13 @@ impl TelnetNegotiation {
   }
 }
 
+impl fmt::Display for TelnetNegotiation {
+  /// Format the sequence as its command and option names, e.g. `WILL GMCP`.
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{} {}", self.kind(), self.option_kind())
+  }
+}
+
 /// A struct representing an arbitrary length IAC subnegotiation sequence.
 #[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub struct TelnetSubnegotiation {
@@ -82,6 +122,12 @@ impl TelnetSubnegotiation {
     Self { option, buffer }
   }
 
+  /// The option of the subnegotiation.
+  #[must_use]
+  pub fn option_kind(&self) -> TelnetOption {
+    TelnetOption::from(self.option)
+  }
+
   #[must_use]
   pub fn to_bytes(self) -> Bytes {
     let head = [IAC, SB, self.option];
@@ -101,6 +147,13 @@ impl TelnetSubnegotiation {
   }
 }
 
+impl fmt::Display for TelnetSubnegotiation {
+  /// Format the sequence as its option name, e.g. `SB GMCP`.
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "SB {}", self.option_kind())
+  }
+}
+
 /// An enum representing various telnet events.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum TelnetEvents {
@@ -116,8 +169,66 @@ pub enum TelnetEvents {
   DataSend(Bytes),
   /// MCCP2/3 compatibility. MUST DECOMPRESS THIS DATA BEFORE PARSING
   DecompressImmediate(Bytes),
+  /// The MCCP2/3 compressed stream being decompressed by the parser is corrupt.
+  DecompressError,
+  /// An MSP sound or music trigger stripped from received data while MSP is enabled.
+  Msp(MspTrigger),
+  /// Malformed or unexpected input. Only reported if enabled in the `ParserConfig`.
+  ProtocolError(TelnetError),
+}
+
+/// Malformed or unexpected input received from the remote end, reported with
+/// `TelnetEvents::ProtocolError`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum TelnetError {
+  /// `IAC SE` outside of a subnegotiation, which is ignored.
+  UnexpectedSe,
+  /// `IAC` followed by a command other than `IAC` or `SE` in a subnegotiation for the option,
+  /// which is not valid there. Both bytes are kept as content.
+  InvalidSubnegotiationCommand(u8),
+  /// A subnegotiation for the option cut short by `IAC SB` before its `IAC SE`, which is
+  /// dropped. The option is `IAC` if the subnegotiation was cut short by `IAC` before its option.
+  TruncatedSubnegotiation(u8),
+  /// A subnegotiation for an option that is not enabled, which is dropped.
+  DisabledOption(u8),
+  /// `IAC` followed by a command the parser does not know, which is ignored.
+  UnknownCommand(u8),
+  /// A subnegotiation for the option exceeded the limits of the `ParserConfig`, and was handled
+  /// according to its `OverflowPolicy`.
+  BufferOverflow(u8),
 }
 
+impl fmt::Display for TelnetError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match *self {
+      TelnetError::UnexpectedSe => write!(f, "IAC SE received outside of a subnegotiation"),
+      TelnetError::InvalidSubnegotiationCommand(option) => {
+        let option = TelnetOption::from(option);
+        write!(f, "invalid command in subnegotiation for option {option}")
+      }
+      TelnetError::TruncatedSubnegotiation(option) => {
+        let option = TelnetOption::from(option);
+        write!(f, "subnegotiation for option {option} cut short")
+      }
+      TelnetError::DisabledOption(option) => {
+        let option = TelnetOption::from(option);
+        write!(f, "subnegotiation received for disabled option {option}")
+      }
+      TelnetError::UnknownCommand(command) => write!(f, "unknown command {command}"),
+      TelnetError::BufferOverflow(option) => {
+        let option = TelnetOption::from(option);
+        write!(
+          f,
+          "subnegotiation for option {option} exceeded the buffer limits"
+        )
+      }
+    }
+  }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for TelnetError {}
+
 impl From<TelnetIAC> for TelnetEvents {
   fn from(iac: TelnetIAC) -> Self {
     TelnetEvents::IAC(iac)
@@ -178,10 +289,136 @@ impl TelnetEvents {
       TelnetEvents::DataReceive(data)
       | TelnetEvents::DataSend(data)
       | TelnetEvents::DecompressImmediate(data) => data,
+      TelnetEvents::DecompressError | TelnetEvents::ProtocolError(_) => Bytes::new(),
+      TelnetEvents::Msp(trigger) => Bytes::from(trigger.to_string().into_bytes()),
+    }
+  }
+
+  /// Pass the event to the matching `TelnetHandler` method.
+  pub fn dispatch<H: TelnetHandler + ?Sized>(self, handler: &mut H) {
+    match self {
+      TelnetEvents::IAC(iac) => handler.on_iac(iac),
+      TelnetEvents::Negotiation(neg) => handler.on_negotiation(neg),
+      TelnetEvents::Subnegotiation(sub) => handler.on_subnegotiation(sub.option, &sub.buffer),
+      TelnetEvents::DataReceive(data) => handler.on_data(&data),
+      TelnetEvents::DataSend(data) => handler.on_send(data),
+      TelnetEvents::DecompressImmediate(data) => handler.on_decompress_immediate(&data),
+      TelnetEvents::DecompressError => handler.on_decompress_error(),
+      TelnetEvents::Msp(trigger) => handler.on_msp(trigger),
+      TelnetEvents::ProtocolError(error) => handler.on_protocol_error(error),
     }
   }
 }
 
+/// A receiver of the events parsed by `Parser::receive_with`, called as each event is found.
+///
+/// Received data and subnegotiations are borrowed from the parser's buffer, so no events are
+/// allocated unless the handler keeps them. `Vec<TelnetEvents>` implements the trait by
+/// collecting the events, as `Parser::receive` does.
+pub trait TelnetHandler {
+  /// Regular data received from the remote end.
+  fn on_data(&mut self, data: &[u8]);
+
+  /// An IAC command sequence.
+  fn on_iac(&mut self, iac: TelnetIAC);
+
+  /// An IAC negotiation sequence.
+  fn on_negotiation(&mut self, negotiation: TelnetNegotiation);
+
+  /// An IAC subnegotiation sequence, with IAC bytes in `data` still escaped.
+  fn on_subnegotiation(&mut self, option: u8, data: &[u8]);
+
+  /// Data to be sent to the remote end.
+  fn on_send(&mut self, data: Bytes);
+
+  /// MCCP2/3 compressed data following the start of a compressed stream, which must be
+  /// decompressed before being received. Ignored by default.
+  ///
+  /// This is only called for compressed streams not decompressed by the parser, see
+  /// `Parser::set_mccp`.
+  fn on_decompress_immediate(&mut self, _data: &[u8]) {}
+
+  /// The MCCP2/3 compressed stream being decompressed by the parser is corrupt. Ignored by
+  /// default.
+  fn on_decompress_error(&mut self) {}
+
+  /// An MSP trigger stripped from received data. Ignored by default.
+  fn on_msp(&mut self, _trigger: MspTrigger) {}
+
+  /// Malformed or unexpected input, see `TelnetEvents::ProtocolError`. Ignored by default.
+  fn on_protocol_error(&mut self, _error: TelnetError) {}
+}
+
+/// Implement `TelnetHandler` for a collection of events, with the method adding an event to it.
+macro_rules! collecting_handler {
+  ($collection:ty, $push:ident) => {
+    impl TelnetHandler for $collection {
+      fn on_data(&mut self, data: &[u8]) {
+        self.$push(TelnetEvents::DataReceive(Bytes::copy_from_slice(data)));
+      }
+
+      fn on_iac(&mut self, iac: TelnetIAC) {
+        self.$push(TelnetEvents::IAC(iac));
+      }
+
+      fn on_negotiation(&mut self, negotiation: TelnetNegotiation) {
+        self.$push(TelnetEvents::Negotiation(negotiation));
+      }
+
+      fn on_subnegotiation(&mut self, option: u8, data: &[u8]) {
+        self.$push(TelnetEvents::Subnegotiation(TelnetSubnegotiation::new(
+          option,
+          Bytes::copy_from_slice(data),
+        )));
+      }
+
+      fn on_send(&mut self, data: Bytes) {
+        self.$push(TelnetEvents::DataSend(data));
+      }
+
+      fn on_decompress_immediate(&mut self, data: &[u8]) {
+        self.$push(TelnetEvents::DecompressImmediate(Bytes::copy_from_slice(
+          data,
+        )));
+      }
+
+      fn on_decompress_error(&mut self) {
+        self.$push(TelnetEvents::DecompressError);
+      }
+
+      fn on_msp(&mut self, trigger: MspTrigger) {
+        self.$push(TelnetEvents::Msp(trigger));
+      }
+
+      fn on_protocol_error(&mut self, error: TelnetError) {
+        self.$push(TelnetEvents::ProtocolError(error));
+      }
+    }
+  };
+}
+
+collecting_handler!(Vec<TelnetEvents>, push);
+collecting_handler!(VecDeque<TelnetEvents>, push_back);
+
+/// An iterator over the events of the bytes given to `Parser::feed`, returned by `Parser::events`.
+pub struct ParserEvents<'a> {
+  parser: &'a mut Parser,
+}
+
+impl<'a> ParserEvents<'a> {
+  pub(crate) fn new(parser: &'a mut Parser) -> Self {
+    Self { parser }
+  }
+}
+
+impl Iterator for ParserEvents<'_> {
+  type Item = TelnetEvents;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    self.parser.next_event()
+  }
+}
+
 /*
 TODO(@cpu): remove/retool this stuff in breaking release.
 */
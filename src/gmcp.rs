@@ -0,0 +1,308 @@
+//! GMCP (Generic Mud Communication Protocol) message codec.
+//!
+//! A GMCP message is carried in an `IAC SB GMCP ... IAC SE` subnegotiation and consists of a
+//! dotted message name, optionally followed by a space and a JSON payload, e.g.
+//! `Core.Supports.Set ["Char 1", "Room 1"]`.
+//!
+//! With the `serde_json` crate feature enabled payloads can be (de)serialized directly.
+
+use alloc::{
+  format,
+  string::{String, ToString},
+  vec::Vec,
+};
+use core::convert::TryFrom;
+use core::fmt::{self, Write};
+use core::str;
+
+use bytes::Bytes;
+
+use crate::events::TelnetSubnegotiation;
+use crate::telnet::op_option::GMCP;
+
+/// An error parsing a GMCP message from a subnegotiation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+  /// The subnegotiation is not for the GMCP option.
+  WrongOption(u8),
+  /// The message is not valid UTF-8.
+  InvalidUtf8,
+  /// The message name is empty, or has no package part.
+  InvalidName,
+}
+
+impl fmt::Display for ParseError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ParseError::WrongOption(option) => {
+        write!(f, "subnegotiation for option {option} is not GMCP")
+      }
+      ParseError::InvalidUtf8 => write!(f, "GMCP message is not valid UTF-8"),
+      ParseError::InvalidName => write!(f, "GMCP message name is not of the form Package.Message"),
+    }
+  }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseError {}
+
+/// A GMCP message, e.g. `Char.Vitals {"hp": 100}`.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct GmcpMessage {
+  /// The package name, e.g. `Char` or `Core.Supports`.
+  pub package: String,
+  /// The message name within the package, e.g. `Vitals` or `Set`.
+  pub message: String,
+  /// The raw JSON payload, if any.
+  pub payload: Option<String>,
+}
+
+impl GmcpMessage {
+  /// Create a message with an optional raw JSON payload.
+  pub fn new<P, M>(package: P, message: M, payload: Option<String>) -> Self
+  where
+    P: Into<String>,
+    M: Into<String>,
+  {
+    Self {
+      package: package.into(),
+      message: message.into(),
+      payload,
+    }
+  }
+
+  /// Parse a message from the text of a GMCP subnegotiation.
+  ///
+  /// # Errors
+  ///
+  /// Returns `ParseError::InvalidName` if the message name does not contain a package.
+  pub fn parse(text: &str) -> Result<Self, ParseError> {
+    let (name, payload) = match text.split_once([' ', '\t', '\r', '\n']) {
+      Some((name, payload)) => (name, Some(payload.trim())),
+      None => (text, None),
+    };
+    let (package, message) = name.rsplit_once('.').ok_or(ParseError::InvalidName)?;
+    if package.is_empty() || message.is_empty() {
+      return Err(ParseError::InvalidName);
+    }
+    Ok(Self::new(
+      package,
+      message,
+      payload.filter(|p| !p.is_empty()).map(String::from),
+    ))
+  }
+
+  /// The full message name, e.g. `Core.Supports.Set`.
+  #[must_use]
+  pub fn name(&self) -> String {
+    format!("{}.{}", self.package, self.message)
+  }
+
+  /// Encode the message as the content of a GMCP subnegotiation.
+  #[must_use]
+  pub fn to_bytes(&self) -> Bytes {
+    Bytes::from(self.to_string().into_bytes())
+  }
+
+  /// `Core.Hello {"client": ..., "version": ...}`, sent by a client after GMCP is enabled.
+  #[must_use]
+  pub fn core_hello(client: &str, version: &str) -> Self {
+    let payload = format!(
+      "{{\"client\":{},\"version\":{}}}",
+      json_string(client),
+      json_string(version)
+    );
+    Self::new("Core", "Hello", Some(payload))
+  }
+
+  /// `Core.Supports.Set [...]`, replacing the set of supported packages.
+  ///
+  /// Each package is given with its version, e.g. `("Char", 1)`.
+  #[must_use]
+  pub fn core_supports_set(packages: &[(&str, u32)]) -> Self {
+    Self::core_supports("Set", packages)
+  }
+
+  /// `Core.Supports.Add [...]`, adding to the set of supported packages.
+  #[must_use]
+  pub fn core_supports_add(packages: &[(&str, u32)]) -> Self {
+    Self::core_supports("Add", packages)
+  }
+
+  /// `Core.Supports.Remove [...]`, removing packages from the set of supported packages.
+  #[must_use]
+  pub fn core_supports_remove(packages: &[&str]) -> Self {
+    let list: Vec<String> = packages
+      .iter()
+      .map(|package| json_string(package))
+      .collect();
+    Self::new(
+      "Core.Supports",
+      "Remove",
+      Some(format!("[{}]", list.join(","))),
+    )
+  }
+
+  fn core_supports(message: &str, packages: &[(&str, u32)]) -> Self {
+    let list: Vec<String> = packages
+      .iter()
+      .map(|(package, version)| json_string(&format!("{package} {version}")))
+      .collect();
+    Self::new(
+      "Core.Supports",
+      message,
+      Some(format!("[{}]", list.join(","))),
+    )
+  }
+}
+
+#[cfg(feature = "serde_json")]
+impl GmcpMessage {
+  /// Create a message with a payload serialized to JSON.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if `payload` cannot be serialized.
+  pub fn with_json<P, M, T>(package: P, message: M, payload: &T) -> Result<Self, serde_json::Error>
+  where
+    P: Into<String>,
+    M: Into<String>,
+    T: serde::Serialize + ?Sized,
+  {
+    Ok(Self::new(
+      package,
+      message,
+      Some(serde_json::to_string(payload)?),
+    ))
+  }
+
+  /// Deserialize the JSON payload. Returns `None` if the message has no payload.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if the payload is not valid JSON for `T`.
+  pub fn json<T>(&self) -> Result<Option<T>, serde_json::Error>
+  where
+    T: serde::de::DeserializeOwned,
+  {
+    self
+      .payload
+      .as_deref()
+      .map(serde_json::from_str)
+      .transpose()
+  }
+}
+
+impl fmt::Display for GmcpMessage {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}.{}", self.package, self.message)?;
+    if let Some(payload) = &self.payload {
+      write!(f, " {payload}")?;
+    }
+    Ok(())
+  }
+}
+
+impl TryFrom<&TelnetSubnegotiation> for GmcpMessage {
+  type Error = ParseError;
+
+  fn try_from(sub: &TelnetSubnegotiation) -> Result<Self, Self::Error> {
+    if sub.option != GMCP {
+      return Err(ParseError::WrongOption(sub.option));
+    }
+    let text = str::from_utf8(&sub.buffer).map_err(|_| ParseError::InvalidUtf8)?;
+    Self::parse(text)
+  }
+}
+
+impl From<GmcpMessage> for TelnetSubnegotiation {
+  fn from(message: GmcpMessage) -> Self {
+    TelnetSubnegotiation::new(GMCP, message.to_bytes())
+  }
+}
+
+/// Quote and escape `value` as a JSON string.
+fn json_string(value: &str) -> String {
+  let mut res = String::with_capacity(value.len() + 2);
+  res.push('"');
+  for c in value.chars() {
+    match c {
+      '"' => res.push_str("\\\""),
+      '\\' => res.push_str("\\\\"),
+      '\n' => res.push_str("\\n"),
+      '\r' => res.push_str("\\r"),
+      '\t' => res.push_str("\\t"),
+      c if (c as u32) < 0x20 => {
+        let _ = write!(res, "\\u{:04x}", c as u32);
+      }
+      c => res.push(c),
+    }
+  }
+  res.push('"');
+  res
+}
+
+#[cfg(test)]
+mod test_gmcp {
+  use super::*;
+
+  #[test]
+  fn test_parse() {
+    let msg = GmcpMessage::parse("Core.Supports.Set [\"Char 1\"]").unwrap();
+    assert_eq!(msg.package, "Core.Supports");
+    assert_eq!(msg.message, "Set");
+    assert_eq!(msg.payload.as_deref(), Some("[\"Char 1\"]"));
+    assert_eq!(msg.name(), "Core.Supports.Set");
+
+    let msg = GmcpMessage::parse("Core.Ping").unwrap();
+    assert_eq!(msg, GmcpMessage::new("Core", "Ping", None));
+
+    assert_eq!(GmcpMessage::parse("Ping"), Err(ParseError::InvalidName));
+    assert_eq!(GmcpMessage::parse(".Ping"), Err(ParseError::InvalidName));
+  }
+
+  #[test]
+  fn test_roundtrip() {
+    let msg = GmcpMessage::new("Char", "Vitals", Some(String::from("{\"hp\":100}")));
+    let sub = TelnetSubnegotiation::from(msg.clone());
+    assert_eq!(&sub.buffer[..], b"Char.Vitals {\"hp\":100}");
+    assert_eq!(GmcpMessage::try_from(&sub), Ok(msg));
+  }
+
+  #[test]
+  fn test_helpers() {
+    assert_eq!(
+      GmcpMessage::core_hello("Blight\"mud", "1.0").to_string(),
+      "Core.Hello {\"client\":\"Blight\\\"mud\",\"version\":\"1.0\"}"
+    );
+    assert_eq!(
+      GmcpMessage::core_supports_set(&[("Char", 1), ("Room", 2)]).to_string(),
+      "Core.Supports.Set [\"Char 1\",\"Room 2\"]"
+    );
+    assert_eq!(
+      GmcpMessage::core_supports_add(&[("Comm.Channel", 1)]).to_string(),
+      "Core.Supports.Add [\"Comm.Channel 1\"]"
+    );
+    assert_eq!(
+      GmcpMessage::core_supports_remove(&["Room"]).to_string(),
+      "Core.Supports.Remove [\"Room\"]"
+    );
+  }
+
+  #[cfg(feature = "serde_json")]
+  #[test]
+  fn test_json() {
+    let msg = GmcpMessage::parse("Char.Vitals {\"hp\": 100, \"sp\": 5}").unwrap();
+    let value: serde_json::Value = msg.json().unwrap().unwrap();
+    assert_eq!(value["hp"], 100);
+
+    let msg = GmcpMessage::with_json("Char", "Vitals", &value).unwrap();
+    assert_eq!(msg.to_string(), "Char.Vitals {\"hp\":100,\"sp\":5}");
+    assert_eq!(
+      GmcpMessage::new("Core", "Ping", None)
+        .json::<serde_json::Value>()
+        .unwrap(),
+      None
+    );
+  }
+}
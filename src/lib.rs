@@ -12,25 +12,42 @@ extern crate alloc;
 #[cfg(feature = "std")]
 extern crate std as alloc;
 
-use alloc::{format, vec, vec::Vec};
+use alloc::{borrow::ToOwned, collections::VecDeque, format, string::String, vec, vec::Vec};
 
-use bytes::{BufMut, Bytes, BytesMut};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 
 pub use bytes;
+pub mod atcp;
+pub mod charset;
 pub mod compatibility;
+pub mod config;
 pub mod events;
+pub mod gmcp;
+pub mod linemode;
+#[cfg(feature = "mccp")]
+mod mccp;
+pub mod msdp;
+pub mod msp;
+pub mod mssp;
+pub mod mxp;
+pub mod naws;
+pub mod new_environ;
+pub mod oob;
+mod qmethod;
 pub mod telnet;
+pub mod ttype;
+pub mod zmp;
 
-use compatibility::{CompatibilityEntry, CompatibilityTable};
-use events::{TelnetEvents, TelnetNegotiation, TelnetSubnegotiation};
-use telnet::op_command::{DO, DONT, EOR, GA, IAC, NOP, SB, SE, WILL, WONT};
-
-enum EventType {
-  None(Bytes),
-  Iac(Bytes),
-  SubNegotiation(Bytes, Option<Bytes>),
-  Neg(Bytes),
-}
+use compatibility::{CompatibilityEntry, CompatibilityTable, OptionState};
+use config::{OverflowPolicy, ParserConfig, SubnegotiationPolicy};
+use events::{
+  ParserEvents, TelnetError, TelnetEvents, TelnetHandler, TelnetIAC, TelnetNegotiation,
+  TelnetSubnegotiation,
+};
+use qmethod::Reply;
+use telnet::op_command::{
+  ABORT, AO, AYT, BRK, DM, DO, DONT, EC, EL, EOF, EOR, GA, IAC, IP, NOP, SB, SE, SUSP, WILL, WONT,
+};
 
 #[deprecated(
   since = "0.2.1",
@@ -44,10 +61,88 @@ macro_rules! vbytes {
   };
 }
 
+/// Where the parser is in the received byte stream, kept between calls to `receive`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum State {
+  Normal,
+  Iac,
+  Neg {
+    command: u8,
+  },
+  Sub,
+  SubOpt {
+    opt: u8,
+  },
+  SubIac {
+    opt: u8,
+  },
+  /// Skipping the rest of a subnegotiation exceeding the configured limits.
+  Overflow {
+    opt: u8,
+    truncated: bool,
+  },
+  OverflowIac {
+    opt: u8,
+    truncated: bool,
+  },
+}
+
+/// Find the next IAC byte in `data`.
+#[cfg(feature = "memchr")]
+fn find_iac(data: &[u8]) -> Option<usize> {
+  memchr::memchr(IAC, data)
+}
+
+/// Find the next IAC byte in `data`, checking a word at a time.
+#[cfg(not(feature = "memchr"))]
+fn find_iac(data: &[u8]) -> Option<usize> {
+  const WORD: usize = core::mem::size_of::<usize>();
+  const LO: usize = usize::MAX / 0xFF;
+  const HI: usize = LO << 7;
+
+  let mut offset = 0;
+  for chunk in data.chunks_exact(WORD) {
+    let mut bytes = [0; WORD];
+    bytes.copy_from_slice(chunk);
+    // IAC bytes are all ones, so they are the zero bytes of the inverted word.
+    let inverted = !usize::from_ne_bytes(bytes);
+    if inverted.wrapping_sub(LO) & !inverted & HI != 0 {
+      break;
+    }
+    offset += WORD;
+  }
+  data[offset..]
+    .iter()
+    .position(|&b| b == IAC)
+    .map(|position| offset + position)
+}
+
 /// A telnet parser that handles the main parts of the protocol.
 pub struct Parser {
   pub options: CompatibilityTable,
+  config: ParserConfig,
+  /// Subnegotiation policies set with `set_subnegotiation_policy`, by option.
+  subnegotiation_policies: [Option<SubnegotiationPolicy>; 256],
+  state: State,
+  /// Whether received data is discarded until the `IAC DM` of a Synch, see `begin_synch`.
+  synch: bool,
+  /// The content of a subnegotiation received over several calls to `receive`.
   buffer: BytesMut,
+  /// Data given to `feed` that has not been processed yet.
+  input: BytesMut,
+  /// Events of the last sequence processed by `next_event`, not returned yet.
+  queue: VecDeque<TelnetEvents>,
+  #[cfg(feature = "mccp")]
+  mccp: mccp::Mccp,
+  window_size: Option<naws::WindowSize>,
+  ttype_responder: Option<ttype::TtypeResponder>,
+  ttype_probe: Option<ttype::TtypeProbe>,
+  charset_responder: Option<charset::CharsetResponder>,
+  charset: Option<String>,
+  environ: Option<new_environ::EnvironStore>,
+  linemode: linemode::LinemodeState,
+  zmp: Option<zmp::ZmpRegistry>,
+  msp: msp::MspFilter,
 }
 
 impl Default for Parser {
@@ -83,7 +178,24 @@ impl Parser {
   pub fn with_support_and_capacity(size: usize, table: CompatibilityTable) -> Self {
     Self {
       options: table,
+      config: ParserConfig::default(),
+      subnegotiation_policies: [None; 256],
+      state: State::Normal,
+      synch: false,
       buffer: BytesMut::with_capacity(size),
+      input: BytesMut::new(),
+      queue: VecDeque::new(),
+      #[cfg(feature = "mccp")]
+      mccp: mccp::Mccp::default(),
+      window_size: None,
+      ttype_responder: None,
+      ttype_probe: None,
+      charset_responder: None,
+      charset: None,
+      environ: None,
+      linemode: linemode::LinemodeState::default(),
+      zmp: None,
+      msp: msp::MspFilter::default(),
     }
   }
 
@@ -98,20 +210,336 @@ impl Parser {
   /// `Vec<TelnetEvents>` - Any events parsed from the internal buffer with the new bytes.
   ///
   pub fn receive(&mut self, data: &[u8]) -> Vec<TelnetEvents> {
-    self.buffer.put(data);
-    self.process()
+    let mut events = Vec::with_capacity(2);
+    self.receive_with(data, &mut events);
+    events
+  }
+
+  /// Receive bytes into the internal buffer, passing the parsed events to a handler.
+  ///
+  /// # Arguments
+  ///
+  /// * `data` - The bytes to be received. This should be sourced from the remote side of a connection.
+  ///
+  /// * `handler` - The `TelnetHandler` called for each event, in the order `receive` would return them.
+  ///
+  /// # Notes
+  ///
+  /// Unlike `receive`, no `Vec` of events is allocated, and received data and subnegotiations are
+  /// passed to the handler as slices of `data`, or of the internal buffer for subnegotiations
+  /// split over several receives.
+  pub fn receive_with(&mut self, data: &[u8], handler: &mut impl TelnetHandler) {
+    if self.is_pending() {
+      // Data given to `feed` comes first.
+      self.feed(data);
+      while let Some(event) = self.next_event() {
+        event.dispatch(handler);
+      }
+      return;
+    }
+    #[cfg(feature = "mccp")]
+    if self.mccp.broken {
+      return;
+    }
+    #[cfg(feature = "mccp")]
+    if let Some(decompressor) = self.mccp.decompressor.take() {
+      return self.receive_compressed(decompressor, data, handler);
+    }
+    self.process(data, handler, false);
+  }
+
+  /// Add received bytes to the internal buffer, without processing them.
+  ///
+  /// # Arguments
+  ///
+  /// * `data` - The bytes to be received. This should be sourced from the remote side of a connection.
+  ///
+  /// # Notes
+  ///
+  /// The bytes are processed as events are taken with `next_event` or `events`, so the parser state
+  /// may be changed between events (e.g. with `set_mccp`) before the bytes following them are
+  /// processed.
+  pub fn feed(&mut self, data: &[u8]) {
+    self.input.put(data);
+  }
+
+  /// Process the bytes given to `feed` up to the next event.
+  ///
+  /// # Returns
+  ///
+  /// `Option<TelnetEvents>` - The next event, or None if all bytes given to `feed` have been processed.
+  ///
+  /// # Notes
+  ///
+  /// Bytes are processed one telnet sequence at a time. A sequence may result in several events,
+  /// e.g. a reply to a negotiation followed by the `Negotiation` event, which are returned by the
+  /// following calls before any more bytes are processed. While received data is being
+  /// decompressed by the parser, all bytes given to `feed` are decompressed at once, but the
+  /// decompressed data is still processed one sequence at a time.
+  pub fn next_event(&mut self) -> Option<TelnetEvents> {
+    loop {
+      if let Some(event) = self.queue.pop_front() {
+        return Some(event);
+      }
+      #[cfg(feature = "mccp")]
+      if !self.mccp.inflated.is_empty() {
+        let mut inflated = core::mem::take(&mut self.mccp.inflated);
+        let mut queue = core::mem::take(&mut self.queue);
+        let processed = self.process(&inflated, &mut queue, true);
+        inflated.advance(processed);
+        self.mccp.inflated = inflated;
+        self.queue = queue;
+        continue;
+      }
+      #[cfg(feature = "mccp")]
+      if core::mem::take(&mut self.mccp.error_pending) {
+        return Some(TelnetEvents::DecompressError);
+      }
+      if self.input.is_empty() {
+        return None;
+      }
+      #[cfg(feature = "mccp")]
+      if self.mccp.broken {
+        self.input.clear();
+        return None;
+      }
+      #[cfg(feature = "mccp")]
+      if let Some(decompressor) = self.mccp.decompressor.take() {
+        let input = core::mem::take(&mut self.input);
+        self.inflate(decompressor, &input);
+        continue;
+      }
+      let mut input = core::mem::take(&mut self.input);
+      let mut queue = core::mem::take(&mut self.queue);
+      let processed = self.process(&input, &mut queue, true);
+      input.advance(processed);
+      self.input = input;
+      self.queue = queue;
+    }
+  }
+
+  /// Whether bytes given to `feed`, or events found in them, are still to be taken with
+  /// `next_event`.
+  fn is_pending(&self) -> bool {
+    #[cfg(feature = "mccp")]
+    if !self.mccp.inflated.is_empty() || self.mccp.error_pending {
+      return true;
+    }
+    !self.input.is_empty() || !self.queue.is_empty()
+  }
+
+  /// Iterate over the events of the bytes given to `feed`, see `next_event`.
+  pub fn events(&mut self) -> ParserEvents<'_> {
+    ParserEvents::new(self)
+  }
+
+  /// Set the limits on received subnegotiations, and what to do with those exceeding them.
+  ///
+  /// # Notes
+  ///
+  /// A subnegotiation already being received is checked against the new limits as more of it
+  /// arrives.
+  pub fn set_config(&mut self, config: ParserConfig) {
+    self.config = config;
+  }
+
+  /// Get the config set with `set_config`.
+  #[must_use]
+  pub fn config(&self) -> &ParserConfig {
+    &self.config
+  }
+
+  /// Get the number of bytes of an unfinished subnegotiation kept between calls to `receive`,
+  /// which is at most `ParserConfig::max_buffered_bytes`.
+  #[must_use]
+  pub fn buffered_len(&self) -> usize {
+    self.buffer.len()
+  }
+
+  /// Set which side of an option must be enabled for received subnegotiations for it to be
+  /// accepted, or `None` to restore the default of `SubnegotiationPolicy::default_for`.
+  pub fn set_subnegotiation_policy(&mut self, option: u8, policy: Option<SubnegotiationPolicy>) {
+    self.subnegotiation_policies[option as usize] = policy;
+  }
+
+  /// Get the policy deciding whether received subnegotiations for an option are accepted.
+  #[must_use]
+  pub fn subnegotiation_policy(&self, option: u8) -> SubnegotiationPolicy {
+    self.subnegotiation_policies[option as usize]
+      .unwrap_or_else(|| SubnegotiationPolicy::default_for(option))
+  }
+
+  /// Enable or disable transparent MCCP2/MCCP3 decompression of received data.
+  ///
+  /// When enabled, data following an accepted `IAC SB MCCP2 IAC SE` (or `MCCP3`) is decompressed
+  /// by the parser and processed as if it had been received uncompressed, instead of being
+  /// returned in a `TelnetEvents::DecompressImmediate` event. When the compressed stream ends
+  /// the parser falls back to processing plain data, and if the stream is corrupt a
+  /// `TelnetEvents::DecompressError` event is emitted and all data received afterwards is
+  /// discarded, until `reset_decompression` is called.
+  ///
+  /// Disabled by default.
+  #[cfg(feature = "mccp")]
+  pub fn set_mccp(&mut self, enabled: bool) {
+    self.mccp.enabled = enabled;
+  }
+
+  /// Get whether received data is currently being decompressed.
+  #[cfg(feature = "mccp")]
+  #[must_use]
+  pub fn is_decompressing(&self) -> bool {
+    self.mccp.decompressor.is_some()
+  }
+
+  /// Get whether received data is being discarded because the compressed stream was corrupt.
+  #[cfg(feature = "mccp")]
+  #[must_use]
+  pub fn is_decompression_broken(&self) -> bool {
+    self.mccp.broken
+  }
+
+  /// Process received data as plain data again after a corrupt compressed stream, e.g. once the
+  /// connection has been re-established. Any compressed stream being decompressed is dropped.
+  #[cfg(feature = "mccp")]
+  pub fn reset_decompression(&mut self) {
+    self.mccp.decompressor = None;
+    self.mccp.broken = false;
+  }
+
+  /// Get whether data sent through the parser is currently being compressed.
+  #[cfg(feature = "mccp")]
+  #[must_use]
+  pub fn is_compressing(&self) -> bool {
+    self.mccp.compressor.is_some()
+  }
+
+  /// Start compressing data sent to the remote end.
+  ///
+  /// # Arguments
+  ///
+  /// `option` - `MCCP2` when acting as a server that has locally enabled MCCP2, or `MCCP3` when
+  /// acting as a client that the remote end has enabled MCCP3 for.
+  ///
+  /// # Returns
+  ///
+  /// `Option<TelnetEvents::DataSend>` - The uncompressed `IAC SB <option> IAC SE` sequence that
+  /// starts the compressed stream, or None if the option is not enabled or data is already being
+  /// compressed.
+  ///
+  /// # Notes
+  ///
+  /// All data sent through the parser after this call (e.g. `send_text`, `negotiate`,
+  /// `subnegotiation`, and negotiation replies) is compressed, until `end_compression` is called
+  /// or the option is disabled.
+  #[cfg(feature = "mccp")]
+  pub fn start_compression(&mut self, option: u8) -> Option<TelnetEvents> {
+    let enabled = match option {
+      telnet::op_option::MCCP2 => self.local_enabled(option),
+      telnet::op_option::MCCP3 => self.remote_enabled(option),
+      _ => false,
+    };
+    if !enabled || self.mccp.compressor.is_some() {
+      return None;
+    }
+    let start = TelnetSubnegotiation::new(option, Bytes::new()).to_bytes();
+    self.mccp.compressor = Some(mccp::Compressor::new(option));
+    Some(TelnetEvents::build_send(start))
+  }
+
+  /// End the compressed stream started with `start_compression`.
+  ///
+  /// # Returns
+  ///
+  /// `Option<TelnetEvents::DataSend>` - The final compressed data to send, or None if data is not
+  /// being compressed.
+  #[cfg(feature = "mccp")]
+  pub fn end_compression(&mut self) -> Option<TelnetEvents> {
+    let compressor = self.mccp.compressor.take()?;
+    Some(TelnetEvents::build_send(compressor.finish()))
+  }
+
+  #[cfg(feature = "mccp")]
+  fn receive_compressed<H: TelnetHandler>(
+    &mut self,
+    mut decompressor: mccp::Decompressor,
+    data: &[u8],
+    handler: &mut H,
+  ) {
+    match decompressor.decompress(data) {
+      mccp::Inflated::Continue(plain) => {
+        self.mccp.decompressor = Some(decompressor);
+        self.process(&plain, handler, false);
+      }
+      mccp::Inflated::End(plain, rest) => {
+        self.process(&plain, handler, false);
+        self.receive_with(&rest, handler);
+      }
+      mccp::Inflated::Error(plain) => {
+        self.process(&plain, handler, false);
+        // The rest of the stream can't be decompressed, nor be told apart from plain data.
+        self.mccp.broken = true;
+        handler.on_decompress_error();
+      }
+    }
+  }
+
+  /// Decompress bytes given to `feed`, keeping the decompressed data for `next_event`.
+  #[cfg(feature = "mccp")]
+  fn inflate(&mut self, mut decompressor: mccp::Decompressor, data: &[u8]) {
+    match decompressor.decompress(data) {
+      mccp::Inflated::Continue(plain) => {
+        self.mccp.decompressor = Some(decompressor);
+        self.mccp.inflated.put(plain);
+      }
+      mccp::Inflated::End(plain, rest) => {
+        self.mccp.inflated.put(plain);
+        // The rest is uncompressed, and processed once the decompressed data has been.
+        self.input.put(rest);
+      }
+      mccp::Inflated::Error(plain) => {
+        self.mccp.inflated.put(plain);
+        self.mccp.broken = true;
+        self.mccp.error_pending = true;
+      }
+    }
+  }
+
+  /// Build a `DataSend` event, compressing the data if a compressed stream has been started.
+  fn send(&mut self, data: Bytes) -> TelnetEvents {
+    TelnetEvents::build_send(self.compress(data))
+  }
+
+  /// Compress data to be sent if a compressed stream has been started.
+  #[cfg_attr(not(feature = "mccp"), allow(clippy::unused_self))]
+  fn compress(&mut self, data: Bytes) -> Bytes {
+    #[cfg(feature = "mccp")]
+    if let Some(compressor) = &mut self.mccp.compressor {
+      return compressor.compress(&data);
+    }
+    data
   }
 
   /// Get whether the remote end supports and is using linemode.
   pub fn linemode_enabled(&mut self) -> bool {
-    matches!(
-      self.options.get_option(telnet::op_option::LINEMODE),
-      CompatibilityEntry {
-        remote: true,
-        remote_state: true,
-        ..
-      }
-    )
+    self.remote_enabled(telnet::op_option::LINEMODE)
+  }
+
+  /// Get the negotiation state of both the local and remote side of an option.
+  #[must_use]
+  pub fn option_state(&self, option: u8) -> OptionState {
+    self.options.option_state(option)
+  }
+
+  /// Get whether an option is enabled locally (us -> them).
+  #[must_use]
+  pub fn local_enabled(&self, option: u8) -> bool {
+    self.option_state(option).local_enabled()
+  }
+
+  /// Get whether an option is enabled remotely (them -> us).
+  #[must_use]
+  pub fn remote_enabled(&self, option: u8) -> bool {
+    self.option_state(option).remote_enabled()
   }
 
   /// Escape IAC bytes in data that is to be transmitted and treated as a non-IAC sequence.
@@ -184,7 +612,7 @@ impl Parser {
   ///
   /// These Send events contain a buffer that should be sent directly to the remote end, as it will have already been encoded properly.
   pub fn negotiate(&mut self, command: u8, option: u8) -> TelnetEvents {
-    TelnetEvents::build_send(TelnetNegotiation::new(command, option).to_bytes())
+    self.send(TelnetNegotiation::new(command, option).to_bytes())
   }
 
   /// Indicate to the other side that you are able and wanting to utilize an option.
@@ -200,19 +628,16 @@ impl Parser {
   /// # Notes
   ///
   /// This method will do nothing if the option is not "supported" locally via the `CompatibilityTable`.
+  ///
+  /// Negotiation follows the RFC 1143 "Q method": if a negotiation for the option is already in
+  /// progress the request is queued, and nothing is sent until the other side replies.
   pub fn _will(&mut self, option: u8) -> Option<TelnetEvents> {
-    match self.options.get_option(option) {
-      mut opt @ CompatibilityEntry {
-        local: true,
-        local_state: false,
-        ..
-      } => {
-        opt.local_state = true;
-        self.options.set_option(option, opt);
-        Some(self.negotiate(WILL, option))
-      }
-      _ => None,
+    if !self.options.get_option(option).local {
+      return None;
     }
+    let (side, send) = qmethod::request_enable(self.options.local_side(option));
+    self.options.set_local_side(option, side);
+    send.then(|| self.negotiate(WILL, option))
   }
 
   /// Indicate to the other side that you are not wanting to utilize an option.
@@ -226,16 +651,9 @@ impl Parser {
   /// `Option<TelnetEvents::DataSend>` - A `DataSend` event to be processed, or None if the option is already disabled.
   ///
   pub fn _wont(&mut self, option: u8) -> Option<TelnetEvents> {
-    match self.options.get_option(option) {
-      mut opt @ CompatibilityEntry {
-        local_state: true, ..
-      } => {
-        opt.local_state = false;
-        self.options.set_option(option, opt);
-        Some(self.negotiate(WONT, option))
-      }
-      _ => None,
-    }
+    let (side, send) = qmethod::request_disable(self.options.local_side(option));
+    self.options.set_local_side(option, side);
+    send.then(|| self.negotiate(WONT, option))
   }
 
   /// Indicate to the other side that you would like them to utilize an option.
@@ -252,14 +670,12 @@ impl Parser {
   ///
   /// This method will do nothing if the option is not "supported" remotely via the `CompatibilityTable`.
   pub fn _do(&mut self, option: u8) -> Option<TelnetEvents> {
-    match self.options.get_option(option) {
-      CompatibilityEntry {
-        remote: true,
-        remote_state: false,
-        ..
-      } => Some(self.negotiate(DO, option)),
-      _ => None,
+    if !self.options.get_option(option).remote {
+      return None;
     }
+    let (side, send) = qmethod::request_enable(self.options.remote_side(option));
+    self.options.set_remote_side(option, side);
+    send.then(|| self.negotiate(DO, option))
   }
 
   /// Indicate to the other side that you would like them to stop utilizing an option.
@@ -273,12 +689,9 @@ impl Parser {
   /// `Option<TelnetEvents::DataSend>` - A `DataSend` event to be processed, or None if the option is already disabled.
   ///
   pub fn _dont(&mut self, option: u8) -> Option<TelnetEvents> {
-    match self.options.get_option(option) {
-      CompatibilityEntry {
-        remote_state: true, ..
-      } => Some(self.negotiate(DONT, option)),
-      _ => None,
-    }
+    let (side, send) = qmethod::request_disable(self.options.remote_side(option));
+    self.options.set_remote_side(option, side);
+    send.then(|| self.negotiate(DONT, option))
   }
 
   /// Send a subnegotiation for a locally supported option.
@@ -305,9 +718,7 @@ impl Parser {
         local: true,
         local_state: true,
         ..
-      } => Some(TelnetEvents::build_send(
-        TelnetSubnegotiation::new(option, Bytes::from(data)).to_bytes(),
-      )),
+      } => Some(self.send(TelnetSubnegotiation::new(option, Bytes::from(data)).to_bytes())),
       _ => None,
     }
   }
@@ -331,6 +742,296 @@ impl Parser {
     self.subnegotiation(option, Bytes::copy_from_slice(text.as_bytes()))
   }
 
+  /// Send a GMCP message.
+  ///
+  /// # Returns
+  ///
+  /// `Option<TelnetEvents::DataSend>` - A `DataSend` event to be processed, or None if GMCP is not supported or is currently disabled.
+  ///
+  /// # Notes
+  ///
+  /// Unlike `subnegotiation`, GMCP may be enabled on either side, as a client enables it
+  /// remotely by answering the server's `WILL GMCP`.
+  pub fn send_gmcp(&mut self, message: &gmcp::GmcpMessage) -> Option<TelnetEvents> {
+    self.send_subnegotiation(telnet::op_option::GMCP, message.to_bytes())
+  }
+
+  /// Send an ATCP message.
+  ///
+  /// # Returns
+  ///
+  /// `Option<TelnetEvents::DataSend>` - A `DataSend` event to be processed, or None if ATCP is not supported or is currently disabled.
+  ///
+  /// # Notes
+  ///
+  /// Unlike `subnegotiation`, ATCP may be enabled on either side, as a client enables it
+  /// remotely by answering the server's `WILL ATCP`.
+  pub fn send_atcp(&mut self, message: &atcp::AtcpMessage) -> Option<TelnetEvents> {
+    self.send_subnegotiation(telnet::op_option::ATCP, message.to_bytes())
+  }
+
+  /// Send an ATCP `hello` or `auth` handshake, encoded with `atcp::hello` or `atcp::auth`.
+  ///
+  /// # Returns
+  ///
+  /// `Option<TelnetEvents::DataSend>` - A `DataSend` event to be processed, or None if ATCP is not enabled on either side.
+  pub fn send_atcp_handshake(&mut self, handshake: Bytes) -> Option<TelnetEvents> {
+    self.send_subnegotiation(telnet::op_option::ATCP, handshake)
+  }
+
+  /// Send a GMCP or ATCP message, depending on its protocol.
+  ///
+  /// # Returns
+  ///
+  /// `Option<TelnetEvents::DataSend>` - A `DataSend` event to be processed, or None if the protocol is not enabled on either side.
+  pub fn send_oob(&mut self, message: oob::OobMessage) -> Option<TelnetEvents> {
+    let sub = TelnetSubnegotiation::from(message);
+    self.send_subnegotiation(sub.option, sub.buffer)
+  }
+
+  /// Send MSDP variables.
+  ///
+  /// # Returns
+  ///
+  /// `Option<TelnetEvents::DataSend>` - A `DataSend` event to be processed, or None if MSDP is not supported or is currently disabled.
+  ///
+  /// # Notes
+  ///
+  /// Unlike `subnegotiation`, MSDP may be enabled on either side, as a client enables it
+  /// remotely by answering the server's `WILL MSDP`.
+  pub fn send_msdp(&mut self, vars: &[msdp::MsdpVariable]) -> Option<TelnetEvents> {
+    self.send_subnegotiation(telnet::op_option::MSDP, msdp::encode(vars))
+  }
+
+  /// Send MSSP variables.
+  ///
+  /// # Returns
+  ///
+  /// `Option<TelnetEvents::DataSend>` - A `DataSend` event to be processed, or None if MSSP is not supported or is currently disabled.
+  ///
+  /// # Notes
+  ///
+  /// This is a convenience wrapper around `subnegotiation` for `op_option::MSSP`.
+  pub fn send_mssp(&mut self, mssp: &mssp::Mssp) -> Option<TelnetEvents> {
+    self.subnegotiation(telnet::op_option::MSSP, mssp.encode())
+  }
+
+  /// Send the local window size.
+  ///
+  /// # Returns
+  ///
+  /// `Option<TelnetEvents::DataSend>` - A `DataSend` event to be processed, or None if NAWS is not supported or is currently disabled.
+  ///
+  /// # Notes
+  ///
+  /// The size is remembered even if NAWS is not enabled, and automatically sent when the remote end
+  /// enables NAWS later on.
+  pub fn send_window_size(&mut self, size: naws::WindowSize) -> Option<TelnetEvents> {
+    self.window_size = Some(size);
+    self.subnegotiation(telnet::op_option::NAWS, size.encode())
+  }
+
+  /// Get the last local window size given to `send_window_size`.
+  #[must_use]
+  pub fn window_size(&self) -> Option<naws::WindowSize> {
+    self.window_size
+  }
+
+  /// Set the terminal types used to automatically answer `IAC SB TTYPE SEND IAC SE` while TTYPE
+  /// is enabled locally, or `None` to leave answering to the caller.
+  ///
+  /// The position in the cycle is kept by the parser, and restarts whenever TTYPE is enabled.
+  pub fn set_ttype_responder(&mut self, responder: Option<ttype::TtypeResponder>) {
+    self.ttype_responder = responder;
+  }
+
+  /// Get the responder set with `set_ttype_responder`.
+  #[must_use]
+  pub fn ttype_responder(&self) -> Option<&ttype::TtypeResponder> {
+    self.ttype_responder.as_ref()
+  }
+
+  /// Start asking the remote end for its terminal types.
+  ///
+  /// # Returns
+  ///
+  /// `Option<TelnetEvents::DataSend>` - A `DataSend` event to be processed, or None if TTYPE is not enabled remotely.
+  ///
+  /// # Notes
+  ///
+  /// Each terminal type received is answered with another `SEND` until the cycle is complete, at
+  /// which point the result is available from `ttype_probe`.
+  pub fn probe_ttype(&mut self) -> Option<TelnetEvents> {
+    if !self.remote_enabled(telnet::op_option::TTYPE) {
+      return None;
+    }
+    self.ttype_probe = Some(ttype::TtypeProbe::new());
+    Some(self.send_ttype_request())
+  }
+
+  /// Get the state of the probe started with `probe_ttype`.
+  #[must_use]
+  pub fn ttype_probe(&self) -> Option<&ttype::TtypeProbe> {
+    self.ttype_probe.as_ref()
+  }
+
+  fn send_ttype_request(&mut self) -> TelnetEvents {
+    let request = TelnetSubnegotiation::from(ttype::TtypeMessage::Send);
+    self.send(request.to_bytes())
+  }
+
+  /// Set the character sets used to automatically answer a CHARSET `REQUEST`, or `None` to leave
+  /// answering to the caller.
+  pub fn set_charset_responder(&mut self, responder: Option<charset::CharsetResponder>) {
+    self.charset_responder = responder;
+  }
+
+  /// Get the responder set with `set_charset_responder`.
+  #[must_use]
+  pub fn charset_responder(&self) -> Option<&charset::CharsetResponder> {
+    self.charset_responder.as_ref()
+  }
+
+  /// Offer the remote end a list of character sets, most preferred first.
+  ///
+  /// # Returns
+  ///
+  /// `Option<TelnetEvents::DataSend>` - A `DataSend` event to be processed, or None if CHARSET is not enabled.
+  ///
+  /// # Notes
+  ///
+  /// When the remote end answers with `ACCEPTED` the character set is available from `charset`.
+  pub fn request_charset(&mut self, charsets: &[&str]) -> Option<TelnetEvents> {
+    if !self.either_enabled(telnet::op_option::CHARSET) {
+      return None;
+    }
+    let request = charset::CharsetMessage::request(charsets.iter().copied());
+    Some(self.send(TelnetSubnegotiation::from(request).to_bytes()))
+  }
+
+  /// Get the character set agreed on through CHARSET, if any.
+  ///
+  /// This is cleared when CHARSET is disabled.
+  #[must_use]
+  pub fn charset(&self) -> Option<&str> {
+    self.charset.as_deref()
+  }
+
+  /// Set the variables used to automatically answer a NEW-ENVIRON `SEND` while NEW-ENVIRON is
+  /// enabled locally, or `None` to leave answering to the caller.
+  pub fn set_environ(&mut self, environ: Option<new_environ::EnvironStore>) {
+    self.environ = environ;
+  }
+
+  /// Get the variables set with `set_environ`.
+  #[must_use]
+  pub fn environ(&self) -> Option<&new_environ::EnvironStore> {
+    self.environ.as_ref()
+  }
+
+  /// Change a variable of the store set with `set_environ`, informing the remote end of the change.
+  ///
+  /// # Returns
+  ///
+  /// `Option<TelnetEvents::DataSend>` - A `DataSend` event with an `INFO` message to be processed, or None if no store is set or NEW-ENVIRON is not enabled locally.
+  pub fn set_environ_var(
+    &mut self,
+    kind: new_environ::VarKind,
+    name: &str,
+    value: &str,
+  ) -> Option<TelnetEvents> {
+    let environ = self.environ.as_mut()?;
+    environ.set(kind, name, value);
+    let var = new_environ::EnvVar::new(kind, name, Some(value.to_owned()));
+    let info = new_environ::EnvironMessage::Info(vec![var]);
+    self.subnegotiation(telnet::op_option::NEWENVIRON, info.encode())
+  }
+
+  /// Get the negotiated LINEMODE editing mode and special line characters.
+  #[must_use]
+  pub fn linemode(&self) -> &linemode::LinemodeState {
+    &self.linemode
+  }
+
+  /// Ask the remote end to use a LINEMODE editing mode.
+  ///
+  /// # Returns
+  ///
+  /// `Option<TelnetEvents::DataSend>` - A `DataSend` event to be processed, or None if LINEMODE is not enabled remotely.
+  ///
+  /// # Notes
+  ///
+  /// The mode returned by `linemode` changes once the remote end acknowledges it.
+  pub fn send_linemode_mode(&mut self, mode: linemode::Mode) -> Option<TelnetEvents> {
+    if !self.remote_enabled(telnet::op_option::LINEMODE) {
+      return None;
+    }
+    let message = linemode::LinemodeMessage::Mode(mode.without_ack());
+    Some(self.send(TelnetSubnegotiation::from(message).to_bytes()))
+  }
+
+  /// Send special line characters, updating the table returned by `linemode`.
+  ///
+  /// # Returns
+  ///
+  /// `Option<TelnetEvents::DataSend>` - A `DataSend` event to be processed, or None if LINEMODE is not enabled.
+  pub fn send_slc(&mut self, triplets: &[linemode::SlcTriplet]) -> Option<TelnetEvents> {
+    if !self.either_enabled(telnet::op_option::LINEMODE) {
+      return None;
+    }
+    for triplet in triplets {
+      self.linemode.slc.apply(*triplet);
+    }
+    let message = linemode::LinemodeMessage::Slc(triplets.to_vec());
+    Some(self.send(TelnetSubnegotiation::from(message).to_bytes()))
+  }
+
+  /// Set the registry used to dispatch received ZMP commands and send their replies, or `None`
+  /// to leave handling them to the caller.
+  ///
+  /// The registry's `zmp.ident` is sent automatically when ZMP is enabled.
+  pub fn set_zmp_registry(&mut self, registry: Option<zmp::ZmpRegistry>) {
+    self.zmp = registry;
+  }
+
+  /// Get the registry set with `set_zmp_registry`.
+  #[must_use]
+  pub fn zmp_registry(&self) -> Option<&zmp::ZmpRegistry> {
+    self.zmp.as_ref()
+  }
+
+  /// Get the registry set with `set_zmp_registry`, to register handlers.
+  pub fn zmp_registry_mut(&mut self) -> Option<&mut zmp::ZmpRegistry> {
+    self.zmp.as_mut()
+  }
+
+  /// Send a ZMP command.
+  ///
+  /// # Returns
+  ///
+  /// `Option<TelnetEvents::DataSend>` - A `DataSend` event to be processed, or None if ZMP is not enabled.
+  pub fn send_zmp(&mut self, command: &zmp::ZmpCommand) -> Option<TelnetEvents> {
+    if !self.either_enabled(telnet::op_option::ZMP) {
+      return None;
+    }
+    let sub = TelnetSubnegotiation::new(telnet::op_option::ZMP, command.encode());
+    Some(self.send(sub.to_bytes()))
+  }
+
+  /// Send a subnegotiation for an option enabled on either side, as the MUD protocols enabled by
+  /// the server's `WILL` are used in both directions.
+  fn send_subnegotiation(&mut self, option: u8, data: Bytes) -> Option<TelnetEvents> {
+    if !self.either_enabled(option) {
+      return None;
+    }
+    Some(self.send(TelnetSubnegotiation::new(option, data).to_bytes()))
+  }
+
+  /// Whether an option is enabled on either side.
+  fn either_enabled(&self, option: u8) -> bool {
+    self.local_enabled(option) || self.remote_enabled(option)
+  }
+
   /// Directly send a string, with appended `\r\n`, to the remote end, along with an `IAC (255) GOAHEAD (249)` sequence.
   ///
   /// # Returns
@@ -341,214 +1042,557 @@ impl Parser {
   ///
   /// The string will have IAC (255) bytes escaped before being sent.
   pub fn send_text(&mut self, text: &str) -> TelnetEvents {
-    TelnetEvents::build_send(Parser::escape_iac(format!("{text}\r\n")))
+    self.send(Parser::escape_iac(format!("{text}\r\n")))
   }
 
-  /// Extract sub-buffers from the current buffer
-  fn extract_event_data(&mut self) -> Vec<EventType> {
-    #[derive(Copy, Clone)]
-    enum State {
-      Normal,
-      Iac,
-      Neg,
-      Sub,
-      SubOpt { opt: u8 },
-      SubIac { opt: u8 },
-    }
-
-    let mut events = Vec::with_capacity(4);
-    let mut iter_state = State::Normal;
-    let mut cmd_begin = 0;
-
-    // Empty self.buffer into an immutable Bytes we can process.
-    // We'll create views of this buffer to pass to the events using 'buf.slice'.
-    // Splitting is O(1) and doesn't copy the data. Freezing is zero-cost. Taking a slice is O(1).
-    let buf = self.buffer.split().freeze();
-    for (index, &val) in buf.iter().enumerate() {
-      (iter_state, cmd_begin) = match (&iter_state, val) {
+  /// Start discarding received data until the `IAC DM` (Data Mark) of a Synch, as described by
+  /// RFC 854.
+  ///
+  /// # Notes
+  ///
+  /// Call this when the connection signals TCP urgent data. Telnet commands, negotiations and
+  /// subnegotiations received before the `IAC DM` are still processed, and the DM itself is
+  /// passed on as a `TelnetEvents::IAC` event. A DM received while not in a Synch is ignored
+  /// apart from its event, e.g. when the urgent notification arrives after the data.
+  pub fn begin_synch(&mut self) {
+    self.synch = true;
+  }
+
+  /// Whether received data is being discarded until the `IAC DM` of a Synch.
+  #[must_use]
+  pub fn is_synching(&self) -> bool {
+    self.synch
+  }
+
+  /// Send a Synch, making the remote end discard the data it has not processed yet.
+  ///
+  /// # Returns
+  ///
+  /// `TelnetEvents::DataSend` - A `DataSend` event with the `IAC DM` sequence.
+  ///
+  /// # Notes
+  ///
+  /// The DM byte must be sent as TCP urgent data (e.g. with `MSG_OOB`), which the remote end
+  /// uses to start discarding data. It is usually preceded by a command such as `IAC IP` or
+  /// `IAC AO` telling the remote end what to do.
+  pub fn send_synch(&mut self) -> TelnetEvents {
+    self.send(Bytes::copy_from_slice(&[IAC, DM]))
+  }
+
+  /// The internal parser method that passes the events in the received bytes to the handler.
+  ///
+  /// Each byte is examined once: the parser state is kept between calls, and only the content of
+  /// a subnegotiation that is not complete yet is buffered.
+  ///
+  /// With `single` set, processing stops after the first complete sequence, and the number of bytes
+  /// processed is returned so the rest can be processed later.
+  #[allow(clippy::too_many_lines)]
+  fn process<H: TelnetHandler>(&mut self, data: &[u8], handler: &mut H, single: bool) -> usize {
+    let mut state = self.state;
+    // The start of the data or subnegotiation content not yet passed on.
+    let mut begin = 0;
+
+    let mut index = 0;
+    while index < data.len() {
+      // Everything up to the next IAC is data, subnegotiation content, or skipped.
+      if let State::SubOpt { opt } = state {
+        let next = find_iac(&data[index..]).map(|offset| index + offset);
+        // Content at the end of the data is buffered until the next call.
+        let (end, limit) = match next {
+          Some(end) => (end, self.config.max_subnegotiation_len),
+          None => (
+            data.len(),
+            self
+              .config
+              .max_subnegotiation_len
+              .min(self.config.max_buffered_bytes),
+          ),
+        };
+        if self.buffer.len() + (end - begin) > limit {
+          let at = begin + limit.saturating_sub(self.buffer.len());
+          state = self.subnegotiation_overflow(opt, &data[begin..at], handler);
+          if single {
+            self.state = state;
+            return at;
+          }
+          begin = at;
+          index = at;
+          continue;
+        }
+        match next {
+          Some(end) => index = end,
+          None => break,
+        }
+      } else if let State::Normal | State::Overflow { .. } = state {
+        match find_iac(&data[index..]) {
+          Some(offset) => index += offset,
+          None => break,
+        }
+      }
+      let val = data[index];
+      state = match (state, val) {
         (State::Normal, IAC) => {
-          if cmd_begin != index {
-            events.push(EventType::None(buf.slice(cmd_begin..index)));
+          // A command ends the text before it, including any partial MSP trigger.
+          if begin != index || self.msp.is_pending() {
+            self.data_received(&data[begin..index], handler, true);
+            if single {
+              // The IAC is processed by the next call, starting from text.
+              self.state = State::Normal;
+              return index;
+            }
           }
-          (State::Iac, index)
+          State::Iac
         }
-        (State::Iac, IAC) => (State::Normal, cmd_begin), // Double IAC, ignore,
-        (State::Iac, GA | EOR | NOP) => {
-          events.push(EventType::Iac(buf.slice(cmd_begin..=index)));
-          (State::Normal, index + 1)
+        (State::Iac, IAC) => {
+          // Escaped IAC, the second one is data.
+          begin = index;
+          State::Normal
         }
-        (State::Iac, SB) => (State::Sub, cmd_begin),
-        (State::Iac, _) => (State::Neg, cmd_begin), // WILL | WONT | DO | DONT | IS | SEND
-        (State::Neg, _) => {
-          events.push(EventType::Neg(buf.slice(cmd_begin..=index)));
-          (State::Normal, index + 1)
+        (State::Iac, EOF | SUSP | ABORT | EOR | NOP | DM | BRK | IP | AO | AYT | EC | EL | GA) => {
+          if val == DM {
+            self.synch = false;
+          }
+          handler.on_iac(TelnetIAC::new(val));
+          begin = index + 1;
+          State::Normal
         }
-        (State::Sub, opt) => (State::SubOpt { opt }, cmd_begin),
-        (State::SubOpt { opt } | State::SubIac { opt }, IAC) => {
-          (State::SubIac { opt: *opt }, cmd_begin)
+        (State::Iac, SB) => State::Sub,
+        (State::Iac, SE) => {
+          self.protocol_error(TelnetError::UnexpectedSe, handler);
+          begin = index + 1;
+          State::Normal
         }
-        (State::SubIac { opt }, SE)
-          if *opt == telnet::op_option::MCCP2 || *opt == telnet::op_option::MCCP3 =>
-        {
-          // MCCP2/MCCP3 MUST DECOMPRESS DATA AFTER THIS!
-          events.push(EventType::SubNegotiation(
-            buf.slice(cmd_begin..=index),
-            Some(buf.slice(index + 1..)),
-          ));
-          cmd_begin = buf.len();
-          break;
+        (State::Iac, WILL | WONT | DO | DONT) => State::Neg { command: val },
+        (State::Iac, command) => {
+          self.protocol_error(TelnetError::UnknownCommand(command), handler);
+          begin = index + 1;
+          State::Normal
+        }
+        (State::Neg { command }, opt) => {
+          self.process_negotiation(command, opt, handler);
+          begin = index + 1;
+          State::Normal
+        }
+        (State::Sub, IAC) => {
+          // No option, the IAC starts a command.
+          self.protocol_error(TelnetError::TruncatedSubnegotiation(IAC), handler);
+          State::Iac
+        }
+        (State::Sub, opt) => {
+          begin = index + 1;
+          State::SubOpt { opt }
+        }
+        (State::SubOpt { opt }, IAC) => State::SubIac { opt },
+        (State::Overflow { opt, truncated }, IAC) => State::OverflowIac { opt, truncated },
+        // Not reached, bytes other than IAC are skipped above.
+        (State::Normal | State::SubOpt { .. } | State::Overflow { .. }, _) => state,
+        (State::SubIac { opt }, SE) => {
+          if self.subnegotiation_ended(opt, &data[begin..index], handler)
+            && (opt == telnet::op_option::MCCP2 || opt == telnet::op_option::MCCP3)
+          {
+            // MCCP2/MCCP3 MUST DECOMPRESS DATA AFTER THIS!
+            self.state = State::Normal;
+            let rest = &data[index + 1..];
+            #[cfg(feature = "mccp")]
+            if self.mccp.enabled {
+              self.mccp.decompressor = Some(mccp::Decompressor::new());
+              if single {
+                return index + 1;
+              }
+              self.receive_with(rest, handler);
+              return data.len();
+            }
+            handler.on_decompress_immediate(rest);
+            return data.len();
+          }
+          begin = index + 1;
+          State::Normal
+        }
+        (State::SubIac { opt }, IAC) => State::SubOpt { opt },
+        (State::SubIac { opt }, SB) => {
+          // A new subnegotiation, the unfinished one is dropped.
+          self.protocol_error(TelnetError::TruncatedSubnegotiation(opt), handler);
+          self.buffer.clear();
+          State::Sub
         }
-        (State::SubIac { .. }, SE) => {
-          events.push(EventType::SubNegotiation(
-            buf.slice(cmd_begin..=index),
-            None,
-          ));
-          (State::Normal, index + 1)
+        (State::SubIac { opt }, _) => {
+          // An invalid command, kept as content.
+          self.protocol_error(TelnetError::InvalidSubnegotiationCommand(opt), handler);
+          State::SubOpt { opt }
+        }
+        (State::OverflowIac { opt, truncated }, SE) => {
+          if truncated {
+            let buffer = core::mem::take(&mut self.buffer);
+            self.subnegotiation_complete(opt, &buffer, handler);
+            self.buffer = buffer;
+            self.buffer.clear();
+          }
+          begin = index + 1;
+          State::Normal
         }
-        (State::SubIac { opt }, _) => (State::SubOpt { opt: *opt }, cmd_begin),
-        (cur_state, _) => (*cur_state, cmd_begin),
+        (State::OverflowIac { .. }, command)
+          if command != IAC && self.config.overflow_policy == OverflowPolicy::Reset =>
+        {
+          // Leave the subnegotiation at the first command, processing it as usual.
+          state = State::Iac;
+          continue;
+        }
+        (State::OverflowIac { opt, truncated }, _) => State::Overflow { opt, truncated },
       };
+      if single && state == State::Normal && begin == index + 1 {
+        // A command, negotiation or subnegotiation ended.
+        self.state = state;
+        return begin;
+      }
+      index += 1;
     }
 
-    if cmd_begin < buf.len() {
-      match iter_state {
-        State::Sub | State::SubOpt { .. } | State::SubIac { .. } => {
-          events.push(EventType::SubNegotiation(buf.slice(cmd_begin..), None));
+    let limit = self
+      .config
+      .max_subnegotiation_len
+      .min(self.config.max_buffered_bytes);
+    match state {
+      State::Normal if begin < data.len() => self.data_received(&data[begin..], handler, false),
+      // Content up to a command at its end was only checked against `max_subnegotiation_len`
+      // above, and the content after it not at all.
+      State::SubOpt { opt } | State::SubIac { opt }
+        if self.buffer.len() + (data.len() - begin) > limit =>
+      {
+        // A final IAC is not content.
+        let iac = usize::from(matches!(state, State::SubIac { .. }));
+        let at = begin
+          + limit
+            .saturating_sub(self.buffer.len())
+            .min(data.len() - iac - begin);
+        state = match (
+          state,
+          self.subnegotiation_overflow(opt, &data[begin..at], handler),
+        ) {
+          (State::SubIac { .. }, State::Overflow { opt, truncated }) => {
+            State::OverflowIac { opt, truncated }
+          }
+          (_, overflow) => overflow,
+        };
+      }
+      State::SubOpt { .. } | State::SubIac { .. } => self.buffer.put(&data[begin..]),
+      // Truncated content kept up to `max_subnegotiation_len` within this call.
+      State::Overflow {
+        truncated: true, ..
+      }
+      | State::OverflowIac {
+        truncated: true, ..
+      } => self.buffer.truncate(self.config.max_buffered_bytes),
+      _ => {}
+    }
+    self.state = state;
+    data.len()
+  }
+
+  /// Handle the end of a subnegotiation, given the content received in this call up to the `SE`.
+  ///
+  /// Returns whether the subnegotiation was accepted.
+  fn subnegotiation_ended<H: TelnetHandler>(
+    &mut self,
+    opt: u8,
+    data: &[u8],
+    handler: &mut H,
+  ) -> bool {
+    let mut buffer = core::mem::take(&mut self.buffer);
+    let content = if buffer.is_empty() {
+      data
+    } else {
+      buffer.put(data);
+      &buffer[..]
+    };
+    // Drop the IAC of the final IAC SE.
+    let accepted = self.subnegotiation_complete(opt, &content[..content.len() - 1], handler);
+    // Keep the capacity of the buffer for the next subnegotiation.
+    buffer.clear();
+    self.buffer = buffer;
+    accepted
+  }
+
+  /// Pass the content of a complete subnegotiation to the handler, if it is accepted.
+  ///
+  /// Returns whether the subnegotiation was accepted.
+  fn subnegotiation_complete<H: TelnetHandler>(
+    &mut self,
+    opt: u8,
+    content: &[u8],
+    handler: &mut H,
+  ) -> bool {
+    let accepted = self.accepts_subnegotiation(opt);
+    if accepted {
+      handler.on_subnegotiation(opt, content);
+      if let Some(event) = self.subnegotiation_received(opt, content) {
+        event.dispatch(handler);
+      }
+    } else {
+      self.protocol_error(TelnetError::DisabledOption(opt), handler);
+    }
+    accepted
+  }
+
+  /// Report malformed input to the handler, if enabled in the config.
+  fn protocol_error<H: TelnetHandler>(&self, error: TelnetError, handler: &mut H) {
+    if self.config.report_protocol_errors {
+      handler.on_protocol_error(error);
+    }
+  }
+
+  /// Handle a subnegotiation exceeding the configured limits, given the content received in this
+  /// call up to the limit.
+  ///
+  /// Returns the state to continue in.
+  fn subnegotiation_overflow<H: TelnetHandler>(
+    &mut self,
+    opt: u8,
+    data: &[u8],
+    handler: &mut H,
+  ) -> State {
+    self.protocol_error(TelnetError::BufferOverflow(opt), handler);
+    match self.config.overflow_policy {
+      OverflowPolicy::TruncateAndError => {
+        self.buffer.put(data);
+        State::Overflow {
+          opt,
+          truncated: true,
         }
-        State::Iac | State::Neg => {
-          // Partial IAC sequence at end of buffer — preserve for next receive()
-          self.buffer.put(buf.slice(cmd_begin..));
+      }
+      OverflowPolicy::DiscardUntilSe | OverflowPolicy::Reset => {
+        self.buffer.clear();
+        State::Overflow {
+          opt,
+          truncated: false,
         }
-        State::Normal => events.push(EventType::None(buf.slice(cmd_begin..))),
       }
     }
+  }
 
-    events
+  /// Pass received data to the handler, stripping MSP triggers while MSP is enabled, or discard
+  /// it during a Synch.
+  ///
+  /// With `end` set, the text is followed by a command, so a partial trigger at its end is passed
+  /// on as text instead of waiting for more data.
+  fn data_received<H: TelnetHandler>(&mut self, data: &[u8], handler: &mut H, end: bool) {
+    if self.synch {
+      self.msp.flush();
+      return;
+    }
+    if !self.either_enabled(telnet::op_option::MSP) {
+      handler.on_data(data);
+      return;
+    }
+    let tokens = if end {
+      self.msp.feed_end(data)
+    } else {
+      self.msp.feed(data)
+    };
+    for token in tokens {
+      match token {
+        msp::MspToken::Text(text) => handler.on_data(&text),
+        msp::MspToken::Trigger(trigger) => handler.on_msp(trigger),
+      }
+    }
   }
 
-  /// The internal parser method that takes the current buffer and generates the corresponding events.
-  fn process(&mut self) -> Vec<TelnetEvents> {
-    let mut event_list = Vec::with_capacity(2);
-    let events = self.extract_event_data();
-    for event in events {
-      match event {
-        EventType::None(buffer) | EventType::Iac(buffer) | EventType::Neg(buffer) => {
-          match (buffer.first(), buffer.get(1), buffer.get(2)) {
-            (Some(&IAC), Some(command), None) if *command != SE => {
-              // IAC command
-              event_list.push(TelnetEvents::build_iac(*command));
-            }
-            (Some(&IAC), Some(command), Some(opt)) => {
-              // Negotiation command
-              event_list.extend(self.process_negotiation(*command, *opt));
-            }
-            (Some(c), _, _) if *c != IAC => {
-              // Not an iac sequence, it's data!
-              event_list.push(TelnetEvents::build_receive(buffer));
-            }
-            _ => {}
+  /// Whether a received subnegotiation for `option` is processed.
+  fn accepts_subnegotiation(&self, option: u8) -> bool {
+    match self.subnegotiation_policy(option) {
+      SubnegotiationPolicy::Local => self.local_enabled(option),
+      SubnegotiationPolicy::Remote => self.remote_enabled(option),
+      SubnegotiationPolicy::Either => self.either_enabled(option),
+      SubnegotiationPolicy::Always => true,
+    }
+  }
+
+  /// Send any automatic reply to a received subnegotiation.
+  fn subnegotiation_received(&mut self, opt: u8, data: &[u8]) -> Option<TelnetEvents> {
+    match opt {
+      telnet::op_option::TTYPE => match ttype::TtypeMessage::decode(data).ok()? {
+        ttype::TtypeMessage::Send => {
+          if !self.local_enabled(opt) {
+            return None;
           }
+          let name = self.ttype_responder.as_mut()?.next_type().to_owned();
+          self.subnegotiation(opt, ttype::TtypeMessage::Is(name).encode())
         }
-        EventType::SubNegotiation(buffer, remaining) => {
-          let len = buffer.len();
-          if buffer[len - 2] == IAC && buffer[len - 1] == SE {
-            // Valid ending
-            let opt = self.options.get_option(buffer[2]);
-            if opt.local && opt.local_state && len - 2 >= 3 {
-              event_list.push(TelnetEvents::build_subnegotiation(
-                buffer[2],
-                vbytes!(&buffer[3..len - 2]),
-              ));
-              if let Some(rbuf) = remaining {
-                event_list.push(TelnetEvents::DecompressImmediate(rbuf));
-              }
-            }
-          } else {
-            // Missing the rest
-            self.buffer.put(&buffer[..]);
+        ttype::TtypeMessage::Is(name) => {
+          if !self.remote_enabled(opt) || !self.ttype_probe.as_mut()?.receive(&name) {
+            return None;
           }
+          Some(self.send_ttype_request())
         }
+      },
+      telnet::op_option::NEWENVIRON => match new_environ::EnvironMessage::decode(data).ok()? {
+        new_environ::EnvironMessage::Send(requests) => {
+          let reply = self.environ.as_ref()?.respond(&requests);
+          self.subnegotiation(opt, reply.encode())
+        }
+        _ => None,
+      },
+      telnet::op_option::ZMP => {
+        let command = zmp::ZmpCommand::decode(data).ok()?;
+        let reply = self.zmp.as_mut()?.dispatch(&command)?;
+        self.send_zmp(&reply)
       }
+      telnet::op_option::LINEMODE => match linemode::LinemodeMessage::decode(data).ok()? {
+        linemode::LinemodeMessage::Mode(mode) if mode.contains(linemode::Mode::ACK) => {
+          if self.remote_enabled(opt) {
+            self.linemode.mode = mode.without_ack();
+          }
+          None
+        }
+        linemode::LinemodeMessage::Mode(mode) => {
+          if !self.local_enabled(opt) || self.linemode.mode == mode {
+            return None;
+          }
+          self.linemode.mode = mode;
+          let reply = linemode::LinemodeMessage::Mode(mode | linemode::Mode::ACK);
+          Some(self.send(TelnetSubnegotiation::from(reply).to_bytes()))
+        }
+        linemode::LinemodeMessage::Slc(triplets) => {
+          let acks: Vec<_> = triplets
+            .into_iter()
+            .filter_map(|triplet| self.linemode.slc.apply(triplet))
+            .collect();
+          if acks.is_empty() {
+            return None;
+          }
+          let reply = linemode::LinemodeMessage::Slc(acks);
+          Some(self.send(TelnetSubnegotiation::from(reply).to_bytes()))
+        }
+        linemode::LinemodeMessage::ForwardMask { .. } => None,
+      },
+      telnet::op_option::CHARSET => match charset::CharsetMessage::decode(data).ok()? {
+        charset::CharsetMessage::Request { charsets, .. } => {
+          let reply = self.charset_responder.as_ref()?.respond(&charsets);
+          if let charset::CharsetMessage::Accepted(name) = &reply {
+            self.charset = Some(name.clone());
+          }
+          Some(self.send(TelnetSubnegotiation::from(reply).to_bytes()))
+        }
+        charset::CharsetMessage::Accepted(name) => {
+          self.charset = Some(name);
+          None
+        }
+        charset::CharsetMessage::TtableIs(_) => {
+          self.charset_responder.as_ref()?;
+          let reply = TelnetSubnegotiation::from(charset::CharsetMessage::TtableRejected);
+          Some(self.send(reply.to_bytes()))
+        }
+        _ => None,
+      },
+      _ => None,
     }
-    event_list
-  }
-
-  fn process_negotiation(&mut self, command: u8, opt: u8) -> Vec<TelnetEvents> {
-    let event = TelnetNegotiation::new(command, opt);
-    match (command, self.options.get_option(opt)) {
-      (
-        WILL,
-        mut entry @ CompatibilityEntry {
-          remote: true,
-          remote_state: false,
-          ..
-        },
-      ) => {
-        entry.remote_state = true;
-        self.options.set_option(opt, entry);
-        vec![
-          TelnetEvents::build_send(vbytes!(&[IAC, DO, opt])),
-          TelnetEvents::Negotiation(event),
-        ]
-      }
-      (WILL, CompatibilityEntry { remote: false, .. }) => {
-        vec![TelnetEvents::build_send(vbytes!(&[IAC, DONT, opt]))]
-      }
-      (
-        WONT,
-        mut entry @ CompatibilityEntry {
-          remote_state: true, ..
-        },
-      ) => {
-        entry.remote_state = false;
-        self.options.set_option(opt, entry);
-        vec![
-          TelnetEvents::build_send(vbytes!(&[IAC, DONT, opt])),
-          TelnetEvents::Negotiation(event),
-        ]
+  }
+
+  fn process_negotiation<H: TelnetHandler>(&mut self, command: u8, opt: u8, handler: &mut H) {
+    let entry = self.options.get_option(opt);
+    let was_enabled = match command {
+      WILL | WONT => entry.remote_state,
+      _ => entry.local_state,
+    };
+    let (remote, (side, response)) = match command {
+      WILL => (
+        true,
+        qmethod::receive_enable(self.options.remote_side(opt), entry.remote),
+      ),
+      WONT => (
+        true,
+        qmethod::receive_disable(self.options.remote_side(opt)),
+      ),
+      DO => (
+        false,
+        qmethod::receive_enable(self.options.local_side(opt), entry.local),
+      ),
+      DONT => (
+        false,
+        qmethod::receive_disable(self.options.local_side(opt)),
+      ),
+      // Not reached, only negotiation commands are processed.
+      _ => return,
+    };
+    if remote {
+      self.options.set_remote_side(opt, side);
+    } else {
+      self.options.set_local_side(opt, side);
+    }
+
+    #[cfg(feature = "mccp")]
+    {
+      // MCCP2 compresses data we send when enabled locally, MCCP3 when enabled remotely.
+      let compressing_side = match opt {
+        telnet::op_option::MCCP2 => !remote,
+        telnet::op_option::MCCP3 => remote,
+        _ => false,
+      };
+      let compressing = self.mccp.compressor.as_ref().map(|c| c.option) == Some(opt);
+      if compressing && compressing_side && !side.is_enabled() {
+        // The compressed stream must be ended before the (uncompressed) reply is sent.
+        if let Some(event) = self.end_compression() {
+          event.dispatch(handler);
+        }
       }
-      (
-        DO,
-        mut entry @ CompatibilityEntry {
-          local: true,
-          local_state: false,
-          ..
-        },
-      ) => {
-        entry.local_state = true;
-        entry.remote_state = true;
-        self.options.set_option(opt, entry);
-        vec![
-          TelnetEvents::build_send(vbytes!(&[IAC, WILL, opt])),
-          TelnetEvents::Negotiation(event),
-        ]
+    }
+    if let Some(reply) = response.reply {
+      let reply = match (remote, reply) {
+        (true, Reply::Agree) => DO,
+        (true, Reply::Refuse) => DONT,
+        (false, Reply::Agree) => WILL,
+        (false, Reply::Refuse) => WONT,
+      };
+      handler.on_send(self.compress(Bytes::copy_from_slice(&[IAC, reply, opt])));
+    }
+    if response.notify {
+      handler.on_negotiation(TelnetNegotiation::new(command, opt));
+    }
+    let event = if !was_enabled && side.is_enabled() {
+      self.option_enabled(opt, remote)
+    } else if was_enabled && !side.is_enabled() {
+      self.option_disabled(opt)
+    } else {
+      None
+    };
+    if let Some(event) = event {
+      event.dispatch(handler);
+    }
+  }
+
+  /// Reset any state kept for an option that is no longer enabled on one side.
+  fn option_disabled(&mut self, opt: u8) -> Option<TelnetEvents> {
+    if self.either_enabled(opt) {
+      return None;
+    }
+    match opt {
+      telnet::op_option::CHARSET => self.charset = None,
+      telnet::op_option::LINEMODE => self.linemode = linemode::LinemodeState::default(),
+      // A partial trigger held back is just text after all.
+      telnet::op_option::MSP if self.msp.is_pending() => {
+        return Some(TelnetEvents::DataReceive(self.msp.flush()));
       }
-      (
-        DO,
-        CompatibilityEntry {
-          local_state: false, ..
-        }
-        | CompatibilityEntry { local: false, .. },
-      ) => {
-        vec![TelnetEvents::build_send(vbytes!(&[IAC, WONT, opt]))]
+      _ => {}
+    }
+    None
+  }
+
+  /// Send any data that should follow an option being enabled on one side.
+  fn option_enabled(&mut self, opt: u8, remote: bool) -> Option<TelnetEvents> {
+    match (opt, remote) {
+      (telnet::op_option::NAWS, false) => {
+        let size = self.window_size?;
+        self.subnegotiation(opt, size.encode())
       }
-      (
-        DONT,
-        mut entry @ CompatibilityEntry {
-          local_state: true, ..
-        },
-      ) => {
-        entry.local_state = false;
-        self.options.set_option(opt, entry);
-        vec![
-          TelnetEvents::build_send(vbytes!(&[IAC, WONT, opt])),
-          TelnetEvents::Negotiation(event),
-        ]
+      (telnet::op_option::TTYPE, false) => {
+        self.ttype_responder.as_mut()?.reset();
+        None
       }
-      (DONT | WONT, CompatibilityEntry { .. }) => {
-        vec![TelnetEvents::Negotiation(event)]
+      // Only identify once, should ZMP be enabled on both sides.
+      (telnet::op_option::ZMP, _) if self.local_enabled(opt) != self.remote_enabled(opt) => {
+        let ident = self.zmp.as_ref()?.ident().clone();
+        self.send_zmp(&ident)
       }
-      _ => Vec::default(),
+      _ => None,
     }
   }
 }
@@ -0,0 +1,486 @@
+//! LINEMODE (RFC 1184) codec.
+//!
+//! The client enables LINEMODE with `WILL LINEMODE`, after which three subnegotiations are used:
+//!
+//! * `MODE <mask>`, sent by the server to set the editing mode, and acknowledged by the client
+//!   echoing it back with the `ACK` bit set.
+//! * `DO/DONT/WILL/WONT FORWARDMASK <mask>`, a bitmask of characters that cause the client to
+//!   forward its line buffer.
+//! * `SLC <function> <modifiers> <value> ...`, the special line characters (such as the erase
+//!   character) mapped to each editing function.
+//!
+//! `IAC` bytes in the forward mask or SLC values are doubled on the wire. `LinemodeMessage::decode`
+//! expects them doubled as they are in a received subnegotiation, while `LinemodeMessage::encode`
+//! leaves them for `TelnetSubnegotiation::to_bytes` to escape.
+
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::fmt;
+use core::ops::{BitAnd, BitOr, BitOrAssign};
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::events::TelnetSubnegotiation;
+use crate::telnet::op_command::{DO, DONT, WILL, WONT};
+use crate::telnet::op_option::LINEMODE;
+use crate::Parser;
+
+/// Sets the editing mode.
+pub const MODE: u8 = 1;
+/// Sets the forward mask.
+pub const FORWARDMASK: u8 = 2;
+/// Sets special line characters.
+pub const SLC: u8 = 3;
+
+/// Constants for SLC functions and modifiers.
+pub mod slc {
+  pub const SYNCH: u8 = 1;
+  pub const BRK: u8 = 2;
+  pub const IP: u8 = 3;
+  pub const AO: u8 = 4;
+  pub const AYT: u8 = 5;
+  pub const EOR: u8 = 6;
+  pub const ABORT: u8 = 7;
+  pub const EOF: u8 = 8;
+  pub const SUSP: u8 = 9;
+  pub const EC: u8 = 10;
+  pub const EL: u8 = 11;
+  pub const EW: u8 = 12;
+  pub const RP: u8 = 13;
+  pub const LNEXT: u8 = 14;
+  pub const XON: u8 = 15;
+  pub const XOFF: u8 = 16;
+  pub const FORW1: u8 = 17;
+  pub const FORW2: u8 = 18;
+  pub const MCL: u8 = 19;
+  pub const MCR: u8 = 20;
+  pub const MCWL: u8 = 21;
+  pub const MCWR: u8 = 22;
+  pub const MCBOL: u8 = 23;
+  pub const MCEOL: u8 = 24;
+  pub const INSRT: u8 = 25;
+  pub const OVER: u8 = 26;
+  pub const ECR: u8 = 27;
+  pub const EWR: u8 = 28;
+  pub const EBOL: u8 = 29;
+  pub const EEOL: u8 = 30;
+
+  /// The highest SLC function number.
+  pub const MAX_FUNCTION: u8 = EEOL;
+
+  /// The function is not supported.
+  pub const NOSUPPORT: u8 = 0;
+  /// The value of the function cannot be changed.
+  pub const CANTCHANGE: u8 = 1;
+  /// The function has the given value.
+  pub const VALUE: u8 = 2;
+  /// The function has its default value.
+  pub const DEFAULT: u8 = 3;
+  /// Mask of the support level bits of the modifiers.
+  pub const LEVELBITS: u8 = 3;
+  /// Output should be flushed when the function is used.
+  pub const FLUSHOUT: u8 = 32;
+  /// Input should be flushed when the function is used.
+  pub const FLUSHIN: u8 = 64;
+  /// The triplet acknowledges one that was sent.
+  pub const ACK: u8 = 128;
+}
+
+/// An error decoding a LINEMODE subnegotiation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+  /// The subnegotiation is not for the LINEMODE option.
+  WrongOption(u8),
+  /// The subnegotiation is empty.
+  Empty,
+  /// The subnegotiation is not `MODE`, `FORWARDMASK` or `SLC`.
+  UnknownCommand(u8),
+  /// The subnegotiation ends before a required field.
+  Truncated,
+}
+
+impl fmt::Display for DecodeError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      DecodeError::WrongOption(option) => {
+        write!(f, "subnegotiation for option {option} is not LINEMODE")
+      }
+      DecodeError::Empty => write!(f, "LINEMODE subnegotiation is empty"),
+      DecodeError::UnknownCommand(command) => write!(f, "unknown LINEMODE command {command}"),
+      DecodeError::Truncated => write!(f, "LINEMODE subnegotiation is truncated"),
+    }
+  }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DecodeError {}
+
+/// The LINEMODE editing mode mask.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash)]
+pub struct Mode(u8);
+
+impl Mode {
+  /// The client edits lines locally.
+  pub const EDIT: Self = Self(1);
+  /// The client translates interrupts and signals into Telnet commands.
+  pub const TRAPSIG: Self = Self(2);
+  /// The mode acknowledges one that was sent.
+  pub const ACK: Self = Self(4);
+  /// The client expands tabs to spaces.
+  pub const SOFT_TAB: Self = Self(8);
+  /// The client echoes non-printable characters literally.
+  pub const LIT_ECHO: Self = Self(16);
+
+  /// No flags set.
+  #[must_use]
+  pub const fn empty() -> Self {
+    Self(0)
+  }
+
+  /// Create a mode from the raw mask, keeping any bits without a named constant.
+  #[must_use]
+  pub const fn from_bits(bits: u8) -> Self {
+    Self(bits)
+  }
+
+  /// The raw mask.
+  #[must_use]
+  pub const fn bits(self) -> u8 {
+    self.0
+  }
+
+  /// Whether all flags in `other` are set.
+  #[must_use]
+  pub const fn contains(self, other: Self) -> bool {
+    self.0 & other.0 == other.0
+  }
+
+  /// Set all flags in `other`.
+  pub fn insert(&mut self, other: Self) {
+    self.0 |= other.0;
+  }
+
+  /// Clear all flags in `other`.
+  pub fn remove(&mut self, other: Self) {
+    self.0 &= !other.0;
+  }
+
+  /// The mode without the `ACK` flag.
+  #[must_use]
+  pub const fn without_ack(self) -> Self {
+    Self(self.0 & !Self::ACK.0)
+  }
+}
+
+impl BitOr for Mode {
+  type Output = Self;
+
+  fn bitor(self, rhs: Self) -> Self {
+    Self(self.0 | rhs.0)
+  }
+}
+
+impl BitOrAssign for Mode {
+  fn bitor_assign(&mut self, rhs: Self) {
+    self.insert(rhs);
+  }
+}
+
+impl BitAnd for Mode {
+  type Output = Self;
+
+  fn bitand(self, rhs: Self) -> Self {
+    Self(self.0 & rhs.0)
+  }
+}
+
+/// A special line character triplet.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash)]
+pub struct SlcTriplet {
+  /// The editing function, one of the `slc` function constants.
+  pub function: u8,
+  /// The support level and flags, from the `slc` modifier constants.
+  pub modifiers: u8,
+  /// The character mapped to the function.
+  pub value: u8,
+}
+
+impl SlcTriplet {
+  #[must_use]
+  pub fn new(function: u8, modifiers: u8, value: u8) -> Self {
+    Self {
+      function,
+      modifiers,
+      value,
+    }
+  }
+
+  /// The support level, one of `slc::NOSUPPORT`, `CANTCHANGE`, `VALUE` or `DEFAULT`.
+  #[must_use]
+  pub fn level(self) -> u8 {
+    self.modifiers & slc::LEVELBITS
+  }
+
+  /// Whether the triplet acknowledges one that was sent.
+  #[must_use]
+  pub fn is_ack(self) -> bool {
+    self.modifiers & slc::ACK != 0
+  }
+}
+
+/// A LINEMODE subnegotiation.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum LinemodeMessage {
+  /// `MODE <mask>`.
+  Mode(Mode),
+  /// `DO/DONT/WILL/WONT FORWARDMASK <mask>`. The mask is empty except with `DO`.
+  ForwardMask { command: u8, mask: Bytes },
+  /// `SLC <triplets>`.
+  Slc(Vec<SlcTriplet>),
+}
+
+impl LinemodeMessage {
+  /// Decode the content of a LINEMODE subnegotiation, with `IAC` bytes still doubled.
+  ///
+  /// A trailing incomplete SLC triplet is ignored.
+  ///
+  /// # Errors
+  ///
+  /// Returns a `DecodeError` if the command is missing or unknown, or `MODE` has no mask.
+  pub fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+    let data = Parser::unescape_iac(Bytes::copy_from_slice(data));
+    match data.first() {
+      Some(&MODE) => data
+        .get(1)
+        .map(|mask| LinemodeMessage::Mode(Mode(*mask)))
+        .ok_or(DecodeError::Truncated),
+      Some(&command @ (DO | DONT | WILL | WONT)) => match data.get(1) {
+        Some(&FORWARDMASK) => Ok(LinemodeMessage::ForwardMask {
+          command,
+          mask: data.slice(2..),
+        }),
+        Some(other) => Err(DecodeError::UnknownCommand(*other)),
+        None => Err(DecodeError::Truncated),
+      },
+      Some(&SLC) => Ok(LinemodeMessage::Slc(
+        data[1..]
+          .chunks_exact(3)
+          .map(|triplet| SlcTriplet::new(triplet[0], triplet[1], triplet[2]))
+          .collect(),
+      )),
+      Some(other) => Err(DecodeError::UnknownCommand(*other)),
+      None => Err(DecodeError::Empty),
+    }
+  }
+
+  /// Encode the message as the content of a LINEMODE subnegotiation, without doubling `IAC`.
+  #[must_use]
+  pub fn encode(&self) -> Bytes {
+    let mut buf = BytesMut::new();
+    match self {
+      LinemodeMessage::Mode(mode) => {
+        buf.put_u8(MODE);
+        buf.put_u8(mode.0);
+      }
+      LinemodeMessage::ForwardMask { command, mask } => {
+        buf.put_u8(*command);
+        buf.put_u8(FORWARDMASK);
+        buf.put(&mask[..]);
+      }
+      LinemodeMessage::Slc(triplets) => {
+        buf.put_u8(SLC);
+        for triplet in triplets {
+          buf.put(&[triplet.function, triplet.modifiers, triplet.value][..]);
+        }
+      }
+    }
+    buf.freeze()
+  }
+}
+
+impl TryFrom<&TelnetSubnegotiation> for LinemodeMessage {
+  type Error = DecodeError;
+
+  fn try_from(sub: &TelnetSubnegotiation) -> Result<Self, Self::Error> {
+    if sub.option != LINEMODE {
+      return Err(DecodeError::WrongOption(sub.option));
+    }
+    Self::decode(&sub.buffer)
+  }
+}
+
+impl From<LinemodeMessage> for TelnetSubnegotiation {
+  fn from(message: LinemodeMessage) -> Self {
+    TelnetSubnegotiation::new(LINEMODE, message.encode())
+  }
+}
+
+/// The negotiated special line characters.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SlcTable {
+  entries: [SlcTriplet; slc::MAX_FUNCTION as usize + 1],
+}
+
+impl Default for SlcTable {
+  fn default() -> Self {
+    let mut entries = [SlcTriplet::default(); slc::MAX_FUNCTION as usize + 1];
+    for (function, entry) in (0..).zip(entries.iter_mut()) {
+      entry.function = function;
+    }
+    Self { entries }
+  }
+}
+
+impl SlcTable {
+  #[must_use]
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Get the entry for a function.
+  #[must_use]
+  pub fn get(&self, function: u8) -> Option<SlcTriplet> {
+    self.entries.get(usize::from(function)).copied()
+  }
+
+  /// Get the character mapped to a function, or `None` if the function is not supported.
+  #[must_use]
+  pub fn value(&self, function: u8) -> Option<u8> {
+    self
+      .get(function)
+      .filter(|entry| entry.level() != slc::NOSUPPORT)
+      .map(|entry| entry.value)
+  }
+
+  /// Get the function a character is mapped to, e.g. `slc::EC` for the erase character.
+  #[must_use]
+  pub fn function_for(&self, value: u8) -> Option<u8> {
+    self
+      .supported()
+      .find(|entry| entry.value == value)
+      .map(|entry| entry.function)
+  }
+
+  /// Iterate over the supported functions.
+  pub fn supported(&self) -> impl Iterator<Item = SlcTriplet> + '_ {
+    self
+      .entries
+      .iter()
+      .skip(1)
+      .filter(|entry| entry.level() != slc::NOSUPPORT)
+      .copied()
+  }
+
+  /// Apply a received triplet, returning the acknowledgement to send if it changed the table.
+  ///
+  /// Acknowledgements are applied without a reply, as are triplets that match the current entry.
+  /// Triplets for unknown functions, and requests for the whole table (function 0), are ignored.
+  pub fn apply(&mut self, triplet: SlcTriplet) -> Option<SlcTriplet> {
+    if triplet.function == 0 {
+      return None;
+    }
+    let entry = self.entries.get_mut(usize::from(triplet.function))?;
+    let modifiers = triplet.modifiers & !slc::ACK;
+    if triplet.is_ack() {
+      entry.modifiers = modifiers;
+      entry.value = triplet.value;
+      return None;
+    }
+    if entry.modifiers == modifiers && entry.value == triplet.value {
+      return None;
+    }
+    entry.modifiers = modifiers;
+    entry.value = triplet.value;
+    Some(SlcTriplet::new(
+      triplet.function,
+      modifiers | slc::ACK,
+      triplet.value,
+    ))
+  }
+}
+
+/// The negotiated LINEMODE state kept by the parser.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct LinemodeState {
+  /// The current editing mode, without the `ACK` flag.
+  pub mode: Mode,
+  /// The special line characters.
+  pub slc: SlcTable,
+}
+
+#[cfg(test)]
+mod test_linemode {
+  use super::*;
+  use crate::telnet::op_command::{IAC, SB, SE};
+  use alloc::vec;
+
+  #[test]
+  fn test_mode() {
+    let message = LinemodeMessage::Mode(Mode::EDIT | Mode::TRAPSIG);
+    assert_eq!(&message.encode()[..], &[MODE, 3]);
+    assert_eq!(LinemodeMessage::decode(&message.encode()), Ok(message));
+    assert_eq!(
+      LinemodeMessage::decode(&[MODE]),
+      Err(DecodeError::Truncated)
+    );
+    assert_eq!(
+      LinemodeMessage::decode(&[9]),
+      Err(DecodeError::UnknownCommand(9))
+    );
+  }
+
+  #[test]
+  fn test_forwardmask() {
+    let message = LinemodeMessage::ForwardMask {
+      command: DO,
+      mask: Bytes::from_static(&[0, IAC, 1]),
+    };
+    let sub = TelnetSubnegotiation::from(message.clone()).to_bytes();
+    assert_eq!(
+      &sub[..],
+      &[IAC, SB, LINEMODE, DO, FORWARDMASK, 0, IAC, IAC, 1, IAC, SE]
+    );
+    assert_eq!(LinemodeMessage::decode(&sub[3..sub.len() - 2]), Ok(message));
+    assert_eq!(
+      LinemodeMessage::decode(&[WONT, FORWARDMASK]),
+      Ok(LinemodeMessage::ForwardMask {
+        command: WONT,
+        mask: Bytes::new()
+      })
+    );
+  }
+
+  #[test]
+  fn test_slc() {
+    let data = [
+      SLC,
+      slc::EC,
+      slc::VALUE,
+      0x7F,
+      slc::EL,
+      slc::VALUE,
+      0x15,
+      99,
+    ];
+    let triplets = vec![
+      SlcTriplet::new(slc::EC, slc::VALUE, 0x7F),
+      SlcTriplet::new(slc::EL, slc::VALUE, 0x15),
+    ];
+    assert_eq!(
+      LinemodeMessage::decode(&data),
+      Ok(LinemodeMessage::Slc(triplets.clone()))
+    );
+
+    let mut table = SlcTable::new();
+    let acks: Vec<_> = triplets.iter().filter_map(|t| table.apply(*t)).collect();
+    assert_eq!(acks.len(), 2);
+    assert!(acks.iter().all(|ack| ack.is_ack()));
+    assert_eq!(table.value(slc::EC), Some(0x7F));
+    assert_eq!(table.function_for(0x15), Some(slc::EL));
+    assert_eq!(table.value(slc::IP), None);
+    // Unchanged entries and acknowledgements are not answered.
+    assert_eq!(table.apply(triplets[0]), None);
+    assert_eq!(table.apply(acks[1]), None);
+    assert_eq!(table.apply(SlcTriplet::new(99, slc::VALUE, 1)), None);
+  }
+}
@@ -0,0 +1,152 @@
+//! MCCP2/MCCP3 (Mud Client Compression Protocol) stream compression.
+//!
+//! Only available with the `mccp` crate feature. See `Parser::set_mccp` and
+//! `Parser::start_compression`.
+
+use alloc::boxed::Box;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use miniz_oxide::deflate::core::CompressorOxide;
+use miniz_oxide::inflate::stream::InflateState;
+use miniz_oxide::{deflate, inflate, DataFormat, MZError, MZFlush, MZStatus};
+
+const CHUNK_SIZE: usize = 4096;
+
+/// The result of feeding compressed data to a `Decompressor`.
+pub(crate) enum Inflated {
+  /// All input was consumed and the compressed stream continues.
+  Continue(Bytes),
+  /// The compressed stream ended. Holds the decompressed data, and the uncompressed data that
+  /// followed the end of the stream.
+  End(Bytes, Bytes),
+  /// The compressed stream is corrupt. Holds the data decompressed before the error.
+  Error(Bytes),
+}
+
+/// An inbound zlib stream.
+pub(crate) struct Decompressor {
+  state: Box<InflateState>,
+}
+
+impl Decompressor {
+  pub(crate) fn new() -> Self {
+    Self {
+      state: InflateState::new_boxed(DataFormat::Zlib),
+    }
+  }
+
+  pub(crate) fn decompress(&mut self, mut input: &[u8]) -> Inflated {
+    let mut out = BytesMut::with_capacity(input.len() * 2);
+    let mut chunk = [0; CHUNK_SIZE];
+    loop {
+      let res = inflate::stream::inflate(&mut self.state, input, &mut chunk, MZFlush::None);
+      out.put(&chunk[..res.bytes_written]);
+      input = &input[res.bytes_consumed..];
+      match res.status {
+        Ok(MZStatus::StreamEnd) => {
+          return Inflated::End(out.freeze(), Bytes::copy_from_slice(input));
+        }
+        Ok(_) if res.bytes_written == CHUNK_SIZE => {}
+        Ok(_) if !input.is_empty() && (res.bytes_consumed > 0 || res.bytes_written > 0) => {}
+        // No progress can be made until more input arrives.
+        Ok(_) | Err(MZError::Buf) => return Inflated::Continue(out.freeze()),
+        Err(_) => return Inflated::Error(out.freeze()),
+      }
+    }
+  }
+}
+
+/// An outbound zlib stream.
+pub(crate) struct Compressor {
+  /// The option (MCCP2 or MCCP3) that started this stream.
+  pub(crate) option: u8,
+  state: Box<CompressorOxide>,
+}
+
+impl Compressor {
+  pub(crate) fn new(option: u8) -> Self {
+    Self {
+      option,
+      state: Box::default(),
+    }
+  }
+
+  /// Compress `input`, flushing so the remote end can decompress it immediately.
+  pub(crate) fn compress(&mut self, input: &[u8]) -> Bytes {
+    self.deflate(input, MZFlush::Sync)
+  }
+
+  /// End the compressed stream, returning the final compressed bytes.
+  pub(crate) fn finish(mut self) -> Bytes {
+    self.deflate(&[], MZFlush::Finish)
+  }
+
+  fn deflate(&mut self, mut input: &[u8], flush: MZFlush) -> Bytes {
+    let mut out = BytesMut::with_capacity(input.len() + 16);
+    let mut chunk = [0; CHUNK_SIZE];
+    loop {
+      let res = deflate::stream::deflate(&mut self.state, input, &mut chunk, flush);
+      out.put(&chunk[..res.bytes_written]);
+      input = &input[res.bytes_consumed..];
+      match res.status {
+        Ok(MZStatus::Ok) if res.bytes_written == CHUNK_SIZE || !input.is_empty() => {}
+        _ => return out.freeze(),
+      }
+    }
+  }
+}
+
+/// The MCCP state of a `Parser`.
+#[derive(Default)]
+pub(crate) struct Mccp {
+  /// Whether received compressed data should be decompressed by the parser.
+  pub(crate) enabled: bool,
+  pub(crate) decompressor: Option<Decompressor>,
+  /// Whether the received compressed stream was corrupt, so received data can't be processed.
+  pub(crate) broken: bool,
+  /// Data decompressed from bytes given to `Parser::feed`, not yet processed by `next_event`.
+  pub(crate) inflated: BytesMut,
+  /// Whether a `DecompressError` event is to be returned once `inflated` has been processed.
+  pub(crate) error_pending: bool,
+  pub(crate) compressor: Option<Compressor>,
+}
+
+#[cfg(test)]
+mod test_mccp {
+  use super::*;
+
+  #[test]
+  fn test_roundtrip() {
+    let mut compressor = Compressor::new(0);
+    let mut decompressor = Decompressor::new();
+
+    let first = compressor.compress(b"Hello, ");
+    match decompressor.decompress(&first) {
+      Inflated::Continue(data) => assert_eq!(data, &b"Hello, "[..]),
+      _ => panic!("expected the stream to continue"),
+    }
+
+    let second = [
+      &compressor.compress(b"world!")[..],
+      &compressor.finish()[..],
+      b"plain",
+    ]
+    .concat();
+    match decompressor.decompress(&second) {
+      Inflated::End(data, rest) => {
+        assert_eq!(data, &b"world!"[..]);
+        assert_eq!(rest, &b"plain"[..]);
+      }
+      _ => panic!("expected the stream to end"),
+    }
+  }
+
+  #[test]
+  fn test_corrupt() {
+    let mut decompressor = Decompressor::new();
+    assert!(matches!(
+      decompressor.decompress(b"not zlib data at all"),
+      Inflated::Error(_)
+    ));
+  }
+}
@@ -0,0 +1,393 @@
+//! MSDP (Mud Server Data Protocol) codec.
+//!
+//! MSDP subnegotiations (`IAC SB MSDP ... IAC SE`) carry a list of variables, each a `VAR`
+//! byte followed by a name and one or more `VAL` bytes each followed by a value. A value is a
+//! string, an array (`ARRAY_OPEN` values `ARRAY_CLOSE`) or a table (`TABLE_OPEN` variables
+//! `TABLE_CLOSE`).
+
+use alloc::{vec, vec::Vec};
+use core::fmt;
+use core::str;
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+/// Precedes a variable name.
+pub const VAR: u8 = 1;
+/// Precedes a variable value.
+pub const VAL: u8 = 2;
+/// Starts a table value.
+pub const TABLE_OPEN: u8 = 3;
+/// Ends a table value.
+pub const TABLE_CLOSE: u8 = 4;
+/// Starts an array value.
+pub const ARRAY_OPEN: u8 = 5;
+/// Ends an array value.
+pub const ARRAY_CLOSE: u8 = 6;
+
+/// Nesting deeper than this is rejected when decoding.
+const MAX_DEPTH: usize = 64;
+
+/// An error decoding an MSDP payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+  /// An unexpected byte was found at the given offset.
+  UnexpectedByte { offset: usize, byte: u8 },
+  /// The payload ended inside an array or table, or before a variable's value.
+  UnexpectedEnd,
+  /// Arrays and tables are nested too deeply.
+  TooDeep,
+}
+
+impl fmt::Display for DecodeError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      DecodeError::UnexpectedByte { offset, byte } => {
+        write!(f, "unexpected byte {byte} at offset {offset}")
+      }
+      DecodeError::UnexpectedEnd => write!(f, "unexpected end of MSDP data"),
+      DecodeError::TooDeep => write!(f, "MSDP data is nested too deeply"),
+    }
+  }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DecodeError {}
+
+/// An MSDP value.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum MsdpValue {
+  String(Bytes),
+  Array(Vec<MsdpValue>),
+  Table(Vec<MsdpVariable>),
+}
+
+impl MsdpValue {
+  /// Get the value as a string, if it is a valid UTF-8 string value.
+  #[must_use]
+  pub fn as_str(&self) -> Option<&str> {
+    match self {
+      MsdpValue::String(value) => str::from_utf8(value).ok(),
+      _ => None,
+    }
+  }
+
+  /// Get the value of a table entry by name, if this is a table.
+  #[must_use]
+  pub fn get(&self, name: &str) -> Option<&MsdpValue> {
+    match self {
+      MsdpValue::Table(vars) => vars
+        .iter()
+        .find(|var| var.name == name.as_bytes())
+        .map(|var| &var.value),
+      _ => None,
+    }
+  }
+
+  fn encode(&self, buf: &mut BytesMut) {
+    match self {
+      MsdpValue::String(value) => buf.put(&value[..]),
+      MsdpValue::Array(values) => {
+        buf.put_u8(ARRAY_OPEN);
+        for value in values {
+          buf.put_u8(VAL);
+          value.encode(buf);
+        }
+        buf.put_u8(ARRAY_CLOSE);
+      }
+      MsdpValue::Table(vars) => {
+        buf.put_u8(TABLE_OPEN);
+        for var in vars {
+          var.encode(buf);
+        }
+        buf.put_u8(TABLE_CLOSE);
+      }
+    }
+  }
+}
+
+impl From<&str> for MsdpValue {
+  fn from(value: &str) -> Self {
+    MsdpValue::String(Bytes::copy_from_slice(value.as_bytes()))
+  }
+}
+
+impl From<Bytes> for MsdpValue {
+  fn from(value: Bytes) -> Self {
+    MsdpValue::String(value)
+  }
+}
+
+/// A named MSDP variable, either top-level or as an entry of a table.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct MsdpVariable {
+  pub name: Bytes,
+  pub value: MsdpValue,
+}
+
+impl MsdpVariable {
+  pub fn new<V: Into<MsdpValue>>(name: &str, value: V) -> Self {
+    Self {
+      name: Bytes::copy_from_slice(name.as_bytes()),
+      value: value.into(),
+    }
+  }
+
+  /// `LIST <list>`, requesting a list such as `COMMANDS`, `REPORTABLE_VARIABLES` or
+  /// `REPORTED_VARIABLES`.
+  #[must_use]
+  pub fn list(list: &str) -> Self {
+    Self::new("LIST", list)
+  }
+
+  /// `REPORT <variables>`, asking the server to send the variables whenever they change.
+  #[must_use]
+  pub fn report(variables: &[&str]) -> Self {
+    Self::command("REPORT", variables)
+  }
+
+  /// `UNREPORT <variables>`, asking the server to stop reporting the variables.
+  #[must_use]
+  pub fn unreport(variables: &[&str]) -> Self {
+    Self::command("UNREPORT", variables)
+  }
+
+  /// `SEND <variables>`, asking the server to send the variables once.
+  #[must_use]
+  pub fn send(variables: &[&str]) -> Self {
+    Self::command("SEND", variables)
+  }
+
+  /// `RESET <list>`, asking the server to reset a list such as `REPORTED_VARIABLES`.
+  #[must_use]
+  pub fn reset(list: &str) -> Self {
+    Self::new("RESET", list)
+  }
+
+  fn command(name: &str, variables: &[&str]) -> Self {
+    match variables {
+      [variable] => Self::new(name, *variable),
+      _ => Self::new(
+        name,
+        MsdpValue::Array(variables.iter().map(|v| MsdpValue::from(*v)).collect()),
+      ),
+    }
+  }
+
+  /// Get the variable name as a string, if it is valid UTF-8.
+  #[must_use]
+  pub fn name_str(&self) -> Option<&str> {
+    str::from_utf8(&self.name).ok()
+  }
+
+  fn encode(&self, buf: &mut BytesMut) {
+    buf.put_u8(VAR);
+    buf.put(&self.name[..]);
+    buf.put_u8(VAL);
+    self.value.encode(buf);
+  }
+}
+
+/// Encode variables as the content of an MSDP subnegotiation.
+#[must_use]
+pub fn encode(vars: &[MsdpVariable]) -> Bytes {
+  let mut buf = BytesMut::new();
+  for var in vars {
+    var.encode(&mut buf);
+  }
+  buf.freeze()
+}
+
+/// Decode the content of an MSDP subnegotiation.
+///
+/// A variable with more than one `VAL` (e.g. `VAR "REPORT" VAL "HEALTH" VAL "MANA"`) is decoded
+/// as an array.
+///
+/// # Errors
+///
+/// Returns a `DecodeError` if the data is not well-formed MSDP.
+pub fn decode(data: &[u8]) -> Result<Vec<MsdpVariable>, DecodeError> {
+  let mut decoder = Decoder { data, pos: 0 };
+  let vars = decoder.variables(0)?;
+  match decoder.peek() {
+    None => Ok(vars),
+    Some(byte) => Err(decoder.unexpected(byte)),
+  }
+}
+
+struct Decoder<'a> {
+  data: &'a [u8],
+  pos: usize,
+}
+
+impl Decoder<'_> {
+  fn peek(&self) -> Option<u8> {
+    self.data.get(self.pos).copied()
+  }
+
+  fn unexpected(&self, byte: u8) -> DecodeError {
+    DecodeError::UnexpectedByte {
+      offset: self.pos,
+      byte,
+    }
+  }
+
+  fn expect(&mut self, expected: u8) -> Result<(), DecodeError> {
+    match self.peek() {
+      Some(byte) if byte == expected => {
+        self.pos += 1;
+        Ok(())
+      }
+      Some(byte) => Err(self.unexpected(byte)),
+      None => Err(DecodeError::UnexpectedEnd),
+    }
+  }
+
+  /// Read bytes up to the next MSDP control byte.
+  fn string(&mut self) -> Bytes {
+    let start = self.pos;
+    while matches!(self.peek(), Some(byte) if !(VAR..=ARRAY_CLOSE).contains(&byte)) {
+      self.pos += 1;
+    }
+    Bytes::copy_from_slice(&self.data[start..self.pos])
+  }
+
+  /// Read `VAR name VAL value...` pairs until the end of the data or a `TABLE_CLOSE`.
+  fn variables(&mut self, depth: usize) -> Result<Vec<MsdpVariable>, DecodeError> {
+    let mut vars = Vec::new();
+    while self.peek() == Some(VAR) {
+      self.pos += 1;
+      let name = self.string();
+      self.expect(VAL)?;
+      let mut values = vec![self.value(depth)?];
+      while self.peek() == Some(VAL) {
+        self.pos += 1;
+        values.push(self.value(depth)?);
+      }
+      let value = if values.len() == 1 {
+        values.remove(0)
+      } else {
+        MsdpValue::Array(values)
+      };
+      vars.push(MsdpVariable { name, value });
+    }
+    Ok(vars)
+  }
+
+  fn value(&mut self, depth: usize) -> Result<MsdpValue, DecodeError> {
+    if depth >= MAX_DEPTH {
+      return Err(DecodeError::TooDeep);
+    }
+    match self.peek() {
+      Some(ARRAY_OPEN) => {
+        self.pos += 1;
+        let mut values = Vec::new();
+        while self.peek() == Some(VAL) {
+          self.pos += 1;
+          values.push(self.value(depth + 1)?);
+        }
+        self.expect(ARRAY_CLOSE)?;
+        Ok(MsdpValue::Array(values))
+      }
+      Some(TABLE_OPEN) => {
+        self.pos += 1;
+        let vars = self.variables(depth + 1)?;
+        self.expect(TABLE_CLOSE)?;
+        Ok(MsdpValue::Table(vars))
+      }
+      _ => Ok(MsdpValue::String(self.string())),
+    }
+  }
+}
+
+#[cfg(test)]
+mod test_msdp {
+  use super::*;
+
+  #[test]
+  fn test_roundtrip() {
+    let vars = vec![
+      MsdpVariable::new("HEALTH", "100"),
+      MsdpVariable::new(
+        "ROOM",
+        MsdpValue::Table(vec![
+          MsdpVariable::new("VNUM", "6008"),
+          MsdpVariable::new(
+            "EXITS",
+            MsdpValue::Table(vec![MsdpVariable::new("n", "6011")]),
+          ),
+          MsdpVariable::new(
+            "TERRAIN",
+            MsdpValue::Array(vec!["inside".into(), "".into()]),
+          ),
+        ]),
+      ),
+    ];
+    let encoded = encode(&vars);
+    assert_eq!(
+      &encoded[..12],
+      &[VAR, b'H', b'E', b'A', b'L', b'T', b'H', VAL, b'1', b'0', b'0', VAR]
+    );
+    let decoded = decode(&encoded).unwrap();
+    assert_eq!(decoded, vars);
+    assert_eq!(
+      decoded[1].value.get("EXITS").and_then(|e| e.get("n")),
+      Some(&MsdpValue::from("6011"))
+    );
+  }
+
+  #[test]
+  fn test_multiple_vals() {
+    let data = [&[VAR][..], b"REPORT", &[VAL], b"HEALTH", &[VAL], b"MANA"].concat();
+    assert_eq!(
+      decode(&data).unwrap(),
+      vec![MsdpVariable::report(&["HEALTH", "MANA"])]
+    );
+  }
+
+  #[test]
+  fn test_commands() {
+    assert_eq!(
+      &encode(&[MsdpVariable::list("COMMANDS")])[..],
+      &[&[VAR][..], b"LIST", &[VAL], b"COMMANDS"].concat()[..]
+    );
+    assert_eq!(
+      &encode(&[MsdpVariable::send(&["HEALTH", "MANA"])])[..],
+      &[
+        &[VAR][..],
+        b"SEND",
+        &[VAL, ARRAY_OPEN, VAL],
+        b"HEALTH",
+        &[VAL],
+        b"MANA",
+        &[ARRAY_CLOSE]
+      ]
+      .concat()[..]
+    );
+    assert_eq!(
+      MsdpVariable::unreport(&["HEALTH"]),
+      MsdpVariable::new("UNREPORT", "HEALTH")
+    );
+    assert_eq!(
+      MsdpVariable::reset("REPORTED_VARIABLES"),
+      MsdpVariable::new("RESET", "REPORTED_VARIABLES")
+    );
+  }
+
+  #[test]
+  fn test_malformed() {
+    assert_eq!(decode(&[VAR, b'A']), Err(DecodeError::UnexpectedEnd));
+    assert_eq!(
+      decode(&[VAR, b'A', VAL, ARRAY_OPEN, VAL]),
+      Err(DecodeError::UnexpectedEnd)
+    );
+    assert_eq!(
+      decode(&[VAR, b'A', VAL, TABLE_CLOSE]),
+      Err(DecodeError::UnexpectedByte {
+        offset: 3,
+        byte: TABLE_CLOSE
+      })
+    );
+    let deep = [&[VAR, b'A', VAL][..], &[ARRAY_OPEN, VAL][..].repeat(100)].concat();
+    assert_eq!(decode(&deep), Err(DecodeError::TooDeep));
+  }
+}
@@ -0,0 +1,371 @@
+//! MSP (MUD Sound Protocol) trigger parsing.
+//!
+//! Once MSP is enabled, the server embeds sound and music triggers in the text it sends, e.g.
+//! `!!SOUND(thunder.wav V=80 L=2 P=60 T=weather U=https://example.com/sounds/)` or
+//! `!!MUSIC(theme.mid C=1)`, which a client strips before displaying the text. The parameters
+//! are:
+//!
+//! * `V` - the volume, 0 to 100.
+//! * `L` - how often to play the file, with -1 repeating until stopped.
+//! * `P` - the priority of a sound, 0 to 100.
+//! * `C` - whether music that is already playing continues, 0 or 1.
+//! * `T` - the type, i.e. the directory the file is found in.
+//! * `U` - the base URL the file can be downloaded from.
+//!
+//! A file name of `Off` stops any playing sound or music.
+//!
+//! `Parser` strips triggers from `TelnetEvents::DataReceive` payloads while MSP is enabled, and
+//! emits `TelnetEvents::Msp` events in their place. `MspFilter` does the same for data from
+//! other sources.
+
+use alloc::{
+  string::{String, ToString},
+  vec::Vec,
+};
+use core::fmt;
+use core::str::FromStr;
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+/// The start of a sound trigger.
+pub const SOUND: &[u8] = b"!!SOUND(";
+
+/// The start of a music trigger.
+pub const MUSIC: &[u8] = b"!!MUSIC(";
+
+/// The longest trigger the filter buffers before giving up and treating it as text.
+pub const MAX_TRIGGER_LEN: usize = 1024;
+
+/// An error parsing an MSP trigger.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+  /// The text is not of the form `!!SOUND(...)` or `!!MUSIC(...)`.
+  NotATrigger,
+  /// The trigger has no file name.
+  MissingFile,
+  /// A parameter is unknown, or its value is invalid.
+  InvalidParameter(char),
+}
+
+impl fmt::Display for ParseError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ParseError::NotATrigger => write!(f, "text is not an MSP trigger"),
+      ParseError::MissingFile => write!(f, "MSP trigger has no file name"),
+      ParseError::InvalidParameter(key) => write!(f, "MSP parameter {key} is invalid"),
+    }
+  }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseError {}
+
+/// Whether a trigger plays a sound or music.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum MspKind {
+  Sound,
+  Music,
+}
+
+/// A `!!SOUND(...)` or `!!MUSIC(...)` trigger. Parameters not given are `None`.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct MspTrigger {
+  pub kind: MspKind,
+  /// The file to play, possibly with wildcards, or `Off`.
+  pub file: String,
+  /// `V`: the volume, 0 to 100.
+  pub volume: Option<u8>,
+  /// `L`: how often to play the file, with -1 repeating until stopped.
+  pub repeats: Option<i32>,
+  /// `P`: the priority of a sound, 0 to 100.
+  pub priority: Option<u8>,
+  /// `C`: whether music that is already playing continues.
+  pub continue_music: Option<bool>,
+  /// `T`: the type of the file.
+  pub sound_type: Option<String>,
+  /// `U`: the base URL the file can be downloaded from.
+  pub url: Option<String>,
+}
+
+impl MspTrigger {
+  /// Create a trigger without parameters.
+  pub fn new<F: Into<String>>(kind: MspKind, file: F) -> Self {
+    Self {
+      kind,
+      file: file.into(),
+      volume: None,
+      repeats: None,
+      priority: None,
+      continue_music: None,
+      sound_type: None,
+      url: None,
+    }
+  }
+
+  /// Whether the trigger stops any playing sound or music.
+  #[must_use]
+  pub fn is_off(&self) -> bool {
+    self.file.eq_ignore_ascii_case("off")
+  }
+
+  /// Parse a full trigger, e.g. `!!SOUND(thunder.wav V=80)`.
+  ///
+  /// # Errors
+  ///
+  /// Returns `ParseError::NotATrigger` if the text is not a trigger, or an error describing the
+  /// invalid part of the trigger.
+  pub fn parse(text: &str) -> Result<Self, ParseError> {
+    let (kind, rest) = if let Some(rest) = text.strip_prefix("!!SOUND(") {
+      (MspKind::Sound, rest)
+    } else if let Some(rest) = text.strip_prefix("!!MUSIC(") {
+      (MspKind::Music, rest)
+    } else {
+      return Err(ParseError::NotATrigger);
+    };
+    let args = rest.strip_suffix(')').ok_or(ParseError::NotATrigger)?;
+    let mut words = args.split_whitespace();
+    let mut trigger = Self::new(kind, words.next().ok_or(ParseError::MissingFile)?);
+    for word in words {
+      let first = word.chars().next().unwrap_or('?');
+      let (key, value) = word
+        .split_once('=')
+        .ok_or(ParseError::InvalidParameter(first))?;
+      let key = match key {
+        "V" | "v" => 'V',
+        "L" | "l" => 'L',
+        "P" | "p" => 'P',
+        "C" | "c" => 'C',
+        "T" | "t" => 'T',
+        "U" | "u" => 'U',
+        _ => return Err(ParseError::InvalidParameter(first)),
+      };
+      let invalid = ParseError::InvalidParameter(key);
+      match key {
+        'V' => trigger.volume = Some(parse_percent(value).ok_or(invalid)?),
+        'L' => trigger.repeats = Some(i32::from_str(value).map_err(|_| invalid)?),
+        'P' => trigger.priority = Some(parse_percent(value).ok_or(invalid)?),
+        'C' => {
+          trigger.continue_music = Some(match value {
+            "0" => false,
+            "1" => true,
+            _ => return Err(invalid),
+          });
+        }
+        'T' => trigger.sound_type = Some(value.to_string()),
+        _ => trigger.url = Some(value.to_string()),
+      }
+    }
+    Ok(trigger)
+  }
+}
+
+fn parse_percent(value: &str) -> Option<u8> {
+  u8::from_str(value).ok().filter(|v| *v <= 100)
+}
+
+impl fmt::Display for MspTrigger {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let name = match self.kind {
+      MspKind::Sound => "SOUND",
+      MspKind::Music => "MUSIC",
+    };
+    write!(f, "!!{}({}", name, self.file)?;
+    if let Some(volume) = self.volume {
+      write!(f, " V={volume}")?;
+    }
+    if let Some(repeats) = self.repeats {
+      write!(f, " L={repeats}")?;
+    }
+    if let Some(priority) = self.priority {
+      write!(f, " P={priority}")?;
+    }
+    if let Some(continue_music) = self.continue_music {
+      write!(f, " C={}", u8::from(continue_music))?;
+    }
+    if let Some(sound_type) = &self.sound_type {
+      write!(f, " T={sound_type}")?;
+    }
+    if let Some(url) = &self.url {
+      write!(f, " U={url}")?;
+    }
+    write!(f, ")")
+  }
+}
+
+/// Text with MSP triggers stripped out.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum MspToken {
+  Text(Bytes),
+  Trigger(MspTrigger),
+}
+
+/// Strips MSP triggers from received text, keeping any partial trigger between calls to `feed`.
+#[derive(Clone, Debug, Default)]
+pub struct MspFilter {
+  pending: Vec<u8>,
+}
+
+impl MspFilter {
+  #[must_use]
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Whether the start of a trigger is buffered, waiting for more data.
+  #[must_use]
+  pub fn is_pending(&self) -> bool {
+    !self.pending.is_empty()
+  }
+
+  /// Strip triggers from the next chunk of received text.
+  ///
+  /// Malformed triggers are left in the text.
+  pub fn feed(&mut self, data: &[u8]) -> Vec<MspToken> {
+    let mut tokens = Vec::new();
+    let mut text = BytesMut::new();
+    for &b in data {
+      self.push(b, &mut text, &mut tokens);
+    }
+    if !text.is_empty() {
+      tokens.push(MspToken::Text(text.freeze()));
+    }
+    tokens
+  }
+
+  /// Strip triggers from the last chunk of a run of text, e.g. one followed by a telnet command,
+  /// passing any partial trigger at its end on as text.
+  pub fn feed_end(&mut self, data: &[u8]) -> Vec<MspToken> {
+    let mut tokens = self.feed(data);
+    if self.is_pending() {
+      let pending = self.flush();
+      match tokens.last_mut() {
+        Some(MspToken::Text(text)) => {
+          let mut joined = BytesMut::with_capacity(text.len() + pending.len());
+          joined.put(&text[..]);
+          joined.put(pending);
+          *text = joined.freeze();
+        }
+        _ => tokens.push(MspToken::Text(pending)),
+      }
+    }
+    tokens
+  }
+
+  /// Take any buffered partial trigger as text, e.g. when MSP is disabled.
+  pub fn flush(&mut self) -> Bytes {
+    Bytes::from(core::mem::take(&mut self.pending))
+  }
+
+  fn push(&mut self, b: u8, text: &mut BytesMut, tokens: &mut Vec<MspToken>) {
+    if self.pending.is_empty() && b != b'!' {
+      text.put_u8(b);
+      return;
+    }
+    self.pending.push(b);
+    let len = self.pending.len();
+    if len <= SOUND.len() {
+      if !SOUND.starts_with(&self.pending) && !MUSIC.starts_with(&self.pending) {
+        // Not a trigger after all. The bytes after the first may still start one.
+        let pending = core::mem::take(&mut self.pending);
+        text.put_u8(pending[0]);
+        for &b in &pending[1..] {
+          self.push(b, text, tokens);
+        }
+      }
+      return;
+    }
+    match b {
+      b')' => {
+        let pending = core::mem::take(&mut self.pending);
+        match MspTrigger::parse(&String::from_utf8_lossy(&pending)) {
+          Ok(trigger) => {
+            if !text.is_empty() {
+              tokens.push(MspToken::Text(text.split().freeze()));
+            }
+            tokens.push(MspToken::Trigger(trigger));
+          }
+          Err(_) => text.put(&pending[..]),
+        }
+      }
+      b'\r' | b'\n' => text.put(&core::mem::take(&mut self.pending)[..]),
+      _ if len > MAX_TRIGGER_LEN => text.put(&core::mem::take(&mut self.pending)[..]),
+      _ => {}
+    }
+  }
+}
+
+#[cfg(test)]
+mod test_msp {
+  use super::*;
+  use alloc::vec;
+
+  #[test]
+  fn test_parse() {
+    let trigger =
+      MspTrigger::parse("!!SOUND(thunder.wav V=80 L=-1 P=60 T=weather U=http://a.b/s/)").unwrap();
+    assert_eq!(trigger.kind, MspKind::Sound);
+    assert_eq!(trigger.file, "thunder.wav");
+    assert_eq!(trigger.volume, Some(80));
+    assert_eq!(trigger.repeats, Some(-1));
+    assert_eq!(trigger.priority, Some(60));
+    assert_eq!(trigger.sound_type.as_deref(), Some("weather"));
+    assert_eq!(trigger.url.as_deref(), Some("http://a.b/s/"));
+    assert_eq!(
+      trigger.to_string(),
+      "!!SOUND(thunder.wav V=80 L=-1 P=60 T=weather U=http://a.b/s/)"
+    );
+
+    let music = MspTrigger::parse("!!MUSIC(Off C=0)").unwrap();
+    assert!(music.is_off());
+    assert_eq!(music.continue_music, Some(false));
+
+    assert_eq!(
+      MspTrigger::parse("!!SOUND(a.wav V=101)"),
+      Err(ParseError::InvalidParameter('V'))
+    );
+    assert_eq!(MspTrigger::parse("!!SOUND()"), Err(ParseError::MissingFile));
+    assert_eq!(MspTrigger::parse("!!SOUND(a"), Err(ParseError::NotATrigger));
+  }
+
+  #[test]
+  fn test_filter() {
+    let mut filter = MspFilter::new();
+    let mut tokens = Vec::new();
+    for chunk in [
+      &b"Boom!!!!SO"[..],
+      b"UND(boom.wav",
+      b" V=50)!\r\n!!MUSIC(x X=1)",
+    ] {
+      tokens.extend(filter.feed(chunk));
+    }
+    let mut boom = MspTrigger::new(MspKind::Sound, "boom.wav");
+    boom.volume = Some(50);
+    assert_eq!(
+      tokens,
+      vec![
+        MspToken::Text(Bytes::from_static(b"Boom!!")),
+        MspToken::Trigger(boom),
+        MspToken::Text(Bytes::from_static(b"!\r\n!!MUSIC(x X=1)")),
+      ]
+    );
+    assert!(!filter.is_pending());
+
+    assert_eq!(filter.feed(b"!!MUS"), vec![]);
+    assert!(filter.is_pending());
+    assert_eq!(&filter.flush()[..], b"!!MUS");
+
+    assert_eq!(
+      filter.feed_end(b"Are you sure!"),
+      vec![MspToken::Text(Bytes::from_static(b"Are you sure!"))]
+    );
+    assert_eq!(
+      filter.feed(b"Really?!"),
+      vec![MspToken::Text(Bytes::from_static(b"Really?"))]
+    );
+    assert_eq!(
+      filter.feed_end(b""),
+      vec![MspToken::Text(Bytes::from_static(b"!"))]
+    );
+    assert!(!filter.is_pending());
+  }
+}
@@ -0,0 +1,322 @@
+//! MSSP (Mud Server Status Protocol) codec.
+//!
+//! MSSP subnegotiations (`IAC SB MSSP ... IAC SE`) carry a list of variables, each a `VAR` byte
+//! followed by a name and one or more `VAL` bytes each followed by a value. A variable may be
+//! given more than once, and may have more than one value (e.g. several `PORT`s).
+
+use alloc::{
+  borrow::ToOwned,
+  string::{String, ToString},
+  vec,
+  vec::Vec,
+};
+use core::iter::FromIterator;
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+/// Precedes a variable name.
+pub const VAR: u8 = 1;
+/// Precedes a variable value.
+pub const VAL: u8 = 2;
+
+/// Names of the standard MSSP variables.
+pub mod var {
+  pub const NAME: &str = "NAME";
+  pub const PLAYERS: &str = "PLAYERS";
+  pub const UPTIME: &str = "UPTIME";
+  pub const CRAWL_DELAY: &str = "CRAWL DELAY";
+  pub const HOSTNAME: &str = "HOSTNAME";
+  pub const PORT: &str = "PORT";
+  pub const CODEBASE: &str = "CODEBASE";
+  pub const CONTACT: &str = "CONTACT";
+  pub const CREATED: &str = "CREATED";
+  pub const DISCORD: &str = "DISCORD";
+  pub const ICON: &str = "ICON";
+  pub const IP: &str = "IP";
+  pub const IPV6: &str = "IPV6";
+  pub const LANGUAGE: &str = "LANGUAGE";
+  pub const LOCATION: &str = "LOCATION";
+  pub const MINIMUM_AGE: &str = "MINIMUM AGE";
+  pub const WEBSITE: &str = "WEBSITE";
+  pub const FAMILY: &str = "FAMILY";
+  pub const GENRE: &str = "GENRE";
+  pub const GAMEPLAY: &str = "GAMEPLAY";
+  pub const STATUS: &str = "STATUS";
+  pub const GAMESYSTEM: &str = "GAMESYSTEM";
+  pub const INTERMUD: &str = "INTERMUD";
+  pub const SUBGENRE: &str = "SUBGENRE";
+  pub const SSL: &str = "SSL";
+}
+
+/// An ordered multimap of MSSP variables to their values.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct Mssp {
+  vars: Vec<(String, Vec<String>)>,
+}
+
+impl Mssp {
+  #[must_use]
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Decode the content of an MSSP subnegotiation.
+  ///
+  /// Decoding is lenient: data before the first `VAR` is ignored, and a variable given more
+  /// than once has its values merged. Names and values that are not valid UTF-8 are decoded
+  /// lossily.
+  #[must_use]
+  pub fn decode(data: &[u8]) -> Self {
+    let mut mssp = Self::new();
+    let markers = data.iter().filter(|b| **b == VAR || **b == VAL);
+    // Skip anything before the first marker.
+    let fields = data.split(|b| *b == VAR || *b == VAL).skip(1);
+
+    let mut current: Option<String> = None;
+    for (marker, field) in markers.zip(fields) {
+      let field = String::from_utf8_lossy(field).into_owned();
+      match (*marker, &current) {
+        (VAR, _) => current = Some(field),
+        (_, Some(name)) => mssp.add(name, field),
+        (_, None) => {}
+      }
+    }
+    mssp
+  }
+
+  /// Encode the variables as the content of an MSSP subnegotiation.
+  #[must_use]
+  pub fn encode(&self) -> Bytes {
+    let mut buf = BytesMut::new();
+    for (name, values) in &self.vars {
+      buf.put_u8(VAR);
+      buf.put(name.as_bytes());
+      for value in values {
+        buf.put_u8(VAL);
+        buf.put(value.as_bytes());
+      }
+    }
+    buf.freeze()
+  }
+
+  /// Add a value to a variable, keeping any existing values.
+  pub fn add<V: Into<String>>(&mut self, name: &str, value: V) {
+    match self.vars.iter_mut().find(|(n, _)| n == name) {
+      Some((_, values)) => values.push(value.into()),
+      None => self.vars.push((name.to_owned(), vec![value.into()])),
+    }
+  }
+
+  /// Set the value of a variable, replacing any existing values.
+  pub fn set<V: Into<String>>(&mut self, name: &str, value: V) {
+    match self.vars.iter_mut().find(|(n, _)| n == name) {
+      Some((_, values)) => *values = vec![value.into()],
+      None => self.vars.push((name.to_owned(), vec![value.into()])),
+    }
+  }
+
+  /// Get the first value of a variable.
+  #[must_use]
+  pub fn get(&self, name: &str) -> Option<&str> {
+    self.get_all(name).first().map(String::as_str)
+  }
+
+  /// Get all values of a variable.
+  #[must_use]
+  pub fn get_all(&self, name: &str) -> &[String] {
+    self
+      .vars
+      .iter()
+      .find(|(n, _)| n == name)
+      .map_or(&[], |(_, values)| values.as_slice())
+  }
+
+  /// Iterate over the variables and their values, in the order they were first given.
+  pub fn iter(&self) -> impl Iterator<Item = (&str, &[String])> {
+    self
+      .vars
+      .iter()
+      .map(|(name, values)| (name.as_str(), values.as_slice()))
+  }
+
+  /// Get a boolean variable such as `ANSI` or `GMCP`, given as `1` or `0`.
+  #[must_use]
+  pub fn flag(&self, name: &str) -> Option<bool> {
+    match self.get(name)?.trim() {
+      "1" => Some(true),
+      "0" => Some(false),
+      _ => None,
+    }
+  }
+
+  /// Set a boolean variable such as `ANSI` or `GMCP`.
+  pub fn set_flag(&mut self, name: &str, value: bool) {
+    self.set(name, if value { "1" } else { "0" });
+  }
+
+  fn number<T: core::str::FromStr>(&self, name: &str) -> Option<T> {
+    self.get(name)?.trim().parse().ok()
+  }
+
+  /// The `NAME` of the MUD.
+  #[must_use]
+  pub fn name(&self) -> Option<&str> {
+    self.get(var::NAME)
+  }
+
+  /// The current number of `PLAYERS`.
+  #[must_use]
+  pub fn players(&self) -> Option<u32> {
+    self.number(var::PLAYERS)
+  }
+
+  /// The `UPTIME`, as the unix time the MUD was started.
+  #[must_use]
+  pub fn uptime(&self) -> Option<u64> {
+    self.number(var::UPTIME)
+  }
+
+  /// The `CRAWL DELAY` in hours, or `-1` for the crawler's default.
+  #[must_use]
+  pub fn crawl_delay(&self) -> Option<i32> {
+    self.number(var::CRAWL_DELAY)
+  }
+
+  /// The `HOSTNAME` of the MUD.
+  #[must_use]
+  pub fn hostname(&self) -> Option<&str> {
+    self.get(var::HOSTNAME)
+  }
+
+  /// All `PORT`s of the MUD. Values that are not valid port numbers are skipped.
+  #[must_use]
+  pub fn ports(&self) -> Vec<u16> {
+    self
+      .get_all(var::PORT)
+      .iter()
+      .filter_map(|port| port.trim().parse().ok())
+      .collect()
+  }
+
+  /// The `CODEBASE` of the MUD.
+  #[must_use]
+  pub fn codebase(&self) -> Option<&str> {
+    self.get(var::CODEBASE)
+  }
+
+  /// The `CONTACT` email address of the MUD.
+  #[must_use]
+  pub fn contact(&self) -> Option<&str> {
+    self.get(var::CONTACT)
+  }
+
+  /// The year the MUD was `CREATED`.
+  #[must_use]
+  pub fn created(&self) -> Option<u32> {
+    self.number(var::CREATED)
+  }
+
+  /// The `LANGUAGE` of the MUD.
+  #[must_use]
+  pub fn language(&self) -> Option<&str> {
+    self.get(var::LANGUAGE)
+  }
+
+  /// The `LOCATION` of the MUD's server.
+  #[must_use]
+  pub fn location(&self) -> Option<&str> {
+    self.get(var::LOCATION)
+  }
+
+  /// The `MINIMUM AGE` to play the MUD.
+  #[must_use]
+  pub fn minimum_age(&self) -> Option<u32> {
+    self.number(var::MINIMUM_AGE)
+  }
+
+  /// The `WEBSITE` of the MUD.
+  #[must_use]
+  pub fn website(&self) -> Option<&str> {
+    self.get(var::WEBSITE)
+  }
+
+  /// The `SSL` port of the MUD, if it supports SSL.
+  #[must_use]
+  pub fn ssl(&self) -> Option<u16> {
+    self.number::<u16>(var::SSL).filter(|port| *port > 1)
+  }
+}
+
+impl<N: AsRef<str>, V: ToString> FromIterator<(N, V)> for Mssp {
+  fn from_iter<I: IntoIterator<Item = (N, V)>>(iter: I) -> Self {
+    let mut mssp = Self::new();
+    for (name, value) in iter {
+      mssp.add(name.as_ref(), value.to_string());
+    }
+    mssp
+  }
+}
+
+#[cfg(test)]
+mod test_mssp {
+  use super::*;
+
+  #[test]
+  fn test_decode() {
+    let data = [
+      &b"junk"[..],
+      &[VAR],
+      b"NAME",
+      &[VAL],
+      b"Blight MUD",
+      &[VAR],
+      b"PLAYERS",
+      &[VAL],
+      b"52",
+      &[VAR],
+      b"PORT",
+      &[VAL],
+      b"4000",
+      &[VAL],
+      b"4001",
+      &[VAR],
+      b"PORT",
+      &[VAL],
+      b"not a port",
+      &[VAR],
+      b"ANSI",
+      &[VAL],
+      b"1",
+    ]
+    .concat();
+    let mssp = Mssp::decode(&data);
+    assert_eq!(mssp.name(), Some("Blight MUD"));
+    assert_eq!(mssp.players(), Some(52));
+    assert_eq!(mssp.ports(), vec![4000, 4001]);
+    assert_eq!(mssp.get_all(var::PORT).len(), 3);
+    assert_eq!(mssp.flag("ANSI"), Some(true));
+    assert_eq!(mssp.uptime(), None);
+    assert_eq!(
+      mssp.iter().map(|(name, _)| name).collect::<Vec<_>>(),
+      vec!["NAME", "PLAYERS", "PORT", "ANSI"]
+    );
+  }
+
+  #[test]
+  fn test_roundtrip() {
+    let mut mssp: Mssp = vec![(var::NAME, "Test"), (var::PORT, "23"), (var::PORT, "4000")]
+      .into_iter()
+      .collect();
+    mssp.set(var::UPTIME, "1700000000");
+    mssp.set_flag("GMCP", false);
+    let encoded = mssp.encode();
+    assert_eq!(
+      &encoded[..13],
+      &[&[VAR][..], b"NAME", &[VAL], b"Test", &[VAR], b"PO"].concat()[..]
+    );
+    let decoded = Mssp::decode(&encoded);
+    assert_eq!(decoded, mssp);
+    assert_eq!(decoded.uptime(), Some(1_700_000_000));
+    assert_eq!(decoded.flag("GMCP"), Some(false));
+  }
+}
@@ -0,0 +1,645 @@
+//! MXP (MUD eXtension Protocol) tokenizer.
+//!
+//! Once MXP is enabled, received text may contain HTML-like tags (`<SEND href="look">look</SEND>`)
+//! and entities (`&lt;`), along with `ESC [ n z` sequences switching the line mode that decides
+//! which tags are allowed:
+//!
+//! * Open lines only allow formatting tags, and elements defined with the `OPEN` flag.
+//! * Secure lines allow all tags, including `<!ELEMENT>` and `<!ENTITY>` definitions.
+//! * Locked lines are plain text.
+//!
+//! `MxpTokenizer` is fed the data of `TelnetEvents::DataReceive` events, and keeps its state
+//! across them, so tags, entities and mode switches may be split between receives. Tags that are
+//! not allowed in the current mode are dropped.
+
+use alloc::{
+  borrow::ToOwned,
+  string::{String, ToString},
+  vec::Vec,
+};
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+/// The escape byte starting a line mode switch.
+pub const ESC: u8 = 0x1B;
+
+/// The longest tag the tokenizer buffers before giving up and treating it as text.
+pub const MAX_TAG_LEN: usize = 4096;
+
+/// The longest entity name the tokenizer buffers before treating it as text.
+pub const MAX_ENTITY_LEN: usize = 64;
+
+/// Tags allowed in open mode.
+pub const OPEN_TAGS: &[&str] = &[
+  "B",
+  "BOLD",
+  "STRONG",
+  "I",
+  "ITALIC",
+  "EM",
+  "U",
+  "UNDERLINE",
+  "S",
+  "STRIKEOUT",
+  "C",
+  "COLOR",
+  "H",
+  "HIGH",
+  "FONT",
+  "NOBR",
+  "P",
+  "BR",
+  "SBR",
+];
+
+/// An MXP line mode.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum LineMode {
+  /// Only open tags are allowed.
+  Open,
+  /// All tags are allowed.
+  Secure,
+  /// No tags are allowed.
+  Locked,
+}
+
+/// An attribute of a tag, either `name=value` or a bare (possibly quoted) value.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct MxpAttribute {
+  pub name: Option<String>,
+  pub value: String,
+}
+
+/// A tag or definition, e.g. `<SEND href="look" hint="Look around">`.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct MxpTag {
+  /// The tag name, or the name being defined for `<!ELEMENT>` and `<!ENTITY>`.
+  pub name: String,
+  pub attributes: Vec<MxpAttribute>,
+}
+
+impl MxpTag {
+  /// Get the value of a named attribute, compared case-insensitively.
+  #[must_use]
+  pub fn get(&self, name: &str) -> Option<&str> {
+    self
+      .attributes
+      .iter()
+      .find(|attr| {
+        attr
+          .name
+          .as_deref()
+          .is_some_and(|n| n.eq_ignore_ascii_case(name))
+      })
+      .map(|attr| attr.value.as_str())
+  }
+
+  /// Get the value of the `index`th attribute without a name.
+  #[must_use]
+  pub fn positional(&self, index: usize) -> Option<&str> {
+    self
+      .attributes
+      .iter()
+      .filter(|attr| attr.name.is_none())
+      .nth(index)
+      .map(|attr| attr.value.as_str())
+  }
+
+  /// Whether a bare flag such as `OPEN` or `DELETE` is given, compared case-insensitively.
+  #[must_use]
+  pub fn has_flag(&self, flag: &str) -> bool {
+    self
+      .attributes
+      .iter()
+      .any(|attr| attr.name.is_none() && attr.value.eq_ignore_ascii_case(flag))
+  }
+
+  /// Parse the content of a tag, between `<` and `>`.
+  fn parse(content: &str) -> Option<Self> {
+    let mut words = split_words(content.trim().trim_end_matches('/')).into_iter();
+    let name = words.next()?.value;
+    Some(Self {
+      name,
+      attributes: words.collect(),
+    })
+  }
+}
+
+/// A token of MXP text.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum MxpToken {
+  /// Plain text, including newlines and any ANSI escape sequences.
+  Text(Bytes),
+  /// An opening tag, e.g. `<SEND href="look">`.
+  StartTag(MxpTag),
+  /// A closing tag, e.g. `</SEND>`, with its name.
+  EndTag(String),
+  /// An entity reference, e.g. `&lt;`, with its name. See `MxpTokenizer::resolve_entity`.
+  Entity(String),
+  /// An `<!ELEMENT>` definition.
+  ElementDefinition(MxpTag),
+  /// An `<!ENTITY>` definition.
+  EntityDefinition(MxpTag),
+  /// The line mode changed.
+  Mode(LineMode),
+  /// `ESC [ 3 z`: all open tags should be closed, and the mode is reset to open.
+  Reset,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum State {
+  Text,
+  Escape,
+  Tag { quote: Option<u8> },
+  Comment,
+  Entity,
+}
+
+/// Tokenizes MXP text, keeping the line mode and any partial token between calls to `feed`.
+#[derive(Clone, Debug)]
+pub struct MxpTokenizer {
+  state: State,
+  pending: Vec<u8>,
+  default_mode: LineMode,
+  line_mode: Option<LineMode>,
+  temp_secure: bool,
+  open_elements: Vec<String>,
+  entities: Vec<(String, String)>,
+}
+
+impl Default for MxpTokenizer {
+  fn default() -> Self {
+    Self {
+      state: State::Text,
+      pending: Vec::new(),
+      default_mode: LineMode::Open,
+      line_mode: None,
+      temp_secure: false,
+      open_elements: Vec::new(),
+      entities: Vec::new(),
+    }
+  }
+}
+
+impl MxpTokenizer {
+  #[must_use]
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// The current line mode.
+  #[must_use]
+  pub fn mode(&self) -> LineMode {
+    self.line_mode.unwrap_or(self.default_mode)
+  }
+
+  /// Reset all state, as when MXP is disabled.
+  pub fn reset(&mut self) {
+    *self = Self::default();
+  }
+
+  /// Resolve an entity to its text, from the standard entities and any `<!ENTITY>` definitions.
+  #[must_use]
+  pub fn resolve_entity(&self, name: &str) -> Option<&str> {
+    match name {
+      "lt" => return Some("<"),
+      "gt" => return Some(">"),
+      "amp" => return Some("&"),
+      "quot" => return Some("\""),
+      "apos" => return Some("'"),
+      "nbsp" => return Some("\u{a0}"),
+      _ => {}
+    }
+    self
+      .entities
+      .iter()
+      .find(|(n, _)| n.eq_ignore_ascii_case(name))
+      .map(|(_, value)| value.as_str())
+  }
+
+  /// Tokenize the next chunk of received text.
+  pub fn feed(&mut self, data: &[u8]) -> Vec<MxpToken> {
+    let mut tokens = Vec::new();
+    let mut text = BytesMut::new();
+
+    for &b in data {
+      match self.state {
+        State::Text => match b {
+          ESC => {
+            self.pending.push(b);
+            self.state = State::Escape;
+          }
+          b'<' if self.tag_mode() != LineMode::Locked => {
+            self.state = State::Tag { quote: None };
+          }
+          b'&' if self.mode() != LineMode::Locked => self.state = State::Entity,
+          b'\n' => {
+            text.put_u8(b);
+            self.end_line();
+          }
+          _ => text.put_u8(b),
+        },
+        State::Escape => {
+          self.pending.push(b);
+          match (self.pending.len(), b) {
+            (2, b'[') => {}
+            (3.., b'0'..=b'9') if self.pending.len() < 6 => {}
+            (4.., b'z') => {
+              let token = self.mode_switch();
+              emit(&mut text, &mut tokens, token);
+              self.pending.clear();
+              self.state = State::Text;
+            }
+            _ => {
+              // Some other escape sequence, e.g. ANSI colors.
+              text.put(&self.pending[..]);
+              self.pending.clear();
+              self.state = State::Text;
+            }
+          }
+        }
+        State::Tag { quote } => {
+          match (quote, b) {
+            (None, b'>') => {
+              let token = self.end_tag();
+              emit(&mut text, &mut tokens, token);
+              continue;
+            }
+            (None, b'"' | b'\'') => self.state = State::Tag { quote: Some(b) },
+            (Some(q), _) if q == b => self.state = State::Tag { quote: None },
+            _ => {}
+          }
+          self.pending.push(b);
+          if self.pending == b"!--" {
+            self.state = State::Comment;
+          } else if self.pending.len() > MAX_TAG_LEN {
+            text.put_u8(b'<');
+            text.put(&self.pending[..]);
+            self.pending.clear();
+            self.state = State::Text;
+          }
+        }
+        State::Comment => {
+          // Comments are dropped, so only the bytes that may end one are kept.
+          if self.pending.len() == 3 {
+            self.pending.remove(0);
+          }
+          self.pending.push(b);
+          if self.pending == b"-->" {
+            self.pending.clear();
+            self.state = State::Text;
+          }
+        }
+        State::Entity => match b {
+          b';' => {
+            let name = String::from_utf8_lossy(&self.pending).into_owned();
+            emit(&mut text, &mut tokens, Some(MxpToken::Entity(name)));
+            self.pending.clear();
+            self.state = State::Text;
+          }
+          b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'_' | b'#'
+            if self.pending.len() < MAX_ENTITY_LEN =>
+          {
+            self.pending.push(b);
+          }
+          _ => {
+            // Not an entity after all, so the `&` and anything after it is text.
+            text.put_u8(b'&');
+            text.put(&self.pending[..]);
+            self.pending.clear();
+            self.state = State::Text;
+            if b == b'\n' {
+              text.put_u8(b);
+              self.end_line();
+            } else if b == b'&' {
+              self.state = State::Entity;
+            } else {
+              text.put_u8(b);
+            }
+          }
+        },
+      }
+    }
+    flush_text(&mut text, &mut tokens);
+    tokens
+  }
+
+  /// The mode a tag starting now is processed in.
+  fn tag_mode(&self) -> LineMode {
+    if self.temp_secure {
+      LineMode::Secure
+    } else {
+      self.mode()
+    }
+  }
+
+  fn end_line(&mut self) {
+    self.line_mode = None;
+    self.temp_secure = false;
+  }
+
+  fn mode_switch(&mut self) -> Option<MxpToken> {
+    let digits = &self.pending[2..self.pending.len() - 1];
+    let code = digits
+      .iter()
+      .fold(0u32, |code, digit| code * 10 + u32::from(digit - b'0'));
+    let previous = self.mode();
+    match code {
+      0 => self.line_mode = Some(LineMode::Open),
+      1 => self.line_mode = Some(LineMode::Secure),
+      2 => self.line_mode = Some(LineMode::Locked),
+      3 => {
+        self.line_mode = None;
+        self.default_mode = LineMode::Open;
+        self.temp_secure = false;
+        return Some(MxpToken::Reset);
+      }
+      4 => self.temp_secure = true,
+      5 => {
+        self.default_mode = LineMode::Open;
+        self.line_mode = None;
+      }
+      6 => {
+        self.default_mode = LineMode::Secure;
+        self.line_mode = None;
+      }
+      7 => {
+        self.default_mode = LineMode::Locked;
+        self.line_mode = None;
+      }
+      // User defined line tags are not supported.
+      _ => {}
+    }
+    (self.mode() != previous).then(|| MxpToken::Mode(self.mode()))
+  }
+
+  fn end_tag(&mut self) -> Option<MxpToken> {
+    let secure = self.tag_mode() == LineMode::Secure;
+    self.temp_secure = false;
+    self.state = State::Text;
+    let content = String::from_utf8_lossy(&self.pending).into_owned();
+    self.pending.clear();
+
+    if let Some(name) = content.strip_prefix('/') {
+      let name = name.trim().to_owned();
+      return (secure || self.is_open(&name)).then_some(MxpToken::EndTag(name));
+    }
+    let tag = MxpTag::parse(&content)?;
+    let upper = tag.name.to_ascii_uppercase();
+    if upper == "!ELEMENT" || upper == "!EL" || upper == "!ENTITY" || upper == "!EN" {
+      if !secure {
+        return None;
+      }
+      let mut attributes = tag.attributes.into_iter();
+      let name = attributes.next()?.value;
+      let definition = MxpTag {
+        name,
+        attributes: attributes.collect(),
+      };
+      if upper.starts_with("!EL") {
+        self.define_element(&definition);
+        Some(MxpToken::ElementDefinition(definition))
+      } else {
+        self.define_entity(&definition);
+        Some(MxpToken::EntityDefinition(definition))
+      }
+    } else if secure || self.is_open(&tag.name) {
+      Some(MxpToken::StartTag(tag))
+    } else {
+      None
+    }
+  }
+
+  fn is_open(&self, name: &str) -> bool {
+    OPEN_TAGS.iter().any(|tag| tag.eq_ignore_ascii_case(name))
+      || self
+        .open_elements
+        .iter()
+        .any(|element| element.eq_ignore_ascii_case(name))
+  }
+
+  fn define_element(&mut self, definition: &MxpTag) {
+    self
+      .open_elements
+      .retain(|element| !element.eq_ignore_ascii_case(&definition.name));
+    if definition.has_flag("OPEN") && !definition.has_flag("DELETE") {
+      self.open_elements.push(definition.name.clone());
+    }
+  }
+
+  fn define_entity(&mut self, definition: &MxpTag) {
+    self
+      .entities
+      .retain(|(name, _)| !name.eq_ignore_ascii_case(&definition.name));
+    if definition.has_flag("DELETE") {
+      return;
+    }
+    if let Some(value) = definition.positional(0) {
+      self
+        .entities
+        .push((definition.name.clone(), value.to_string()));
+    }
+  }
+}
+
+/// Push a token after any text preceding it. Text around a dropped token is kept together.
+fn emit(text: &mut BytesMut, tokens: &mut Vec<MxpToken>, token: Option<MxpToken>) {
+  if let Some(token) = token {
+    flush_text(text, tokens);
+    tokens.push(token);
+  }
+}
+
+fn flush_text(text: &mut BytesMut, tokens: &mut Vec<MxpToken>) {
+  if !text.is_empty() {
+    tokens.push(MxpToken::Text(text.split().freeze()));
+  }
+}
+
+/// Split tag content into whitespace separated words, keeping quoted values together.
+fn split_words(content: &str) -> Vec<MxpAttribute> {
+  let mut words = Vec::new();
+  let mut chars = content.chars().peekable();
+  loop {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+    if chars.peek().is_none() {
+      return words;
+    }
+    let mut name = None;
+    let mut value = String::new();
+    while let Some(c) = chars.next() {
+      match c {
+        c if c.is_whitespace() => break,
+        '"' | '\'' => {
+          for q in chars.by_ref() {
+            if q == c {
+              break;
+            }
+            value.push(q);
+          }
+        }
+        '=' if name.is_none() => name = Some(core::mem::take(&mut value)),
+        c => value.push(c),
+      }
+    }
+    words.push(MxpAttribute { name, value });
+  }
+}
+
+#[cfg(test)]
+mod test_mxp {
+  use super::*;
+  use alloc::vec;
+
+  fn text(s: &str) -> MxpToken {
+    MxpToken::Text(Bytes::copy_from_slice(s.as_bytes()))
+  }
+
+  #[test]
+  fn test_open_mode() {
+    let mut mxp = MxpTokenizer::new();
+    let tokens = mxp.feed(b"<B>bold</B> &lt;<SEND href=look>x</SEND>\x1b[1;31m");
+    assert_eq!(
+      tokens,
+      vec![
+        MxpToken::StartTag(MxpTag {
+          name: "B".to_string(),
+          attributes: vec![]
+        }),
+        text("bold"),
+        MxpToken::EndTag("B".to_string()),
+        text(" "),
+        MxpToken::Entity("lt".to_string()),
+        text("x\x1b[1;31m"),
+      ]
+    );
+    assert_eq!(mxp.resolve_entity("lt"), Some("<"));
+  }
+
+  #[test]
+  fn test_secure_line() {
+    let mut mxp = MxpTokenizer::new();
+    let tokens = mxp.feed(b"\x1b[1z<SEND href=\"look at\" 'Look'>x</SEND>\n<SEND>");
+    let send = MxpTag {
+      name: "SEND".to_string(),
+      attributes: vec![
+        MxpAttribute {
+          name: Some("href".to_string()),
+          value: "look at".to_string(),
+        },
+        MxpAttribute {
+          name: None,
+          value: "Look".to_string(),
+        },
+      ],
+    };
+    assert_eq!(send.get("HREF"), Some("look at"));
+    assert_eq!(send.positional(0), Some("Look"));
+    assert_eq!(
+      tokens,
+      vec![
+        MxpToken::Mode(LineMode::Secure),
+        MxpToken::StartTag(send),
+        text("x"),
+        MxpToken::EndTag("SEND".to_string()),
+        text("\n"),
+      ]
+    );
+    // The secure line ended with the newline.
+    assert_eq!(mxp.mode(), LineMode::Open);
+  }
+
+  #[test]
+  fn test_split_chunks() {
+    let mut mxp = MxpTokenizer::new();
+    let mut tokens = Vec::new();
+    for chunk in [
+      &b"\x1b["[..],
+      b"6",
+      b"z<CO",
+      b"LOR red>a&am",
+      b"p;b</COLOR><!-",
+      b"- <B> -->c",
+    ] {
+      tokens.extend(mxp.feed(chunk));
+    }
+    assert_eq!(
+      tokens,
+      vec![
+        MxpToken::Mode(LineMode::Secure),
+        MxpToken::StartTag(MxpTag {
+          name: "COLOR".to_string(),
+          attributes: vec![MxpAttribute {
+            name: None,
+            value: "red".to_string()
+          }],
+        }),
+        text("a"),
+        MxpToken::Entity("amp".to_string()),
+        text("b"),
+        MxpToken::EndTag("COLOR".to_string()),
+        text("c"),
+      ]
+    );
+    // A locked default mode survives newlines.
+    assert_eq!(
+      mxp.feed(b"\x1b[7z\n<B>&amp"),
+      vec![MxpToken::Mode(LineMode::Locked), text("\n<B>&amp")]
+    );
+    assert_eq!(mxp.feed(b"\x1b[3z"), vec![MxpToken::Reset]);
+    assert_eq!(mxp.mode(), LineMode::Open);
+  }
+
+  #[test]
+  fn test_long_comment() {
+    let mut mxp = MxpTokenizer::new();
+    assert_eq!(mxp.feed(b"a<!-- "), vec![text("a")]);
+    for _ in 0..MAX_TAG_LEN {
+      assert_eq!(mxp.feed(b"- > --"), vec![]);
+    }
+    assert!(mxp.pending.len() <= 3);
+    assert_eq!(mxp.feed(b"->b"), vec![text("b")]);
+  }
+
+  #[test]
+  fn test_definitions() {
+    let mut mxp = MxpTokenizer::new();
+    // Definitions are not allowed in open mode.
+    assert_eq!(mxp.feed(b"<!ENTITY hp '100'>"), vec![]);
+
+    let tokens =
+      mxp.feed(b"\x1b[4z<!ELEMENT RName '<FONT blue>' FLAG=RoomName OPEN>\x1b[4z<!EN hp 100>");
+    assert_eq!(tokens.len(), 2);
+    match &tokens[0] {
+      MxpToken::ElementDefinition(tag) => {
+        assert_eq!(tag.name, "RName");
+        assert_eq!(tag.positional(0), Some("<FONT blue>"));
+        assert_eq!(tag.get("flag"), Some("RoomName"));
+        assert!(tag.has_flag("open"));
+      }
+      token => panic!("unexpected token {:?}", token),
+    }
+    assert_eq!(mxp.resolve_entity("HP"), Some("100"));
+    assert_eq!(mxp.mode(), LineMode::Open);
+
+    // Elements defined as open are allowed in open mode.
+    assert_eq!(
+      mxp.feed(b"<RName>"),
+      vec![MxpToken::StartTag(MxpTag {
+        name: "RName".to_string(),
+        attributes: vec![]
+      })]
+    );
+    assert_eq!(
+      mxp.feed(b"\x1b[4z<!EN hp DELETE>"),
+      vec![MxpToken::EntityDefinition(MxpTag {
+        name: "hp".to_string(),
+        attributes: vec![MxpAttribute {
+          name: None,
+          value: "DELETE".to_string()
+        }],
+      })]
+    );
+    assert_eq!(mxp.resolve_entity("hp"), None);
+  }
+}
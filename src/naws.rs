@@ -0,0 +1,137 @@
+//! NAWS (Negotiate About Window Size, RFC 1073) codec.
+//!
+//! The client sends its window size as `IAC SB NAWS <width> <height> IAC SE`, where width and
+//! height are 16 bit big-endian values. Any byte of the dimensions equal to `IAC` (255) must be
+//! doubled on the wire, which `TelnetSubnegotiation::to_bytes` takes care of.
+
+use core::convert::TryFrom;
+use core::fmt;
+
+use bytes::Bytes;
+
+use crate::events::TelnetSubnegotiation;
+use crate::telnet::op_option::NAWS;
+use crate::Parser;
+
+/// An error decoding a NAWS subnegotiation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+  /// The subnegotiation is not for the NAWS option.
+  WrongOption(u8),
+  /// The subnegotiation does not contain exactly two 16 bit values.
+  InvalidLength(usize),
+}
+
+impl fmt::Display for DecodeError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      DecodeError::WrongOption(option) => {
+        write!(f, "subnegotiation for option {option} is not NAWS")
+      }
+      DecodeError::InvalidLength(len) => write!(f, "NAWS data has invalid length {len}"),
+    }
+  }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DecodeError {}
+
+/// A window size in characters. A dimension of 0 means it is unknown.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash)]
+pub struct WindowSize {
+  pub width: u16,
+  pub height: u16,
+}
+
+impl WindowSize {
+  #[must_use]
+  pub fn new(width: u16, height: u16) -> Self {
+    Self { width, height }
+  }
+
+  /// Encode the window size as the (unescaped) content of a NAWS subnegotiation.
+  #[must_use]
+  pub fn encode(self) -> Bytes {
+    let [w1, w2] = self.width.to_be_bytes();
+    let [h1, h2] = self.height.to_be_bytes();
+    Bytes::copy_from_slice(&[w1, w2, h1, h2])
+  }
+
+  /// Decode the content of a NAWS subnegotiation.
+  ///
+  /// Both unescaped content, and content where `IAC` bytes are still doubled as they were on the
+  /// wire, are accepted.
+  ///
+  /// # Errors
+  ///
+  /// Returns `DecodeError::InvalidLength` if the data does not hold exactly two 16 bit values.
+  pub fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+    let unescaped;
+    let data = if data.len() == 4 {
+      data
+    } else {
+      unescaped = Parser::unescape_iac(Bytes::copy_from_slice(data));
+      &unescaped[..]
+    };
+    match data {
+      [w1, w2, h1, h2] => Ok(Self::new(
+        u16::from_be_bytes([*w1, *w2]),
+        u16::from_be_bytes([*h1, *h2]),
+      )),
+      _ => Err(DecodeError::InvalidLength(data.len())),
+    }
+  }
+}
+
+impl TryFrom<&TelnetSubnegotiation> for WindowSize {
+  type Error = DecodeError;
+
+  fn try_from(sub: &TelnetSubnegotiation) -> Result<Self, Self::Error> {
+    if sub.option != NAWS {
+      return Err(DecodeError::WrongOption(sub.option));
+    }
+    Self::decode(&sub.buffer)
+  }
+}
+
+impl From<WindowSize> for TelnetSubnegotiation {
+  fn from(size: WindowSize) -> Self {
+    TelnetSubnegotiation::new(NAWS, size.encode())
+  }
+}
+
+#[cfg(test)]
+mod test_naws {
+  use super::*;
+  use crate::telnet::op_command::{IAC, SB, SE};
+
+  #[test]
+  fn test_escaping() {
+    let size = WindowSize::new(255, 0xFFFF);
+    assert_eq!(&size.encode()[..], &[0, 255, 255, 255]);
+    assert_eq!(
+      &TelnetSubnegotiation::from(size).to_bytes()[..],
+      &[IAC, SB, NAWS, 0, IAC, IAC, IAC, IAC, IAC, IAC, IAC, SE]
+    );
+  }
+
+  #[test]
+  fn test_decode() {
+    assert_eq!(
+      WindowSize::decode(&[0, 80, 0, 24]),
+      Ok(WindowSize::new(80, 24))
+    );
+    assert_eq!(
+      WindowSize::decode(&[0, 255, 255, 255]),
+      Ok(WindowSize::new(255, 0xFFFF))
+    );
+    assert_eq!(
+      WindowSize::decode(&[0, 255, 255, 1, 0]),
+      Ok(WindowSize::new(255, 256))
+    );
+    assert_eq!(
+      WindowSize::decode(&[0, 80, 0]),
+      Err(DecodeError::InvalidLength(3))
+    );
+  }
+}
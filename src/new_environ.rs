@@ -0,0 +1,482 @@
+//! NEW-ENVIRON (RFC 1572) codec, with the variables of MNES (Mud New-Environ Standard).
+//!
+//! The server asks for environment variables with `IAC SB NEW-ENVIRON SEND ... IAC SE`, listing
+//! the variables it wants (or none, for all of them). The client answers with `IS` followed by
+//! each variable, and may later send changes unprompted with `INFO`.
+//!
+//! Each variable is given as `VAR <name>` for well-known variables or `USERVAR <name>` for user
+//! defined ones, followed by `VALUE <value>` unless it is undefined. Any `VAR`, `VALUE`, `ESC` or
+//! `USERVAR` byte inside a name or value is quoted by preceding it with `ESC`.
+
+use alloc::{string::String, vec::Vec};
+use core::convert::TryFrom;
+use core::fmt;
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::events::TelnetSubnegotiation;
+use crate::telnet::op_option::NEWENVIRON;
+
+/// The variables sent by the client.
+pub const IS: u8 = 0;
+/// Asks the client for variables.
+pub const SEND: u8 = 1;
+/// Variables that changed, sent by the client unprompted.
+pub const INFO: u8 = 2;
+
+/// Precedes the name of a well-known variable.
+pub const VAR: u8 = 0;
+/// Precedes the value of a variable.
+pub const VALUE: u8 = 1;
+/// Quotes the following byte of a name or value.
+pub const ESC: u8 = 2;
+/// Precedes the name of a user defined variable.
+pub const USERVAR: u8 = 3;
+
+/// Names of the well-known variables defined by RFC 1572.
+pub mod var {
+  pub const USER: &str = "USER";
+  pub const JOB: &str = "JOB";
+  pub const ACCT: &str = "ACCT";
+  pub const PRINTER: &str = "PRINTER";
+  pub const SYSTEMTYPE: &str = "SYSTEMTYPE";
+  pub const DISPLAY: &str = "DISPLAY";
+}
+
+/// Names of the variables defined by MNES, sent as well-known (`VAR`) variables.
+pub mod mnes {
+  pub const CHARSET: &str = "CHARSET";
+  pub const CLIENT_NAME: &str = "CLIENT_NAME";
+  pub const CLIENT_VERSION: &str = "CLIENT_VERSION";
+  pub const MTTS: &str = "MTTS";
+  pub const TERMINAL_TYPE: &str = "TERMINAL_TYPE";
+  pub const IPADDRESS: &str = "IPADDRESS";
+}
+
+/// An error decoding a NEW-ENVIRON subnegotiation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+  /// The subnegotiation is not for the NEW-ENVIRON option.
+  WrongOption(u8),
+  /// The subnegotiation is empty.
+  Empty,
+  /// The subnegotiation is not `IS`, `SEND` or `INFO`.
+  UnknownCommand(u8),
+  /// A byte other than `VAR` or `USERVAR` was found where a variable was expected.
+  UnexpectedByte {
+    /// The offset of the byte within the subnegotiation data.
+    offset: usize,
+    byte: u8,
+  },
+}
+
+impl fmt::Display for DecodeError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      DecodeError::WrongOption(option) => {
+        write!(f, "subnegotiation for option {option} is not NEW-ENVIRON")
+      }
+      DecodeError::Empty => write!(f, "NEW-ENVIRON subnegotiation is empty"),
+      DecodeError::UnknownCommand(command) => write!(f, "unknown NEW-ENVIRON command {command}"),
+      DecodeError::UnexpectedByte { offset, byte } => {
+        write!(f, "unexpected byte {byte} at offset {offset}")
+      }
+    }
+  }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DecodeError {}
+
+/// Whether a variable is well-known or user defined.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum VarKind {
+  /// A well-known variable, sent with `VAR`.
+  Var,
+  /// A user defined variable, sent with `USERVAR`.
+  UserVar,
+}
+
+impl VarKind {
+  fn marker(self) -> u8 {
+    match self {
+      VarKind::Var => VAR,
+      VarKind::UserVar => USERVAR,
+    }
+  }
+}
+
+/// A variable in an `IS` or `INFO` message.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct EnvVar {
+  pub kind: VarKind,
+  pub name: String,
+  /// The value, or `None` if the variable is undefined.
+  pub value: Option<String>,
+}
+
+impl EnvVar {
+  pub fn new<N: Into<String>>(kind: VarKind, name: N, value: Option<String>) -> Self {
+    Self {
+      kind,
+      name: name.into(),
+      value,
+    }
+  }
+}
+
+/// A variable asked for by a `SEND` message.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct EnvRequest {
+  pub kind: VarKind,
+  /// The name of the variable, or `None` for all variables of this kind.
+  pub name: Option<String>,
+}
+
+impl EnvRequest {
+  pub fn new<N: Into<String>>(kind: VarKind, name: Option<N>) -> Self {
+    Self {
+      kind,
+      name: name.map(Into::into),
+    }
+  }
+}
+
+/// A NEW-ENVIRON subnegotiation.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum EnvironMessage {
+  /// `IS`, the variables sent in answer to a `SEND`.
+  Is(Vec<EnvVar>),
+  /// `SEND`, asking for the listed variables, or all variables if the list is empty.
+  Send(Vec<EnvRequest>),
+  /// `INFO`, variables that changed since they were last sent.
+  Info(Vec<EnvVar>),
+}
+
+impl EnvironMessage {
+  /// Decode the content of a NEW-ENVIRON subnegotiation.
+  ///
+  /// Names and values that are not valid UTF-8 are decoded lossily.
+  ///
+  /// # Errors
+  ///
+  /// Returns a `DecodeError` if the command is missing or unknown, or a name is not preceded by
+  /// `VAR` or `USERVAR`.
+  pub fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+    let (command, rest) = data.split_first().ok_or(DecodeError::Empty)?;
+    let fields = split_fields(rest)?;
+    match *command {
+      SEND => Ok(EnvironMessage::Send(
+        fields
+          .into_iter()
+          .map(|field| EnvRequest {
+            kind: field.kind,
+            name: Some(field.name).filter(|name| !name.is_empty()),
+          })
+          .collect(),
+      )),
+      IS => Ok(EnvironMessage::Is(fields)),
+      INFO => Ok(EnvironMessage::Info(fields)),
+      command => Err(DecodeError::UnknownCommand(command)),
+    }
+  }
+
+  /// Encode the message as the content of a NEW-ENVIRON subnegotiation, quoting names and values.
+  #[must_use]
+  pub fn encode(&self) -> Bytes {
+    let mut buf = BytesMut::new();
+    match self {
+      EnvironMessage::Send(requests) => {
+        buf.put_u8(SEND);
+        for request in requests {
+          buf.put_u8(request.kind.marker());
+          if let Some(name) = &request.name {
+            put_quoted(&mut buf, name);
+          }
+        }
+      }
+      EnvironMessage::Is(vars) => {
+        buf.put_u8(IS);
+        put_vars(&mut buf, vars);
+      }
+      EnvironMessage::Info(vars) => {
+        buf.put_u8(INFO);
+        put_vars(&mut buf, vars);
+      }
+    }
+    buf.freeze()
+  }
+}
+
+impl TryFrom<&TelnetSubnegotiation> for EnvironMessage {
+  type Error = DecodeError;
+
+  fn try_from(sub: &TelnetSubnegotiation) -> Result<Self, Self::Error> {
+    if sub.option != NEWENVIRON {
+      return Err(DecodeError::WrongOption(sub.option));
+    }
+    Self::decode(&sub.buffer)
+  }
+}
+
+impl From<EnvironMessage> for TelnetSubnegotiation {
+  fn from(message: EnvironMessage) -> Self {
+    TelnetSubnegotiation::new(NEWENVIRON, message.encode())
+  }
+}
+
+fn put_vars(buf: &mut BytesMut, vars: &[EnvVar]) {
+  for var in vars {
+    buf.put_u8(var.kind.marker());
+    put_quoted(buf, &var.name);
+    if let Some(value) = &var.value {
+      buf.put_u8(VALUE);
+      put_quoted(buf, value);
+    }
+  }
+}
+
+fn put_quoted(buf: &mut BytesMut, text: &str) {
+  for &b in text.as_bytes() {
+    if matches!(b, VAR | VALUE | ESC | USERVAR) {
+      buf.put_u8(ESC);
+    }
+    buf.put_u8(b);
+  }
+}
+
+/// Split the variables following the command, unquoting names and values.
+fn split_fields(data: &[u8]) -> Result<Vec<EnvVar>, DecodeError> {
+  let mut vars = Vec::new();
+  let mut field = Vec::new();
+  // Whether the current field is a value rather than a name.
+  let mut in_value = false;
+  let mut bytes = data.iter().enumerate();
+
+  while let Some((index, &b)) = bytes.next() {
+    match b {
+      VAR | USERVAR => {
+        finish_field(&mut vars, &mut field, in_value);
+        in_value = false;
+        let kind = if b == VAR {
+          VarKind::Var
+        } else {
+          VarKind::UserVar
+        };
+        vars.push(EnvVar::new(kind, String::new(), None));
+      }
+      // Anything before the first variable is an error; the offset includes the command byte.
+      _ if vars.is_empty() => {
+        return Err(DecodeError::UnexpectedByte {
+          offset: index + 1,
+          byte: b,
+        });
+      }
+      VALUE => {
+        finish_field(&mut vars, &mut field, in_value);
+        in_value = true;
+      }
+      ESC => {
+        if let Some((_, &quoted)) = bytes.next() {
+          field.push(quoted);
+        }
+      }
+      _ => field.push(b),
+    }
+  }
+  finish_field(&mut vars, &mut field, in_value);
+  Ok(vars)
+}
+
+fn finish_field(vars: &mut [EnvVar], field: &mut Vec<u8>, in_value: bool) {
+  if let Some(var) = vars.last_mut() {
+    let text = String::from_utf8_lossy(field).into_owned();
+    if in_value {
+      var.value = Some(text);
+    } else {
+      var.name = text;
+    }
+  }
+  field.clear();
+}
+
+/// The client's variables, used to answer `SEND` requests.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct EnvironStore {
+  vars: Vec<EnvVar>,
+}
+
+impl EnvironStore {
+  #[must_use]
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Set a variable, replacing any previous value.
+  pub fn set<N, V>(&mut self, kind: VarKind, name: N, value: V)
+  where
+    N: Into<String>,
+    V: Into<String>,
+  {
+    let name = name.into();
+    let value = Some(value.into());
+    match self.find_mut(kind, &name) {
+      Some(var) => var.value = value,
+      None => self.vars.push(EnvVar::new(kind, name, value)),
+    }
+  }
+
+  /// Set a well-known (`VAR`) variable, such as one of the `mnes` variables.
+  pub fn set_var<N: Into<String>, V: Into<String>>(&mut self, name: N, value: V) {
+    self.set(VarKind::Var, name, value);
+  }
+
+  /// Set a user defined (`USERVAR`) variable.
+  pub fn set_user_var<N: Into<String>, V: Into<String>>(&mut self, name: N, value: V) {
+    self.set(VarKind::UserVar, name, value);
+  }
+
+  /// Remove a variable, returning its value.
+  pub fn remove(&mut self, kind: VarKind, name: &str) -> Option<String> {
+    let index = self
+      .vars
+      .iter()
+      .position(|var| var.kind == kind && var.name == name)?;
+    self.vars.remove(index).value
+  }
+
+  /// Get the value of a variable.
+  #[must_use]
+  pub fn get(&self, kind: VarKind, name: &str) -> Option<&str> {
+    self
+      .vars
+      .iter()
+      .find(|var| var.kind == kind && var.name == name)?
+      .value
+      .as_deref()
+  }
+
+  /// Iterate over the variables, in the order they were first set.
+  pub fn iter(&self) -> impl Iterator<Item = &EnvVar> {
+    self.vars.iter()
+  }
+
+  /// Answer a `SEND` for the given requests with an `IS` message.
+  ///
+  /// Requested variables that are not set are sent as undefined.
+  #[must_use]
+  pub fn respond(&self, requests: &[EnvRequest]) -> EnvironMessage {
+    if requests.is_empty() {
+      return EnvironMessage::Is(self.vars.clone());
+    }
+    let mut vars = Vec::new();
+    for request in requests {
+      match &request.name {
+        Some(name) => vars.push(
+          self
+            .vars
+            .iter()
+            .find(|var| var.kind == request.kind && var.name == *name)
+            .cloned()
+            .unwrap_or_else(|| EnvVar::new(request.kind, name.clone(), None)),
+        ),
+        None => vars.extend(
+          self
+            .vars
+            .iter()
+            .filter(|var| var.kind == request.kind)
+            .cloned(),
+        ),
+      }
+    }
+    EnvironMessage::Is(vars)
+  }
+
+  fn find_mut(&mut self, kind: VarKind, name: &str) -> Option<&mut EnvVar> {
+    self
+      .vars
+      .iter_mut()
+      .find(|var| var.kind == kind && var.name == name)
+  }
+}
+
+#[cfg(test)]
+mod test_new_environ {
+  use super::*;
+  use alloc::vec;
+
+  #[test]
+  fn test_quoting() {
+    let message = EnvironMessage::Is(vec![
+      EnvVar::new(VarKind::UserVar, "A\x01B", Some(String::from("\x02\x03"))),
+      EnvVar::new(VarKind::Var, var::USER, None),
+      EnvVar::new(VarKind::Var, var::DISPLAY, Some(String::new())),
+    ]);
+    let encoded = message.encode();
+    assert_eq!(
+      &encoded[..],
+      b"\x00\x03A\x02\x01B\x01\x02\x02\x02\x03\x00USER\x00DISPLAY\x01"
+    );
+    assert_eq!(EnvironMessage::decode(&encoded), Ok(message));
+  }
+
+  #[test]
+  fn test_send() {
+    let message = EnvironMessage::Send(vec![
+      EnvRequest::new(VarKind::Var, Some(mnes::CLIENT_NAME)),
+      EnvRequest::new::<String>(VarKind::UserVar, None),
+    ]);
+    assert_eq!(&message.encode()[..], b"\x01\x00CLIENT_NAME\x03");
+    assert_eq!(EnvironMessage::decode(&message.encode()), Ok(message));
+    assert_eq!(
+      EnvironMessage::decode(&[SEND]),
+      Ok(EnvironMessage::Send(vec![]))
+    );
+    assert_eq!(
+      EnvironMessage::decode(b"\x00USER"),
+      Err(DecodeError::UnexpectedByte {
+        offset: 1,
+        byte: b'U'
+      })
+    );
+    assert_eq!(
+      EnvironMessage::decode(&[7]),
+      Err(DecodeError::UnknownCommand(7))
+    );
+  }
+
+  #[test]
+  fn test_store() {
+    let mut store = EnvironStore::new();
+    store.set_var(mnes::CLIENT_NAME, "Blightmud");
+    store.set_var(mnes::CLIENT_VERSION, "5.3.1");
+    store.set_user_var("THEME", "dark");
+    store.set_var(mnes::CLIENT_VERSION, "5.4.0");
+    assert_eq!(store.get(VarKind::Var, mnes::CLIENT_VERSION), Some("5.4.0"));
+
+    assert_eq!(
+      store.respond(&[
+        EnvRequest::new(VarKind::Var, Some(mnes::CLIENT_NAME)),
+        EnvRequest::new(VarKind::Var, Some(mnes::IPADDRESS)),
+        EnvRequest::new::<String>(VarKind::UserVar, None),
+      ]),
+      EnvironMessage::Is(vec![
+        EnvVar::new(
+          VarKind::Var,
+          mnes::CLIENT_NAME,
+          Some(String::from("Blightmud"))
+        ),
+        EnvVar::new(VarKind::Var, mnes::IPADDRESS, None),
+        EnvVar::new(VarKind::UserVar, "THEME", Some(String::from("dark"))),
+      ])
+    );
+    assert_eq!(
+      store.respond(&[]),
+      EnvironMessage::Is(store.iter().cloned().collect())
+    );
+    assert_eq!(
+      store.remove(VarKind::UserVar, "THEME"),
+      Some(String::from("dark"))
+    );
+  }
+}
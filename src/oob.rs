@@ -0,0 +1,182 @@
+//! A common representation of GMCP and ATCP out-of-band messages.
+//!
+//! Both protocols send messages named `Package.Message` with an optional payload, so a client
+//! supporting both can handle them the same way, e.g.
+//!
+//! ```
+//! use core::convert::TryFrom;
+//! use libmudtelnet::events::TelnetSubnegotiation;
+//! use libmudtelnet::oob::{OobMessage, OobProtocol};
+//!
+//! fn handle(sub: &TelnetSubnegotiation) {
+//!   if let Ok(message) = OobMessage::try_from(sub) {
+//!     println!("{:?} {}", message.protocol, message.name());
+//!   }
+//! }
+//! ```
+
+use alloc::{format, string::String};
+use core::convert::TryFrom;
+use core::fmt;
+
+use crate::atcp::{self, AtcpMessage};
+use crate::events::TelnetSubnegotiation;
+use crate::gmcp::{self, GmcpMessage};
+use crate::telnet::op_option::{ATCP, GMCP};
+
+/// The protocol an out-of-band message is sent with.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum OobProtocol {
+  Gmcp,
+  Atcp,
+}
+
+impl OobProtocol {
+  /// The telnet option of the protocol.
+  #[must_use]
+  pub fn option(self) -> u8 {
+    match self {
+      OobProtocol::Gmcp => GMCP,
+      OobProtocol::Atcp => ATCP,
+    }
+  }
+}
+
+/// An error parsing an out-of-band message from a subnegotiation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+  /// The subnegotiation is neither for GMCP nor ATCP.
+  WrongOption(u8),
+  /// The message is not valid UTF-8.
+  InvalidUtf8,
+  /// The message name is empty, or has no package part.
+  InvalidName,
+}
+
+impl fmt::Display for ParseError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ParseError::WrongOption(option) => {
+        write!(f, "subnegotiation for option {option} is not GMCP or ATCP")
+      }
+      ParseError::InvalidUtf8 => write!(f, "message is not valid UTF-8"),
+      ParseError::InvalidName => write!(f, "message name is not of the form Package.Message"),
+    }
+  }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseError {}
+
+impl From<gmcp::ParseError> for ParseError {
+  fn from(err: gmcp::ParseError) -> Self {
+    match err {
+      gmcp::ParseError::WrongOption(option) => ParseError::WrongOption(option),
+      gmcp::ParseError::InvalidUtf8 => ParseError::InvalidUtf8,
+      gmcp::ParseError::InvalidName => ParseError::InvalidName,
+    }
+  }
+}
+
+impl From<atcp::ParseError> for ParseError {
+  fn from(err: atcp::ParseError) -> Self {
+    match err {
+      atcp::ParseError::WrongOption(option) => ParseError::WrongOption(option),
+      atcp::ParseError::InvalidUtf8 => ParseError::InvalidUtf8,
+      atcp::ParseError::InvalidName => ParseError::InvalidName,
+    }
+  }
+}
+
+/// A GMCP or ATCP message.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct OobMessage {
+  pub protocol: OobProtocol,
+  /// The package name, e.g. `Char`.
+  pub package: String,
+  /// The message name within the package, e.g. `Vitals`.
+  pub message: String,
+  /// The payload: JSON for GMCP, plain text for ATCP.
+  pub payload: Option<String>,
+}
+
+impl OobMessage {
+  /// The full message name, e.g. `Char.Vitals`.
+  #[must_use]
+  pub fn name(&self) -> String {
+    format!("{}.{}", self.package, self.message)
+  }
+}
+
+impl From<GmcpMessage> for OobMessage {
+  fn from(message: GmcpMessage) -> Self {
+    Self {
+      protocol: OobProtocol::Gmcp,
+      package: message.package,
+      message: message.message,
+      payload: message.payload,
+    }
+  }
+}
+
+impl From<AtcpMessage> for OobMessage {
+  fn from(message: AtcpMessage) -> Self {
+    Self {
+      protocol: OobProtocol::Atcp,
+      package: message.package,
+      message: message.message,
+      payload: message.body,
+    }
+  }
+}
+
+impl TryFrom<&TelnetSubnegotiation> for OobMessage {
+  type Error = ParseError;
+
+  fn try_from(sub: &TelnetSubnegotiation) -> Result<Self, Self::Error> {
+    match sub.option {
+      GMCP => Ok(GmcpMessage::try_from(sub)?.into()),
+      ATCP => Ok(AtcpMessage::try_from(sub)?.into()),
+      option => Err(ParseError::WrongOption(option)),
+    }
+  }
+}
+
+impl From<OobMessage> for TelnetSubnegotiation {
+  fn from(message: OobMessage) -> Self {
+    match message.protocol {
+      OobProtocol::Gmcp => {
+        GmcpMessage::new(message.package, message.message, message.payload).into()
+      }
+      OobProtocol::Atcp => {
+        AtcpMessage::new(message.package, message.message, message.payload).into()
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod test_oob {
+  use super::*;
+  use bytes::Bytes;
+
+  #[test]
+  fn test_routing() {
+    let gmcp = TelnetSubnegotiation::new(GMCP, Bytes::from_static(b"Room.Info {\"num\":1}"));
+    let atcp = TelnetSubnegotiation::new(ATCP, Bytes::from_static(b"Room.Num 1"));
+    let gmcp_msg = OobMessage::try_from(&gmcp).unwrap();
+    let atcp_msg = OobMessage::try_from(&atcp).unwrap();
+    assert_eq!(gmcp_msg.protocol, OobProtocol::Gmcp);
+    assert_eq!(atcp_msg.protocol, OobProtocol::Atcp);
+    assert_eq!(gmcp_msg.name(), "Room.Info");
+    assert_eq!(atcp_msg.payload.as_deref(), Some("1"));
+    assert_eq!(TelnetSubnegotiation::from(gmcp_msg), gmcp);
+    assert_eq!(TelnetSubnegotiation::from(atcp_msg), atcp);
+
+    let other = TelnetSubnegotiation::new(70, Bytes::new());
+    assert_eq!(
+      OobMessage::try_from(&other),
+      Err(ParseError::WrongOption(70))
+    );
+  }
+}
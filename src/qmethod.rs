@@ -0,0 +1,120 @@
+//! RFC 1143 "Q method" option negotiation.
+//!
+//! These transitions are shared by both sides of an option: for the local side "enable" means
+//! `WILL`/`DO` and "disable" means `WONT`/`DONT`, while for the remote side the roles of the
+//! commands are swapped. Following the Q method guarantees that we never answer a command that
+//! only confirms what we asked for, which prevents negotiation loops.
+
+use crate::compatibility::{QQueue, QState, SideState};
+
+/// The reply we must send in response to a received negotiation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Reply {
+  /// Send the positive command (`WILL` for local, `DO` for remote).
+  Agree,
+  /// Send the negative command (`WONT` for local, `DONT` for remote).
+  Refuse,
+}
+
+/// The outcome of processing a received negotiation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Response {
+  pub(crate) reply: Option<Reply>,
+  /// Whether the received command should be surfaced to the application.
+  pub(crate) notify: bool,
+}
+
+impl Response {
+  fn ignore() -> Self {
+    Self {
+      reply: None,
+      notify: false,
+    }
+  }
+
+  fn refuse() -> Self {
+    Self {
+      reply: Some(Reply::Refuse),
+      notify: false,
+    }
+  }
+
+  fn notify(reply: Option<Reply>) -> Self {
+    Self {
+      reply,
+      notify: true,
+    }
+  }
+}
+
+/// We want to enable the option. Returns the new state and whether the positive command
+/// must be sent.
+pub(crate) fn request_enable(side: SideState) -> (SideState, bool) {
+  use QQueue::{Empty, Opposite};
+  use QState::{No, WantNo, WantYes, Yes};
+
+  match (side.state, side.queue) {
+    (No, _) => (SideState::new(WantYes, Empty), true),
+    (WantNo, Empty) => (SideState::new(WantNo, Opposite), false),
+    (WantYes, Opposite) => (SideState::new(WantYes, Empty), false),
+    // Already enabled, or already queued/negotiating towards enabled.
+    (Yes | WantNo | WantYes, _) => (side, false),
+  }
+}
+
+/// We want to disable the option. Returns the new state and whether the negative command
+/// must be sent.
+pub(crate) fn request_disable(side: SideState) -> (SideState, bool) {
+  use QQueue::{Empty, Opposite};
+  use QState::{No, WantNo, WantYes, Yes};
+
+  match (side.state, side.queue) {
+    (Yes, _) => (SideState::new(WantNo, Empty), true),
+    (WantNo, Opposite) => (SideState::new(WantNo, Empty), false),
+    (WantYes, Empty) => (SideState::new(WantYes, Opposite), false),
+    // Already disabled, or already queued/negotiating towards disabled.
+    (No | WantNo | WantYes, _) => (side, false),
+  }
+}
+
+/// The other side sent the positive command (`DO` for local, `WILL` for remote).
+pub(crate) fn receive_enable(side: SideState, supported: bool) -> (SideState, Response) {
+  use QQueue::{Empty, Opposite};
+  use QState::{No, WantNo, WantYes, Yes};
+
+  match (side.state, side.queue) {
+    (No, _) if supported => (
+      SideState::new(Yes, Empty),
+      Response::notify(Some(Reply::Agree)),
+    ),
+    (No, _) => (side, Response::refuse()),
+    (Yes, _) => (side, Response::ignore()),
+    // The other side answered our disable request with an enable: treat it as a refusal.
+    (WantNo, Empty) => (SideState::new(No, Empty), Response::ignore()),
+    // The other side agreed to what we asked for last.
+    (WantNo, Opposite) | (WantYes, Empty) => (SideState::new(Yes, Empty), Response::ignore()),
+    (WantYes, Opposite) => (
+      SideState::new(WantNo, Empty),
+      Response::notify(Some(Reply::Refuse)),
+    ),
+  }
+}
+
+/// The other side sent the negative command (`DONT` for local, `WONT` for remote).
+pub(crate) fn receive_disable(side: SideState) -> (SideState, Response) {
+  use QQueue::{Empty, Opposite};
+  use QState::{No, WantNo, WantYes, Yes};
+
+  match (side.state, side.queue) {
+    (No, _) => (side, Response::ignore()),
+    (Yes, _) => (
+      SideState::new(No, Empty),
+      Response::notify(Some(Reply::Refuse)),
+    ),
+    (WantNo, Empty) | (WantYes, _) => (SideState::new(No, Empty), Response::notify(None)),
+    (WantNo, Opposite) => (
+      SideState::new(WantYes, Empty),
+      Response::notify(Some(Reply::Agree)),
+    ),
+  }
+}
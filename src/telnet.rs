@@ -1,3 +1,5 @@
+use core::fmt;
+
 // Define a public u8 constant with the given name and constant value.
 macro_rules! u8_const {
   ($name: ident, $value: expr) => {
@@ -5,6 +7,47 @@ macro_rules! u8_const {
   };
 }
 
+// Define an enum of the named codes of a module of constants, with `Unknown` holding any other
+// code, conversions from and to `u8`, and `Display` giving the name, or the code if unknown.
+macro_rules! u8_enum {
+  ($(#[$meta:meta])* $name:ident { $($variant:ident = $value:path => $display:literal),* $(,)? }) => {
+    $(#[$meta])*
+    #[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+    pub enum $name {
+      $($variant,)*
+      /// A code without a name. Converting a named code from `u8` never results in `Unknown`.
+      Unknown(u8),
+    }
+
+    impl From<u8> for $name {
+      fn from(value: u8) -> Self {
+        match value {
+          $($value => $name::$variant,)*
+          _ => $name::Unknown(value),
+        }
+      }
+    }
+
+    impl From<$name> for u8 {
+      fn from(value: $name) -> u8 {
+        match value {
+          $($name::$variant => $value,)*
+          $name::Unknown(value) => value,
+        }
+      }
+    }
+
+    impl fmt::Display for $name {
+      fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+          $($name::$variant => f.write_str($display),)*
+          $name::Unknown(value) => write!(f, "{value}"),
+        }
+      }
+    }
+  };
+}
+
 /// Module containing constants for Telnet Command codes.
 // TODO(XXX): rename to cmd.
 pub mod op_command {
@@ -20,6 +63,60 @@ pub mod op_command {
   u8_const!(SEND, 1);
   u8_const!(GA, 249);
   u8_const!(EOR, 239);
+  u8_const!(DM, 242);
+  u8_const!(BRK, 243);
+  u8_const!(IP, 244);
+  u8_const!(AO, 245);
+  u8_const!(AYT, 246);
+  u8_const!(EC, 247);
+  u8_const!(EL, 248);
+  u8_const!(ABORT, 238);
+  u8_const!(SUSP, 237);
+  u8_const!(EOF, 236);
+}
+
+u8_enum! {
+  /// A Telnet command code, from RFC 854, RFC 885 (`EOR`) and RFC 1184 (`ABORT`, `SUSP`, `EOF`).
+  TelnetCommand {
+    Eof = op_command::EOF => "EOF",
+    Susp = op_command::SUSP => "SUSP",
+    Abort = op_command::ABORT => "ABORT",
+    Eor = op_command::EOR => "EOR",
+    Se = op_command::SE => "SE",
+    Nop = op_command::NOP => "NOP",
+    Dm = op_command::DM => "DM",
+    Brk = op_command::BRK => "BRK",
+    Ip = op_command::IP => "IP",
+    Ao = op_command::AO => "AO",
+    Ayt = op_command::AYT => "AYT",
+    Ec = op_command::EC => "EC",
+    El = op_command::EL => "EL",
+    Ga = op_command::GA => "GA",
+    Sb = op_command::SB => "SB",
+    Will = op_command::WILL => "WILL",
+    Wont = op_command::WONT => "WONT",
+    Do = op_command::DO => "DO",
+    Dont = op_command::DONT => "DONT",
+    Iac = op_command::IAC => "IAC",
+  }
+}
+
+impl TelnetCommand {
+  /// Whether the command is sent on its own as `IAC <command>`, rather than starting a
+  /// negotiation or subnegotiation.
+  #[must_use]
+  pub fn is_two_byte(self) -> bool {
+    !matches!(
+      self,
+      TelnetCommand::Sb
+        | TelnetCommand::Will
+        | TelnetCommand::Wont
+        | TelnetCommand::Do
+        | TelnetCommand::Dont
+        | TelnetCommand::Iac
+        | TelnetCommand::Unknown(_)
+    )
+  }
 }
 
 /// Module containing constants for Telnet Option codes.
@@ -65,10 +162,72 @@ pub mod op_option {
   u8_const!(AUTHENTICATION, 37);
   u8_const!(ENCRYPT, 38);
   u8_const!(NEWENVIRON, 39);
+  u8_const!(CHARSET, 42);
+  u8_const!(MSDP, 69);
   u8_const!(MSSP, 70);
+  u8_const!(MSP, 90);
+  u8_const!(MXP, 91);
   u8_const!(ZMP, 93);
   u8_const!(EXOPL, 255);
   u8_const!(MCCP2, 86);
   u8_const!(MCCP3, 87);
+  u8_const!(ATCP, 200);
   u8_const!(GMCP, 201);
 }
+
+u8_enum! {
+  /// A Telnet option code.
+  TelnetOption {
+    Binary = op_option::BINARY => "BINARY",
+    Echo = op_option::ECHO => "ECHO",
+    Rcp = op_option::RCP => "RCP",
+    Sga = op_option::SGA => "SGA",
+    Nams = op_option::NAMS => "NAMS",
+    Status = op_option::STATUS => "STATUS",
+    Tm = op_option::TM => "TM",
+    Rcte = op_option::RCTE => "RCTE",
+    Naol = op_option::NAOL => "NAOL",
+    Naop = op_option::NAOP => "NAOP",
+    Naocrd = op_option::NAOCRD => "NAOCRD",
+    Naohts = op_option::NAOHTS => "NAOHTS",
+    Naohtd = op_option::NAOHTD => "NAOHTD",
+    Naoffd = op_option::NAOFFD => "NAOFFD",
+    Naovts = op_option::NAOVTS => "NAOVTS",
+    Naovtd = op_option::NAOVTD => "NAOVTD",
+    Naolfd = op_option::NAOLFD => "NAOLFD",
+    Xascii = op_option::XASCII => "XASCII",
+    Logout = op_option::LOGOUT => "LOGOUT",
+    Bm = op_option::BM => "BM",
+    Det = op_option::DET => "DET",
+    Supdup = op_option::SUPDUP => "SUPDUP",
+    SupdupOutput = op_option::SUPDUPOUTPUT => "SUPDUP-OUTPUT",
+    Sndloc = op_option::SNDLOC => "SNDLOC",
+    Ttype = op_option::TTYPE => "TTYPE",
+    Eor = op_option::EOR => "EOR",
+    Tuid = op_option::TUID => "TUID",
+    Outmrk = op_option::OUTMRK => "OUTMRK",
+    Ttyloc = op_option::TTYLOC => "TTYLOC",
+    Regime3270 = op_option::_3270REGIME => "3270-REGIME",
+    X3Pad = op_option::X3PAD => "X.3-PAD",
+    Naws = op_option::NAWS => "NAWS",
+    Tspeed = op_option::TSPEED => "TSPEED",
+    Lflow = op_option::LFLOW => "LFLOW",
+    Linemode = op_option::LINEMODE => "LINEMODE",
+    Xdisploc = op_option::XDISPLOC => "XDISPLOC",
+    Environ = op_option::ENVIRON => "ENVIRON",
+    Authentication = op_option::AUTHENTICATION => "AUTHENTICATION",
+    Encrypt = op_option::ENCRYPT => "ENCRYPT",
+    NewEnviron = op_option::NEWENVIRON => "NEW-ENVIRON",
+    Charset = op_option::CHARSET => "CHARSET",
+    Msdp = op_option::MSDP => "MSDP",
+    Mssp = op_option::MSSP => "MSSP",
+    Msp = op_option::MSP => "MSP",
+    Mxp = op_option::MXP => "MXP",
+    Zmp = op_option::ZMP => "ZMP",
+    Exopl = op_option::EXOPL => "EXOPL",
+    Mccp2 = op_option::MCCP2 => "MCCP2",
+    Mccp3 = op_option::MCCP3 => "MCCP3",
+    Atcp = op_option::ATCP => "ATCP",
+    Gmcp = op_option::GMCP => "GMCP",
+  }
+}
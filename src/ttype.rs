@@ -0,0 +1,406 @@
+//! TTYPE (Terminal Type, RFC 1091) codec, with support for MTTS (Mud Terminal Type Standard).
+//!
+//! The server asks for a terminal type with `IAC SB TTYPE SEND IAC SE`, and the client answers with
+//! `IAC SB TTYPE IS <name> IAC SE`. Each further `SEND` asks for the client's next terminal type,
+//! and the client signals the end of its list by repeating the last type.
+//!
+//! MTTS uses the cycle to send the client name, then the terminal type, and finally
+//! `MTTS <bitvector>` describing what the client supports (see `MttsFlags`).
+
+use alloc::{
+  borrow::ToOwned,
+  string::{String, ToString},
+  vec,
+  vec::Vec,
+};
+use core::convert::TryFrom;
+use core::fmt;
+use core::ops::{BitAnd, BitOr, BitOrAssign};
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::events::TelnetSubnegotiation;
+use crate::telnet::op_option::TTYPE;
+
+/// Precedes a terminal type sent by the client.
+pub const IS: u8 = 0;
+/// Asks the client for its (next) terminal type.
+pub const SEND: u8 = 1;
+
+/// The most terminal types a `TtypeProbe` will ask for before giving up on the cycle ending.
+pub const MAX_TYPES: usize = 8;
+
+/// An error decoding a TTYPE subnegotiation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+  /// The subnegotiation is not for the TTYPE option.
+  WrongOption(u8),
+  /// The subnegotiation is empty.
+  Empty,
+  /// The subnegotiation is neither `IS` nor `SEND`.
+  UnknownCommand(u8),
+}
+
+impl fmt::Display for DecodeError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      DecodeError::WrongOption(option) => {
+        write!(f, "subnegotiation for option {option} is not TTYPE")
+      }
+      DecodeError::Empty => write!(f, "TTYPE subnegotiation is empty"),
+      DecodeError::UnknownCommand(command) => write!(f, "unknown TTYPE command {command}"),
+    }
+  }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DecodeError {}
+
+/// A TTYPE subnegotiation.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum TtypeMessage {
+  /// `IS <name>`, a terminal type sent by the client.
+  Is(String),
+  /// `SEND`, a request for the client's next terminal type.
+  Send,
+}
+
+impl TtypeMessage {
+  /// Decode the content of a TTYPE subnegotiation.
+  ///
+  /// Terminal type names that are not valid UTF-8 are decoded lossily.
+  ///
+  /// # Errors
+  ///
+  /// Returns `DecodeError::Empty` or `DecodeError::UnknownCommand` if the data is not an `IS` or
+  /// `SEND` subnegotiation.
+  pub fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+    match data.split_first() {
+      Some((&IS, name)) => Ok(TtypeMessage::Is(String::from_utf8_lossy(name).into_owned())),
+      Some((&SEND, _)) => Ok(TtypeMessage::Send),
+      Some((command, _)) => Err(DecodeError::UnknownCommand(*command)),
+      None => Err(DecodeError::Empty),
+    }
+  }
+
+  /// Encode the message as the content of a TTYPE subnegotiation.
+  #[must_use]
+  pub fn encode(&self) -> Bytes {
+    match self {
+      TtypeMessage::Is(name) => {
+        let mut buf = BytesMut::with_capacity(name.len() + 1);
+        buf.put_u8(IS);
+        buf.put(name.as_bytes());
+        buf.freeze()
+      }
+      TtypeMessage::Send => Bytes::from_static(&[SEND]),
+    }
+  }
+}
+
+impl TryFrom<&TelnetSubnegotiation> for TtypeMessage {
+  type Error = DecodeError;
+
+  fn try_from(sub: &TelnetSubnegotiation) -> Result<Self, Self::Error> {
+    if sub.option != TTYPE {
+      return Err(DecodeError::WrongOption(sub.option));
+    }
+    Self::decode(&sub.buffer)
+  }
+}
+
+impl From<TtypeMessage> for TelnetSubnegotiation {
+  fn from(message: TtypeMessage) -> Self {
+    TelnetSubnegotiation::new(TTYPE, message.encode())
+  }
+}
+
+/// The MTTS bitvector, describing the capabilities of a client.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash)]
+pub struct MttsFlags(u16);
+
+impl MttsFlags {
+  /// The client supports all common ANSI color codes.
+  pub const ANSI: Self = Self(1);
+  /// The client supports all common VT100 codes.
+  pub const VT100: Self = Self(2);
+  /// The client is using UTF-8 character encoding.
+  pub const UTF8: Self = Self(4);
+  /// The client supports all 256 color codes.
+  pub const COLORS_256: Self = Self(8);
+  /// The client supports xterm mouse tracking.
+  pub const MOUSE_TRACKING: Self = Self(16);
+  /// The client supports the OSC color palette.
+  pub const OSC_COLOR_PALETTE: Self = Self(32);
+  /// The client is using a screen reader.
+  pub const SCREEN_READER: Self = Self(64);
+  /// The client is a proxy allowing different users to connect from the same IP address.
+  pub const PROXY: Self = Self(128);
+  /// The client supports truecolor codes using semicolon notation.
+  pub const TRUECOLOR: Self = Self(256);
+  /// The client supports the Mud New Environment Standard.
+  pub const MNES: Self = Self(512);
+  /// The client supports the Mud Server Link Protocol.
+  pub const MSLP: Self = Self(1024);
+  /// The client supports SSL for data encryption.
+  pub const SSL: Self = Self(2048);
+
+  /// No flags set.
+  #[must_use]
+  pub const fn empty() -> Self {
+    Self(0)
+  }
+
+  /// Create flags from the raw bitvector, keeping any bits without a named constant.
+  #[must_use]
+  pub const fn from_bits(bits: u16) -> Self {
+    Self(bits)
+  }
+
+  /// The raw bitvector.
+  #[must_use]
+  pub const fn bits(self) -> u16 {
+    self.0
+  }
+
+  /// Whether no flags are set.
+  #[must_use]
+  pub const fn is_empty(self) -> bool {
+    self.0 == 0
+  }
+
+  /// Whether all flags in `other` are set.
+  #[must_use]
+  pub const fn contains(self, other: Self) -> bool {
+    self.0 & other.0 == other.0
+  }
+
+  /// Set all flags in `other`.
+  pub fn insert(&mut self, other: Self) {
+    self.0 |= other.0;
+  }
+
+  /// Clear all flags in `other`.
+  pub fn remove(&mut self, other: Self) {
+    self.0 &= !other.0;
+  }
+
+  /// Parse an `MTTS <bitvector>` terminal type.
+  #[must_use]
+  pub fn parse(name: &str) -> Option<Self> {
+    let bits = name.strip_prefix("MTTS ")?;
+    bits.trim().parse().ok().map(Self)
+  }
+}
+
+impl BitOr for MttsFlags {
+  type Output = Self;
+
+  fn bitor(self, rhs: Self) -> Self {
+    Self(self.0 | rhs.0)
+  }
+}
+
+impl BitOrAssign for MttsFlags {
+  fn bitor_assign(&mut self, rhs: Self) {
+    self.insert(rhs);
+  }
+}
+
+impl BitAnd for MttsFlags {
+  type Output = Self;
+
+  fn bitand(self, rhs: Self) -> Self {
+    Self(self.0 & rhs.0)
+  }
+}
+
+/// Formats the flags as an MTTS terminal type, e.g. `MTTS 137`.
+impl fmt::Display for MttsFlags {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "MTTS {}", self.0)
+  }
+}
+
+/// The client side of the TTYPE cycle, answering each `SEND` with the next terminal type.
+///
+/// Once every type has been sent the last one is repeated to mark the end of the list, after
+/// which the cycle starts over.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TtypeResponder {
+  types: Vec<String>,
+  position: usize,
+}
+
+impl TtypeResponder {
+  /// Create a responder following the MTTS convention, sending the client name, then the terminal
+  /// type, then the MTTS flags.
+  ///
+  /// By convention the client name and terminal type are upper case, e.g. `BLIGHTMUD` and `XTERM`.
+  #[must_use]
+  pub fn new(client: &str, terminal: &str, flags: MttsFlags) -> Self {
+    Self::with_types(vec![
+      client.to_owned(),
+      terminal.to_owned(),
+      flags.to_string(),
+    ])
+  }
+
+  /// Create a responder cycling through the given terminal types.
+  ///
+  /// An empty list answers every request with `UNKNOWN`.
+  #[must_use]
+  pub fn with_types(types: Vec<String>) -> Self {
+    Self { types, position: 0 }
+  }
+
+  /// The terminal types in the cycle.
+  #[must_use]
+  pub fn types(&self) -> &[String] {
+    &self.types
+  }
+
+  /// The position in the cycle of the type the next `SEND` is answered with.
+  #[must_use]
+  pub fn position(&self) -> usize {
+    self.position
+  }
+
+  /// Get the terminal type to answer a `SEND` with, advancing the cycle.
+  pub fn next_type(&mut self) -> &str {
+    let Some(last) = self.types.len().checked_sub(1) else {
+      return "UNKNOWN";
+    };
+    let index = self.position.min(last);
+    self.position = (self.position + 1) % (self.types.len() + 1);
+    &self.types[index]
+  }
+
+  /// Restart the cycle from the first terminal type.
+  pub fn reset(&mut self) {
+    self.position = 0;
+  }
+}
+
+/// The server side of the TTYPE cycle, collecting the client's terminal types.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct TtypeProbe {
+  types: Vec<String>,
+  complete: bool,
+}
+
+impl TtypeProbe {
+  #[must_use]
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Record a terminal type sent by the client.
+  ///
+  /// Returns `true` if another `SEND` should be sent to continue the cycle. The cycle is complete
+  /// when the client repeats a type, sends an MTTS bitvector, or has sent `MAX_TYPES` types.
+  pub fn receive(&mut self, name: &str) -> bool {
+    if self.complete {
+      return false;
+    }
+    if self.types.last().map(String::as_str) == Some(name) {
+      self.complete = true;
+      return false;
+    }
+    self.types.push(name.to_owned());
+    self.complete = MttsFlags::parse(name).is_some() || self.types.len() >= MAX_TYPES;
+    !self.complete
+  }
+
+  /// Whether the client has reached the end of its terminal types.
+  #[must_use]
+  pub fn is_complete(&self) -> bool {
+    self.complete
+  }
+
+  /// All distinct terminal types received, in order.
+  #[must_use]
+  pub fn types(&self) -> &[String] {
+    &self.types
+  }
+
+  /// The client name, which MTTS clients send as their first terminal type.
+  #[must_use]
+  pub fn client_name(&self) -> Option<&str> {
+    self.types.first().map(String::as_str)
+  }
+
+  /// The terminal type, which MTTS clients send as their second terminal type. Clients with a
+  /// single terminal type send it first.
+  #[must_use]
+  pub fn terminal_type(&self) -> Option<&str> {
+    self
+      .types
+      .get(1)
+      .or_else(|| self.types.first())
+      .map(String::as_str)
+  }
+
+  /// The MTTS flags, if the client sent them.
+  #[must_use]
+  pub fn mtts(&self) -> Option<MttsFlags> {
+    self.types.iter().find_map(|name| MttsFlags::parse(name))
+  }
+}
+
+#[cfg(test)]
+mod test_ttype {
+  use super::*;
+
+  #[test]
+  fn test_codec() {
+    assert_eq!(TtypeMessage::decode(&[SEND]), Ok(TtypeMessage::Send));
+    let is = TtypeMessage::Is(String::from("XTERM"));
+    assert_eq!(&is.encode()[..], b"\x00XTERM");
+    assert_eq!(TtypeMessage::decode(&is.encode()), Ok(is));
+    assert_eq!(TtypeMessage::decode(&[]), Err(DecodeError::Empty));
+    assert_eq!(
+      TtypeMessage::decode(&[2]),
+      Err(DecodeError::UnknownCommand(2))
+    );
+  }
+
+  #[test]
+  fn test_flags() {
+    let flags = MttsFlags::ANSI | MttsFlags::UTF8 | MttsFlags::COLORS_256 | MttsFlags::TRUECOLOR;
+    assert_eq!(flags.to_string(), "MTTS 269");
+    assert_eq!(MttsFlags::parse("MTTS 269"), Some(flags));
+    assert!(flags.contains(MttsFlags::UTF8 | MttsFlags::ANSI));
+    assert!(!flags.contains(MttsFlags::SCREEN_READER));
+    assert_eq!(MttsFlags::parse("XTERM"), None);
+    assert_eq!(MttsFlags::parse("MTTS x"), None);
+  }
+
+  #[test]
+  fn test_cycle() {
+    let mut responder = TtypeResponder::new("BLIGHTMUD", "XTERM", MttsFlags::ANSI);
+    let mut probe = TtypeProbe::new();
+    assert!(probe.receive(responder.next_type()));
+    assert!(probe.receive(responder.next_type()));
+    assert!(!probe.receive(responder.next_type()));
+    assert!(probe.is_complete());
+    assert_eq!(probe.client_name(), Some("BLIGHTMUD"));
+    assert_eq!(probe.terminal_type(), Some("XTERM"));
+    assert_eq!(probe.mtts(), Some(MttsFlags::ANSI));
+
+    // The last type is repeated once, then the cycle starts over.
+    assert_eq!(responder.next_type(), "MTTS 1");
+    assert_eq!(responder.next_type(), "BLIGHTMUD");
+    assert_eq!(responder.position(), 1);
+  }
+
+  #[test]
+  fn test_probe_plain_client() {
+    let mut probe = TtypeProbe::new();
+    assert!(probe.receive("VT100"));
+    assert!(!probe.receive("VT100"));
+    assert_eq!(probe.terminal_type(), Some("VT100"));
+    assert_eq!(probe.mtts(), None);
+    assert_eq!(TtypeResponder::with_types(vec![]).next_type(), "UNKNOWN");
+  }
+}
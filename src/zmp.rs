@@ -0,0 +1,403 @@
+//! ZMP (Zenith MUD Protocol) codec and command dispatcher.
+//!
+//! A ZMP command is carried in an `IAC SB ZMP ... IAC SE` subnegotiation as a list of
+//! NUL-terminated strings: the command name followed by its arguments, e.g.
+//! `zmp.check\0color.\0`.
+//!
+//! `ZmpRegistry` dispatches received commands to handlers by name, and answers the core
+//! `zmp.ping`, `zmp.check` and `zmp.ident` commands itself.
+
+use alloc::{
+  borrow::ToOwned,
+  boxed::Box,
+  format,
+  string::{String, ToString},
+  vec,
+  vec::Vec,
+};
+use core::convert::TryFrom;
+use core::fmt;
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use crate::events::TelnetSubnegotiation;
+use crate::telnet::op_option::ZMP;
+
+/// Names of the core ZMP commands.
+pub mod cmd {
+  pub const PING: &str = "zmp.ping";
+  pub const TIME: &str = "zmp.time";
+  pub const IDENT: &str = "zmp.ident";
+  pub const CHECK: &str = "zmp.check";
+  pub const SUPPORT: &str = "zmp.support";
+  pub const NO_SUPPORT: &str = "zmp.no-support";
+  pub const INPUT: &str = "zmp.input";
+}
+
+/// An error decoding a ZMP subnegotiation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+  /// The subnegotiation is not for the ZMP option.
+  WrongOption(u8),
+  /// The subnegotiation has no command name.
+  MissingName,
+}
+
+impl fmt::Display for DecodeError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      DecodeError::WrongOption(option) => {
+        write!(f, "subnegotiation for option {option} is not ZMP")
+      }
+      DecodeError::MissingName => write!(f, "ZMP subnegotiation has no command name"),
+    }
+  }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DecodeError {}
+
+/// A ZMP command, e.g. `zmp.check color.`.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct ZmpCommand {
+  pub name: String,
+  pub args: Vec<String>,
+}
+
+impl ZmpCommand {
+  pub fn new<N, I, A>(name: N, args: I) -> Self
+  where
+    N: Into<String>,
+    I: IntoIterator<Item = A>,
+    A: Into<String>,
+  {
+    Self {
+      name: name.into(),
+      args: args.into_iter().map(Into::into).collect(),
+    }
+  }
+
+  /// The package of the command, e.g. `zmp.` for `zmp.ping`.
+  #[must_use]
+  pub fn package(&self) -> &str {
+    self
+      .name
+      .rfind('.')
+      .map_or("", |index| &self.name[..=index])
+  }
+
+  /// Decode the content of a ZMP subnegotiation.
+  ///
+  /// A missing NUL after the last argument is tolerated. Strings that are not valid UTF-8 are
+  /// decoded lossily.
+  ///
+  /// # Errors
+  ///
+  /// Returns `DecodeError::MissingName` if the command name is empty.
+  pub fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+    let data = data.strip_suffix(&[0]).unwrap_or(data);
+    let mut parts = data
+      .split(|b| *b == 0)
+      .map(|part| String::from_utf8_lossy(part).into_owned());
+    let name = parts
+      .next()
+      .filter(|name| !name.is_empty())
+      .ok_or(DecodeError::MissingName)?;
+    Ok(Self {
+      name,
+      args: parts.collect(),
+    })
+  }
+
+  /// Encode the command as the content of a ZMP subnegotiation.
+  ///
+  /// NUL bytes cannot be sent, and end the string they are found in.
+  #[must_use]
+  pub fn encode(&self) -> Bytes {
+    let mut buf = BytesMut::new();
+    for part in core::iter::once(&self.name).chain(&self.args) {
+      let part = part.as_bytes();
+      let len = part.iter().position(|b| *b == 0).unwrap_or(part.len());
+      buf.put(&part[..len]);
+      buf.put_u8(0);
+    }
+    buf.freeze()
+  }
+}
+
+impl fmt::Display for ZmpCommand {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.name)?;
+    for arg in &self.args {
+      write!(f, " {arg}")?;
+    }
+    Ok(())
+  }
+}
+
+impl TryFrom<&TelnetSubnegotiation> for ZmpCommand {
+  type Error = DecodeError;
+
+  fn try_from(sub: &TelnetSubnegotiation) -> Result<Self, Self::Error> {
+    if sub.option != ZMP {
+      return Err(DecodeError::WrongOption(sub.option));
+    }
+    Self::decode(&sub.buffer)
+  }
+}
+
+impl From<ZmpCommand> for TelnetSubnegotiation {
+  fn from(command: ZmpCommand) -> Self {
+    TelnetSubnegotiation::new(ZMP, command.encode())
+  }
+}
+
+/// A handler for a received ZMP command, returning any command to send in reply.
+pub type ZmpHandler = Box<dyn FnMut(&ZmpCommand) -> Option<ZmpCommand> + Send + Sync>;
+
+/// A source of the current UTC time, formatted as `YYYY-MM-DD HH:MM:SS`, used to answer
+/// `zmp.ping`.
+pub type ZmpClock = Box<dyn Fn() -> String + Send + Sync>;
+
+/// Dispatches received ZMP commands to handlers by name.
+///
+/// Handlers can be registered for a full command name, or for a whole package by registering its
+/// name with a trailing dot (e.g. `color.`). The core commands are answered without a handler:
+///
+/// * `zmp.ping` with `zmp.time`, if a clock is available.
+/// * `zmp.check` with `zmp.support` or `zmp.no-support`.
+/// * `zmp.ident` by recording the remote identity, see `remote_ident`.
+pub struct ZmpRegistry {
+  handlers: Vec<(String, ZmpHandler)>,
+  ident: ZmpCommand,
+  remote_ident: Option<ZmpCommand>,
+  clock: Option<ZmpClock>,
+}
+
+impl ZmpRegistry {
+  /// Create a registry identifying itself with the given name, version and description.
+  ///
+  /// With the `std` feature enabled the system clock is used to answer `zmp.ping`.
+  #[must_use]
+  pub fn new(name: &str, version: &str, description: &str) -> Self {
+    Self {
+      handlers: Vec::new(),
+      ident: ZmpCommand::new(cmd::IDENT, vec![name, version, description]),
+      remote_ident: None,
+      clock: default_clock(),
+    }
+  }
+
+  /// Set the clock used to answer `zmp.ping`, or `None` to leave pings unanswered.
+  pub fn set_clock(&mut self, clock: Option<ZmpClock>) {
+    self.clock = clock;
+  }
+
+  /// Register a handler for a command, or a package if `name` ends with a dot, replacing any
+  /// previous handler for the same name.
+  ///
+  /// Handlers must be `Send` and `Sync`, so a `Parser` keeping the registry can be moved to
+  /// another thread.
+  pub fn register<F>(&mut self, name: &str, handler: F)
+  where
+    F: FnMut(&ZmpCommand) -> Option<ZmpCommand> + Send + Sync + 'static,
+  {
+    self.unregister(name);
+    self.handlers.push((name.to_owned(), Box::new(handler)));
+  }
+
+  /// Remove the handler for a command or package. Returns whether one was registered.
+  pub fn unregister(&mut self, name: &str) -> bool {
+    let len = self.handlers.len();
+    self.handlers.retain(|(n, _)| n != name);
+    self.handlers.len() != len
+  }
+
+  /// Whether a command or package is supported, as asked by `zmp.check`.
+  #[must_use]
+  pub fn is_supported(&self, name: &str) -> bool {
+    matches!(name, "zmp." | cmd::PING | cmd::CHECK | cmd::IDENT)
+      || self.handlers.iter().any(|(handler, _)| {
+        handler == name || (handler.ends_with('.') && name.starts_with(handler.as_str()))
+      })
+  }
+
+  /// The `zmp.ident` command identifying this end, to be sent when ZMP is enabled.
+  #[must_use]
+  pub fn ident(&self) -> &ZmpCommand {
+    &self.ident
+  }
+
+  /// The `zmp.ident` command received from the remote end, if any.
+  #[must_use]
+  pub fn remote_ident(&self) -> Option<&ZmpCommand> {
+    self.remote_ident.as_ref()
+  }
+
+  /// Dispatch a received command, returning any command to send in reply.
+  ///
+  /// Commands without a handler are ignored.
+  pub fn dispatch(&mut self, command: &ZmpCommand) -> Option<ZmpCommand> {
+    match command.name.as_str() {
+      cmd::PING => {
+        let time = self.clock.as_ref()?();
+        return Some(ZmpCommand::new(cmd::TIME, vec![time]));
+      }
+      cmd::CHECK => {
+        let name = command.args.first()?;
+        let reply = if self.is_supported(name) {
+          cmd::SUPPORT
+        } else {
+          cmd::NO_SUPPORT
+        };
+        return Some(ZmpCommand::new(reply, vec![name.clone()]));
+      }
+      cmd::IDENT => self.remote_ident = Some(command.clone()),
+      _ => {}
+    }
+    // Prefer an exact match over a package handler.
+    let index = self
+      .handlers
+      .iter()
+      .position(|(name, _)| *name == command.name)
+      .or_else(|| {
+        let package = command.package();
+        self.handlers.iter().position(|(name, _)| name == package)
+      })?;
+    (self.handlers[index].1)(command)
+  }
+}
+
+// Returns an `Option` to match the `no_std` version.
+#[cfg(feature = "std")]
+#[allow(clippy::unnecessary_wraps)]
+fn default_clock() -> Option<ZmpClock> {
+  Some(Box::new(|| {
+    let now = std::time::SystemTime::now()
+      .duration_since(std::time::UNIX_EPOCH)
+      .map_or(0, |elapsed| elapsed.as_secs());
+    format_utc(now)
+  }))
+}
+
+#[cfg(not(feature = "std"))]
+fn default_clock() -> Option<ZmpClock> {
+  None
+}
+
+/// Format seconds since the unix epoch as `YYYY-MM-DD HH:MM:SS`.
+#[cfg(feature = "std")]
+fn format_utc(secs: u64) -> String {
+  let days = i64::try_from(secs / 86400).unwrap_or(i64::MAX);
+  let time = secs % 86400;
+  // Convert days to a civil date, from Howard Hinnant's `civil_from_days`.
+  let z = days + 719_468;
+  let era = z.div_euclid(146_097);
+  let doe = z.rem_euclid(146_097);
+  let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+  let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+  let mp = (5 * doy + 2) / 153;
+  let day = doy - (153 * mp + 2) / 5 + 1;
+  let month = if mp < 10 { mp + 3 } else { mp - 9 };
+  let year = yoe + era * 400 + i64::from(month <= 2);
+  format!(
+    "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}",
+    time / 3600,
+    time / 60 % 60,
+    time % 60
+  )
+}
+
+impl fmt::Debug for ZmpRegistry {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("ZmpRegistry")
+      .field(
+        "handlers",
+        &self
+          .handlers
+          .iter()
+          .map(|(name, _)| name)
+          .collect::<Vec<_>>(),
+      )
+      .field("ident", &self.ident.to_string())
+      .field("remote_ident", &self.remote_ident)
+      .finish_non_exhaustive()
+  }
+}
+
+#[cfg(test)]
+mod test_zmp {
+  use super::*;
+
+  #[test]
+  fn test_codec() {
+    let command = ZmpCommand::new("zmp.check", vec!["color."]);
+    assert_eq!(&command.encode()[..], b"zmp.check\0color.\0");
+    assert_eq!(ZmpCommand::decode(&command.encode()), Ok(command.clone()));
+    assert_eq!(ZmpCommand::decode(b"zmp.check\0color."), Ok(command));
+    assert_eq!(
+      ZmpCommand::decode(b"zmp.ping\0"),
+      Ok(ZmpCommand::new("zmp.ping", Vec::<String>::new()))
+    );
+    assert_eq!(
+      ZmpCommand::decode(b"zmp.input\0\0"),
+      Ok(ZmpCommand::new("zmp.input", vec![""]))
+    );
+    assert_eq!(ZmpCommand::decode(b"\0"), Err(DecodeError::MissingName));
+    assert_eq!(
+      &ZmpCommand::new("a.b", vec!["x\0y"]).encode()[..],
+      b"a.b\0x\0"
+    );
+    assert_eq!(
+      ZmpCommand::new("color.define", vec!["1"]).package(),
+      "color."
+    );
+  }
+
+  #[cfg(feature = "std")]
+  #[test]
+  fn test_format_utc() {
+    assert_eq!(format_utc(0), "1970-01-01 00:00:00");
+    assert_eq!(format_utc(951_782_400), "2000-02-29 00:00:00");
+    assert_eq!(format_utc(1_700_000_000), "2023-11-14 22:13:20");
+  }
+
+  #[test]
+  fn test_registry() {
+    let mut registry = ZmpRegistry::new("libmudtelnet", "2.0", "test client");
+    registry.set_clock(Some(Box::new(|| String::from("2024-01-01 00:00:00"))));
+    registry.register("color.", |_| None);
+    registry.register("color.define", |command| {
+      Some(ZmpCommand::new("color.ok", command.args.clone()))
+    });
+
+    assert_eq!(
+      registry.dispatch(&ZmpCommand::new(cmd::PING, Vec::<String>::new())),
+      Some(ZmpCommand::new(cmd::TIME, vec!["2024-01-01 00:00:00"]))
+    );
+    assert_eq!(
+      registry.dispatch(&ZmpCommand::new(cmd::CHECK, vec!["color.use"])),
+      Some(ZmpCommand::new(cmd::SUPPORT, vec!["color.use"]))
+    );
+    assert_eq!(
+      registry.dispatch(&ZmpCommand::new(cmd::CHECK, vec!["sound."])),
+      Some(ZmpCommand::new(cmd::NO_SUPPORT, vec!["sound."]))
+    );
+    assert_eq!(
+      registry.dispatch(&ZmpCommand::new("color.define", vec!["1"])),
+      Some(ZmpCommand::new("color.ok", vec!["1"]))
+    );
+    assert_eq!(
+      registry.dispatch(&ZmpCommand::new("color.use", vec!["1"])),
+      None
+    );
+
+    let ident = ZmpCommand::new(cmd::IDENT, vec!["Server", "1.0", "A server"]);
+    assert_eq!(registry.dispatch(&ident), None);
+    assert_eq!(registry.remote_ident(), Some(&ident));
+    assert!(registry.is_supported("zmp."));
+    assert!(!registry.is_supported("zmp.foo"));
+    assert!(registry.unregister("color."));
+    assert!(!registry.is_supported("color.use"));
+  }
+}
@@ -1,8 +1,28 @@
+use std::convert::TryFrom;
+
 use bytes::Bytes;
 
-use libmudtelnet::compatibility::{CompatibilityEntry, CompatibilityTable};
-use libmudtelnet::events::{TelnetEvents, TelnetSubnegotiation};
-use libmudtelnet::telnet::{op_command as cmd, op_option as opt};
+use libmudtelnet::atcp::{self, AtcpMessage};
+use libmudtelnet::charset::{CharsetMessage, CharsetResponder};
+use libmudtelnet::compatibility::{
+  CompatibilityEntry, CompatibilityTable, OptionState, QQueue, QState, SideState,
+};
+use libmudtelnet::config::{OverflowPolicy, ParserConfig, SubnegotiationPolicy};
+use libmudtelnet::events::{
+  TelnetError, TelnetEvents, TelnetHandler, TelnetIAC, TelnetNegotiation, TelnetSubnegotiation,
+};
+use libmudtelnet::gmcp::GmcpMessage;
+use libmudtelnet::linemode::{slc, LinemodeMessage, Mode, SlcTriplet};
+use libmudtelnet::msdp::{self, MsdpVariable};
+use libmudtelnet::msp::{MspKind, MspTrigger};
+use libmudtelnet::mssp::{self, Mssp};
+use libmudtelnet::mxp::{LineMode, MxpToken, MxpTokenizer};
+use libmudtelnet::naws::WindowSize;
+use libmudtelnet::new_environ::{mnes, EnvRequest, EnvVar, EnvironMessage, EnvironStore, VarKind};
+use libmudtelnet::oob::{OobMessage, OobProtocol};
+use libmudtelnet::telnet::{op_command as cmd, op_option as opt, TelnetCommand, TelnetOption};
+use libmudtelnet::ttype::{MttsFlags, TtypeMessage, TtypeResponder};
+use libmudtelnet::zmp::{self, ZmpCommand, ZmpRegistry};
 use libmudtelnet::Parser;
 
 /// Test the parser and its general functionality.
@@ -15,6 +35,9 @@ enum Event {
   Recv,
   Send,
   Decom,
+  DecomError,
+  Msp,
+  ProtocolError,
 }
 
 macro_rules! events {
@@ -86,6 +109,18 @@ fn handle_events(event_list: Vec<TelnetEvents>) -> CapturedEvents {
         println!("DECOMPRESS: {:?}", buffer);
         events.push(Event::Decom);
       }
+      TelnetEvents::DecompressError => {
+        println!("DECOMPRESS ERROR");
+        events.push(Event::DecomError);
+      }
+      TelnetEvents::Msp(trigger) => {
+        println!("MSP: {}", trigger);
+        events.push(Event::Msp);
+      }
+      TelnetEvents::ProtocolError(error) => {
+        println!("PROTOCOL ERROR: {}", error);
+        events.push(Event::ProtocolError);
+      }
     };
   }
   events
@@ -95,11 +130,11 @@ fn handle_events(event_list: Vec<TelnetEvents>) -> CapturedEvents {
 fn test_parser() {
   let mut instance: Parser = Parser::new();
   instance.options.support_local(opt::GMCP);
-  instance.options.support_local(opt::MCCP2);
+  instance.options.support_remote(opt::MCCP2);
   if let Some(ev) = instance._will(opt::GMCP) {
     assert_eq!(handle_events(vec![ev]), events![Event::Send]);
   }
-  if let Some(ev) = instance._will(opt::MCCP2) {
+  if let Some(ev) = instance._do(opt::MCCP2) {
     assert_eq!(handle_events(vec![ev]), events![Event::Send]);
   }
   assert_eq!(
@@ -110,6 +145,10 @@ fn test_parser() {
     handle_events(instance.receive(&[cmd::IAC, cmd::DO, opt::GMCP])),
     events![]
   );
+  assert_eq!(
+    handle_events(instance.receive(&[cmd::IAC, cmd::WILL, opt::MCCP2])),
+    events![]
+  );
   assert_eq!(
     handle_events(instance.receive(&[&[cmd::IAC, cmd::DO, 200][..], b"Some random data"].concat())),
     events![Event::Send, Event::Recv]
@@ -162,6 +201,7 @@ fn test_subneg_separate_receives() {
   let mut instance: Parser = Parser::with_capacity(10);
   instance.options.support_local(opt::GMCP);
   instance._will(opt::GMCP);
+  instance.receive(&[cmd::IAC, cmd::DO, opt::GMCP]);
   let mut events = instance.receive(
     &[
       &[cmd::IAC, cmd::SB, opt::GMCP][..],
@@ -205,6 +245,7 @@ fn test_subneg_utf8_content() {
   let mut parser = Parser::new();
   parser.options.support_local(GMCP);
   parser._will(GMCP);
+  parser.receive(&[IAC, cmd::DO, GMCP]);
 
   // Construct a GMCP message containing a UTF-8 sequence that happens
   // to include SE (0xF0). This should be permitted as long as the SE isn't
@@ -239,6 +280,7 @@ fn test_iac_split_at_buffer_boundary() {
   let mut parser = Parser::new();
   parser.options.support_local(opt::GMCP);
   parser._will(opt::GMCP);
+  parser.receive(&[cmd::IAC, cmd::DO, opt::GMCP]);
 
   // First buffer ends with IAC (0xFF) — the SB+GMCP bytes arrive in the next buffer.
   let buf1 = b"some text\xFF";
@@ -287,180 +329,1857 @@ fn test_negotiation_split_at_buffer_boundary() {
   );
 }
 
-/// Test escaping IAC bytes in a buffer.
+/// Test that a repeated WILL from the remote end is only answered once.
 #[test]
-fn test_escape() {
-  let initial = vec![
-    cmd::IAC,
-    cmd::SB,
-    201,
-    cmd::IAC,
-    205,
-    202,
-    cmd::IAC,
-    cmd::SE,
-  ];
-  let expected = Bytes::copy_from_slice(&[
-    cmd::IAC,
-    cmd::IAC,
-    cmd::SB,
-    201,
-    cmd::IAC,
-    cmd::IAC,
-    205,
-    202,
-    cmd::IAC,
-    cmd::IAC,
-    cmd::SE,
-  ]);
-  assert_eq!(expected, Parser::escape_iac(initial.clone()));
-  assert_eq!(initial, Parser::unescape_iac(expected));
-}
+fn test_q_method_duplicate_will() {
+  let mut parser = Parser::new();
+  parser.options.support_remote(opt::GMCP);
 
-/// Test unescaping IAC bytes in a buffer.
-#[test]
-fn test_unescape() {
-  let initial = vec![
-    cmd::IAC,
-    cmd::IAC,
-    cmd::SB,
-    201,
-    cmd::IAC,
-    cmd::IAC,
-    205,
-    202,
-    cmd::IAC,
-    cmd::IAC,
-    cmd::SE,
-  ];
-  let expected = Bytes::copy_from_slice(&[
-    cmd::IAC,
-    cmd::SB,
-    201,
-    cmd::IAC,
-    205,
-    202,
-    cmd::IAC,
-    cmd::SE,
-  ]);
-  assert_eq!(expected, Parser::unescape_iac(initial.clone()));
-  assert_eq!(initial, Parser::escape_iac(expected));
+  let events = parser.receive(&[cmd::IAC, cmd::WILL, opt::GMCP]);
+  assert_eq!(
+    events,
+    vec![
+      TelnetEvents::DataSend(Bytes::copy_from_slice(&[cmd::IAC, cmd::DO, opt::GMCP])),
+      TelnetEvents::Negotiation(TelnetNegotiation::new(cmd::WILL, opt::GMCP)),
+    ]
+  );
+  assert_eq!(parser.receive(&[cmd::IAC, cmd::WILL, opt::GMCP]), vec![]);
+  assert!(parser.options.get_option(opt::GMCP).remote_state);
 }
 
+/// Test that a WILL answering our own DO is not answered again.
 #[test]
-fn test_escape_roundtrip_bug_one() {
-  // The original libtelnet-rs mishandles this input:
-  let data = vec![cmd::IAC, cmd::IAC, 228];
-  let escaped = Parser::escape_iac(data.clone());
-  let unescaped = Parser::unescape_iac(escaped);
-  assert_eq!(unescaped, data);
+fn test_q_method_do_answered() {
+  let mut parser = Parser::new();
+  parser.options.support_remote(opt::GMCP);
+
+  assert_eq!(
+    parser._do(opt::GMCP),
+    Some(TelnetEvents::DataSend(Bytes::copy_from_slice(&[
+      cmd::IAC,
+      cmd::DO,
+      opt::GMCP
+    ])))
+  );
+  // A second request while the first is pending sends nothing.
+  assert_eq!(parser._do(opt::GMCP), None);
+  assert_eq!(
+    parser.options.remote_side(opt::GMCP),
+    SideState::new(QState::WantYes, QQueue::Empty)
+  );
+  assert!(!parser.options.get_option(opt::GMCP).remote_state);
+
+  // The WILL only confirms our request, and is neither answered nor reported.
+  assert_eq!(parser.receive(&[cmd::IAC, cmd::WILL, opt::GMCP]), vec![]);
+  assert_eq!(
+    parser.options.remote_side(opt::GMCP),
+    SideState::new(QState::Yes, QQueue::Empty)
+  );
 }
 
+/// Test that a refused request leaves the option disabled without a reply.
 #[test]
-fn test_escape_roundtrip_bug_two() {
-  // The original libtelnet-rs mishandles this input:
-  let data = vec![228, cmd::IAC, cmd::IAC];
-  let escaped = Parser::escape_iac(data.clone());
-  let unescaped = Parser::unescape_iac(escaped);
-  assert_eq!(unescaped, data);
+fn test_q_method_will_refused() {
+  let mut parser = Parser::new();
+  parser.options.support_local(opt::GMCP);
+
+  assert!(parser._will(opt::GMCP).is_some());
+  assert_eq!(
+    parser.receive(&[cmd::IAC, cmd::DONT, opt::GMCP]),
+    vec![TelnetEvents::Negotiation(TelnetNegotiation::new(
+      cmd::DONT,
+      opt::GMCP
+    ))]
+  );
+  assert_eq!(parser.options.local_side(opt::GMCP), SideState::default());
+  // Further DONTs are ignored.
+  assert_eq!(parser.receive(&[cmd::IAC, cmd::DONT, opt::GMCP]), vec![]);
 }
 
+/// Test that a request for the opposite state is queued behind a pending negotiation.
 #[test]
-fn test_bad_subneg_dbuffer() {
-  // Configure opt 0xFF (IAC) as local supported, and local state enabled.
-  let entry = CompatibilityEntry::new(true, false, true, false);
-  let opts = CompatibilityTable::from_options(&[(cmd::IAC, entry.into_u8())]);
-  // Receive a malformed subnegotiation - this should not panic.
-  Parser::with_support(opts).receive(&[cmd::IAC, cmd::SB, cmd::IAC, cmd::SE]);
+fn test_q_method_queued_opposite() {
+  let mut parser = Parser::new();
+  parser.options.support_local(opt::GMCP);
+
+  assert!(parser._will(opt::GMCP).is_some());
+  // Changing our mind while WILL is pending queues a WONT instead of sending it.
+  assert_eq!(parser._wont(opt::GMCP), None);
+  assert_eq!(
+    parser.options.local_side(opt::GMCP),
+    SideState::new(QState::WantYes, QQueue::Opposite)
+  );
+
+  // Once the DO arrives the queued WONT is sent.
+  assert_eq!(
+    parser.receive(&[cmd::IAC, cmd::DO, opt::GMCP]),
+    vec![
+      TelnetEvents::DataSend(Bytes::copy_from_slice(&[cmd::IAC, cmd::WONT, opt::GMCP])),
+      TelnetEvents::Negotiation(TelnetNegotiation::new(cmd::DO, opt::GMCP)),
+    ]
+  );
+  assert_eq!(
+    parser.options.local_side(opt::GMCP),
+    SideState::new(QState::WantNo, QQueue::Empty)
+  );
+
+  // And the DONT confirming it is not answered.
+  assert_eq!(
+    parser.receive(&[cmd::IAC, cmd::DONT, opt::GMCP]),
+    vec![TelnetEvents::Negotiation(TelnetNegotiation::new(
+      cmd::DONT,
+      opt::GMCP
+    ))]
+  );
+  assert_eq!(parser.options.local_side(opt::GMCP), SideState::default());
 }
 
+/// Test that two peers using the parser settle instead of looping.
 #[test]
-fn test_into_bytes() {
-  let bytes = libmudtelnet::events::TelnetIAC::new(cmd::IAC).to_bytes();
-  assert!(!bytes.is_empty())
-}
+fn test_q_method_no_loop() {
+  let mut client = Parser::new();
+  let mut server = Parser::new();
+  client.options.support(opt::GMCP);
+  server.options.support(opt::GMCP);
 
-#[cfg(test)]
-mod compat_tests {
-  use compat::{test_app, TelnetApplication};
+  let mut to_server = vec![client._will(opt::GMCP).unwrap().to_bytes()];
+  to_server.push(client._do(opt::GMCP).unwrap().to_bytes());
+  to_server.push(server._will(opt::GMCP).unwrap().to_bytes());
+  let mut to_client = vec![to_server.pop().unwrap()];
 
-  #[test]
-  fn test_parser_diff1() {
-    test_app(&TelnetApplication {
-      options: vec![(255, 254)],
-      received_data: vec![vec![255, 255, 255, 255, 255, 254, 255, 0]],
-    });
+  for _ in 0..4 {
+    let mut next_to_client = Vec::new();
+    for data in to_server.drain(..) {
+      for ev in server.receive(&data) {
+        if let TelnetEvents::DataSend(data) = ev {
+          next_to_client.push(data);
+        }
+      }
+    }
+    for data in to_client.drain(..) {
+      for ev in client.receive(&data) {
+        if let TelnetEvents::DataSend(data) = ev {
+          to_server.push(data);
+        }
+      }
+    }
+    to_client = next_to_client;
   }
 
-  #[test]
-  fn test_parser_diff2() {
-    test_app(&TelnetApplication {
-      options: vec![],
-      received_data: vec![vec![45, 255, 250, 255]],
-    });
+  assert!(to_server.is_empty() && to_client.is_empty());
+  for parser in [&client, &server] {
+    let entry = parser.options.get_option(opt::GMCP);
+    assert!(entry.local_state && entry.remote_state);
   }
+}
 
-  #[test]
-  fn test_parser_diff3() {
-    test_app(&TelnetApplication {
-      options: vec![(0, 1)],
-      received_data: vec![vec![255, 253, 0]],
+/// Test that every received negotiation command only affects its own side of an option.
+#[test]
+fn test_option_state_sides_independent() {
+  let states = [QState::No, QState::Yes, QState::WantNo, QState::WantYes];
+  let queues = [QQueue::Empty, QQueue::Opposite];
+  let sides: Vec<SideState> = states
+    .iter()
+    .flat_map(|state| {
+      queues
+        .iter()
+        .map(move |queue| SideState::new(*state, *queue))
     })
-  }
+    .collect();
 
-  #[test]
-  fn test_parser_diff4() {
-    test_app(&TelnetApplication {
-      options: vec![],
-      received_data: vec![vec![255, 250, 255, 255, 240, 250]],
-    })
-  }
+  for command in [cmd::WILL, cmd::WONT, cmd::DO, cmd::DONT] {
+    for support in 0..4 {
+      for local in &sides {
+        for remote in &sides {
+          let mut parser = Parser::new();
+          if support & 1 != 0 {
+            parser.options.support_local(opt::GMCP);
+          }
+          if support & 2 != 0 {
+            parser.options.support_remote(opt::GMCP);
+          }
+          parser.options.set_local_side(opt::GMCP, *local);
+          parser.options.set_remote_side(opt::GMCP, *remote);
 
-  #[test]
-  fn test_parser_diff5() {
-    test_app(&TelnetApplication {
-      options: vec![],
-      received_data: vec![vec![255, 250, 255, 240, 0]],
-    })
-  }
+          parser.receive(&[cmd::IAC, command, opt::GMCP]);
 
-  #[test]
-  fn test_parser_diff6() {
-    test_app(&TelnetApplication {
-      options: vec![],
-      received_data: vec![vec![240, 255, 250, 255, 240, 0]],
-    })
+          let state = parser.option_state(opt::GMCP);
+          if command == cmd::WILL || command == cmd::WONT {
+            assert_eq!(state.local, *local, "{command} changed local side");
+          } else {
+            assert_eq!(state.remote, *remote, "{command} changed remote side");
+          }
+        }
+      }
+    }
   }
+}
 
-  #[test]
-  fn test_parser_diff7() {
-    test_app(&TelnetApplication {
-      options: vec![],
-      received_data: vec![vec![255]],
-    })
-  }
+/// Test that DO enables only the local side, and WILL only the remote side.
+#[test]
+fn test_option_state_do_will() {
+  let mut parser = Parser::new();
+  parser.options.support(opt::LINEMODE);
 
-  #[test]
-  fn test_parser_diff8() {
-    test_app(&TelnetApplication {
-      options: vec![],
-      received_data: vec![vec![255, 252, 0]],
-    })
-  }
+  parser.receive(&[cmd::IAC, cmd::DO, opt::LINEMODE]);
+  assert!(parser.local_enabled(opt::LINEMODE));
+  assert!(!parser.remote_enabled(opt::LINEMODE));
+  assert!(!parser.linemode_enabled());
 
-  #[test]
-  fn test_parser_diff9() {
-    test_app(&TelnetApplication {
-      options: vec![],
-      received_data: vec![vec![254, 255, 255, 255, 254, 0]],
-    })
+  parser.receive(&[cmd::IAC, cmd::WILL, opt::LINEMODE]);
+  assert!(parser.local_enabled(opt::LINEMODE));
+  assert!(parser.remote_enabled(opt::LINEMODE));
+  assert!(parser.linemode_enabled());
+
+  parser.receive(&[cmd::IAC, cmd::DONT, opt::LINEMODE]);
+  assert!(!parser.local_enabled(opt::LINEMODE));
+  assert!(parser.remote_enabled(opt::LINEMODE));
+
+  parser.receive(&[cmd::IAC, cmd::WONT, opt::LINEMODE]);
+  assert_eq!(parser.option_state(opt::LINEMODE), OptionState::default());
+}
+
+/// Test a client receiving and sending GMCP messages once the server enabled GMCP.
+#[test]
+fn test_gmcp_message() {
+  let mut parser = Parser::new();
+  parser.options.support_remote(opt::GMCP);
+  let hello = GmcpMessage::core_hello("blightmud", "5.0.0");
+  assert_eq!(parser.send_gmcp(&hello), None);
+  parser.receive(&[cmd::IAC, cmd::WILL, opt::GMCP]);
+
+  let events = parser.receive(
+    &[
+      &[cmd::IAC, cmd::SB, opt::GMCP][..],
+      b"Char.Vitals {\"hp\": 100}",
+      &[cmd::IAC, cmd::SE][..],
+    ]
+    .concat(),
+  );
+  let msg = match events.as_slice() {
+    [TelnetEvents::Subnegotiation(sub)] => GmcpMessage::try_from(sub).unwrap(),
+    _ => panic!("expected a single subnegotiation, got {:?}", events),
+  };
+  assert_eq!(msg.package, "Char");
+  assert_eq!(msg.message, "Vitals");
+  assert_eq!(msg.payload.as_deref(), Some("{\"hp\": 100}"));
+
+  for msg in [hello, GmcpMessage::core_supports_set(&[("Char", 1)])] {
+    let sub = TelnetSubnegotiation::new(opt::GMCP, msg.to_bytes());
+    assert_eq!(
+      parser.send_gmcp(&msg),
+      Some(TelnetEvents::DataSend(sub.to_bytes()))
+    );
   }
+}
 
-  #[test]
-  fn test_parser_diff10() {
-    test_app(&TelnetApplication {
-      options: vec![(255, 254), (1, 0)],
-      received_data: vec![vec![255, 253, 255]],
-    })
+/// Test a client sending and receiving MSDP variables once the server enabled MSDP.
+#[test]
+fn test_msdp_message() {
+  let mut parser = Parser::new();
+  parser.options.support_remote(opt::MSDP);
+  let report = MsdpVariable::report(&["HEALTH"]);
+  assert_eq!(parser.send_msdp(std::slice::from_ref(&report)), None);
+  parser.receive(&[cmd::IAC, cmd::WILL, opt::MSDP]);
+
+  assert_eq!(
+    parser.send_msdp(&[report]).unwrap().to_bytes(),
+    &[
+      &[cmd::IAC, cmd::SB, opt::MSDP, msdp::VAR][..],
+      b"REPORT",
+      &[msdp::VAL],
+      b"HEALTH",
+      &[cmd::IAC, cmd::SE],
+    ]
+    .concat()[..]
+  );
+
+  let events = parser.receive(
+    &[
+      &[cmd::IAC, cmd::SB, opt::MSDP, msdp::VAR][..],
+      b"HEALTH",
+      &[msdp::VAL],
+      b"97",
+      &[cmd::IAC, cmd::SE],
+    ]
+    .concat(),
+  );
+  let vars = match events.as_slice() {
+    [TelnetEvents::Subnegotiation(sub)] => msdp::decode(&sub.buffer).unwrap(),
+    _ => panic!("expected a single subnegotiation, got {:?}", events),
+  };
+  assert_eq!(vars, vec![MsdpVariable::new("HEALTH", "97")]);
+}
+
+/// Test a server sending MSSP variables once the client asks for them.
+#[test]
+fn test_mssp_send() {
+  let mut server = Parser::new();
+  server.options.support_local(opt::MSSP);
+  let mssp: Mssp = vec![(mssp::var::NAME, "Test MUD"), (mssp::var::PLAYERS, "3")]
+    .into_iter()
+    .collect();
+  assert_eq!(server.send_mssp(&mssp), None);
+
+  server.receive(&[cmd::IAC, cmd::DO, opt::MSSP]);
+  let sent = server.send_mssp(&mssp).unwrap().to_bytes();
+  assert_eq!(&sent[..3], &[cmd::IAC, cmd::SB, opt::MSSP]);
+  assert_eq!(&sent[sent.len() - 2..], &[cmd::IAC, cmd::SE]);
+
+  let decoded = Mssp::decode(&sent[3..sent.len() - 2]);
+  assert_eq!(decoded.name(), Some("Test MUD"));
+  assert_eq!(decoded.players(), Some(3));
+}
+
+/// Test a client receiving MSSP variables from a server that announced MSSP with WILL.
+#[test]
+fn test_mssp_after_will() {
+  let mut server = Parser::new();
+  let mut client = Parser::new();
+  server.options.support_local(opt::MSSP);
+  client.options.support_remote(opt::MSSP);
+  let will = server._will(opt::MSSP).unwrap().to_bytes();
+  let reply: Vec<u8> = client
+    .receive(&will)
+    .into_iter()
+    .filter(|ev| matches!(ev, TelnetEvents::DataSend(_)))
+    .flat_map(|ev| ev.to_bytes())
+    .collect();
+  assert_eq!(reply, vec![cmd::IAC, cmd::DO, opt::MSSP]);
+  server.receive(&reply);
+
+  let mssp: Mssp = vec![(mssp::var::NAME, "Test MUD")].into_iter().collect();
+  let sent = server.send_mssp(&mssp).unwrap().to_bytes();
+  let events = client.receive(&sent);
+  let decoded = match events.as_slice() {
+    [TelnetEvents::Subnegotiation(sub)] => Mssp::decode(&sub.buffer),
+    _ => panic!("expected a single subnegotiation, got {:?}", events),
+  };
+  assert_eq!(decoded.name(), Some("Test MUD"));
+
+  // The server only accepts MSSP from the end that enabled it.
+  assert_eq!(server.receive(&sent), vec![]);
+}
+
+/// Test overriding the side an option must be enabled on for its subnegotiations.
+#[test]
+fn test_subnegotiation_policy() {
+  let mut instance = Parser::new();
+  instance.options.support_remote(opt::GMCP);
+  let sub = TelnetSubnegotiation::new(opt::GMCP, Bytes::from_static(b"Core.Ping"));
+  let data = sub.clone().to_bytes();
+  assert_eq!(
+    instance.subnegotiation_policy(opt::GMCP),
+    SubnegotiationPolicy::Either
+  );
+  assert_eq!(instance.receive(&data), vec![]);
+
+  instance.set_subnegotiation_policy(opt::GMCP, Some(SubnegotiationPolicy::Always));
+  assert_eq!(
+    instance.receive(&data),
+    vec![TelnetEvents::Subnegotiation(sub.clone())]
+  );
+
+  instance.receive(&[cmd::IAC, cmd::WILL, opt::GMCP]);
+  instance.set_subnegotiation_policy(opt::GMCP, Some(SubnegotiationPolicy::Local));
+  assert_eq!(instance.receive(&data), vec![]);
+  instance.set_subnegotiation_policy(opt::GMCP, None);
+  assert_eq!(
+    instance.receive(&data),
+    vec![TelnetEvents::Subnegotiation(sub)]
+  );
+}
+
+/// Test that the window size is only sent while NAWS is enabled, and re-sent once it is.
+#[test]
+fn test_naws_window_size() {
+  let mut parser = Parser::new();
+  parser.options.support_local(opt::NAWS);
+
+  // NAWS isn't enabled yet, so nothing is sent.
+  assert_eq!(parser.send_window_size(WindowSize::new(80, 24)), None);
+  assert_eq!(parser.send_window_size(WindowSize::new(255, 50)), None);
+  assert_eq!(parser.window_size(), Some(WindowSize::new(255, 50)));
+
+  // Once the server asks for NAWS the last known size is sent after the WILL.
+  let events = parser.receive(&[cmd::IAC, cmd::DO, opt::NAWS]);
+  assert_eq!(
+    events,
+    vec![
+      TelnetEvents::DataSend(Bytes::copy_from_slice(&[cmd::IAC, cmd::WILL, opt::NAWS])),
+      TelnetEvents::Negotiation(TelnetNegotiation::new(cmd::DO, opt::NAWS)),
+      TelnetEvents::DataSend(Bytes::copy_from_slice(&[
+        cmd::IAC,
+        cmd::SB,
+        opt::NAWS,
+        0,
+        cmd::IAC,
+        cmd::IAC,
+        0,
+        50,
+        cmd::IAC,
+        cmd::SE
+      ])),
+    ]
+  );
+
+  // A repeated DO doesn't re-send it.
+  assert_eq!(parser.receive(&[cmd::IAC, cmd::DO, opt::NAWS]), vec![]);
+
+  let sent = parser.send_window_size(WindowSize::new(120, 40)).unwrap();
+  let sub = TelnetSubnegotiation::new(opt::NAWS, Bytes::copy_from_slice(&[0, 120, 0, 40]));
+  assert_eq!(sent.to_bytes(), sub.clone().to_bytes());
+  assert_eq!(WindowSize::try_from(&sub), Ok(WindowSize::new(120, 40)));
+}
+
+/// Test a client answering a server's TTYPE probe with the MTTS cycle.
+#[test]
+fn test_ttype_mtts_cycle() {
+  let mut client = Parser::new();
+  let mut server = Parser::new();
+  client.options.support_local(opt::TTYPE);
+  server.options.support_remote(opt::TTYPE);
+  client.set_ttype_responder(Some(TtypeResponder::new(
+    "BLIGHTMUD",
+    "XTERM",
+    MttsFlags::ANSI | MttsFlags::UTF8 | MttsFlags::TRUECOLOR,
+  )));
+
+  // The probe needs TTYPE to be enabled.
+  assert_eq!(server.probe_ttype(), None);
+  let request = server._do(opt::TTYPE).unwrap().to_bytes();
+  let reply = client.receive(&request);
+  assert_eq!(
+    handle_events(server.receive(&reply[0].clone().to_bytes())),
+    events![]
+  );
+  assert!(server.remote_enabled(opt::TTYPE));
+
+  let mut to_client = server.probe_ttype().unwrap().to_bytes();
+  let mut replies = Vec::new();
+  while !to_client.is_empty() {
+    let events = client.receive(&to_client);
+    assert_eq!(
+      handle_events(events.clone()),
+      events![Event::Subnegotiation, Event::Send]
+    );
+    let to_server = events[1].clone().to_bytes();
+    replies.push(to_server.clone());
+    to_client = server
+      .receive(&to_server)
+      .into_iter()
+      .filter(|ev| matches!(ev, TelnetEvents::DataSend(_)))
+      .flat_map(|ev| ev.to_bytes())
+      .collect();
+  }
+  assert_eq!(
+    replies[0],
+    TelnetSubnegotiation::from(TtypeMessage::Is("BLIGHTMUD".to_string())).to_bytes()
+  );
+  assert_eq!(replies.len(), 3);
+
+  let probe = server.ttype_probe().unwrap();
+  assert!(probe.is_complete());
+  assert_eq!(probe.client_name(), Some("BLIGHTMUD"));
+  assert_eq!(probe.terminal_type(), Some("XTERM"));
+  assert_eq!(probe.mtts().unwrap().bits(), 261);
+  assert_eq!(client.ttype_responder().unwrap().position(), 3);
+
+  // Re-enabling TTYPE restarts the cycle.
+  client.receive(&[cmd::IAC, cmd::DONT, opt::TTYPE]);
+  client.receive(&[cmd::IAC, cmd::DO, opt::TTYPE]);
+  assert_eq!(client.ttype_responder().unwrap().position(), 0);
+}
+
+/// Test CHARSET requests being answered from the preference list.
+#[test]
+fn test_charset_responder() {
+  let mut client = Parser::new();
+  let mut server = Parser::new();
+  client.options.support_local(opt::CHARSET);
+  server.options.support_remote(opt::CHARSET);
+  client.set_charset_responder(Some(CharsetResponder::new(vec!["UTF-8", "US-ASCII"])));
+
+  assert_eq!(server.request_charset(&["UTF-8"]), None);
+  let request = server._do(opt::CHARSET).unwrap().to_bytes();
+  let reply = client.receive(&request)[0].clone().to_bytes();
+  server.receive(&reply);
+
+  let request = server
+    .request_charset(&["ISO-8859-1", "utf-8", "US-ASCII"])
+    .unwrap()
+    .to_bytes();
+  let events = client.receive(&request);
+  assert_eq!(
+    events[1],
+    TelnetEvents::DataSend(
+      TelnetSubnegotiation::from(CharsetMessage::Accepted("utf-8".to_string())).to_bytes()
+    )
+  );
+  assert_eq!(client.charset(), Some("utf-8"));
+  assert_eq!(
+    handle_events(server.receive(&events[1].clone().to_bytes())),
+    events![Event::Subnegotiation]
+  );
+  assert_eq!(server.charset(), Some("utf-8"));
+
+  // Nothing acceptable is rejected.
+  let request = server.request_charset(&["CP437"]).unwrap().to_bytes();
+  let events = client.receive(&request);
+  assert_eq!(
+    events[1],
+    TelnetEvents::DataSend(TelnetSubnegotiation::from(CharsetMessage::Rejected).to_bytes())
+  );
+  assert_eq!(client.charset(), Some("utf-8"));
+
+  // Disabling CHARSET forgets the agreed character set.
+  client.receive(&[cmd::IAC, cmd::DONT, opt::CHARSET]);
+  assert_eq!(client.charset(), None);
+}
+
+/// Test NEW-ENVIRON SEND requests being answered with MNES variables.
+#[test]
+fn test_new_environ_mnes() {
+  let mut client = Parser::new();
+  client.options.support_local(opt::NEWENVIRON);
+  let mut environ = EnvironStore::new();
+  environ.set_var(mnes::CLIENT_NAME, "Blightmud");
+  environ.set_var(mnes::CLIENT_VERSION, "5.3.1");
+  environ.set_var(mnes::CHARSET, "UTF-8");
+  client.set_environ(Some(environ));
+
+  let send = TelnetSubnegotiation::from(EnvironMessage::Send(vec![
+    EnvRequest::new(VarKind::Var, Some(mnes::CLIENT_NAME)),
+    EnvRequest::new(VarKind::Var, Some(mnes::IPADDRESS)),
+  ]))
+  .to_bytes();
+  // Nothing is answered before NEW-ENVIRON is enabled.
+  assert_eq!(client.receive(&send), vec![]);
+  client.receive(&[cmd::IAC, cmd::DO, opt::NEWENVIRON]);
+
+  let events = client.receive(&send);
+  assert_eq!(
+    handle_events(events.clone()),
+    events![Event::Subnegotiation, Event::Send]
+  );
+  let reply = TelnetSubnegotiation::from(EnvironMessage::Is(vec![
+    EnvVar::new(
+      VarKind::Var,
+      mnes::CLIENT_NAME,
+      Some("Blightmud".to_string()),
+    ),
+    EnvVar::new(VarKind::Var, mnes::IPADDRESS, None),
+  ]));
+  assert_eq!(events[1], TelnetEvents::DataSend(reply.clone().to_bytes()));
+
+  // The server receives the reply for the remotely enabled option.
+  let mut server = Parser::new();
+  server.options.support_remote(opt::NEWENVIRON);
+  server.receive(&[cmd::IAC, cmd::WILL, opt::NEWENVIRON]);
+  let events = server.receive(&reply.clone().to_bytes());
+  assert_eq!(events, vec![TelnetEvents::Subnegotiation(reply)]);
+
+  let info = client
+    .set_environ_var(VarKind::Var, mnes::CHARSET, "ISO-8859-1")
+    .unwrap();
+  assert_eq!(
+    info,
+    TelnetEvents::DataSend(
+      TelnetSubnegotiation::from(EnvironMessage::Info(vec![EnvVar::new(
+        VarKind::Var,
+        mnes::CHARSET,
+        Some("ISO-8859-1".to_string())
+      )]))
+      .to_bytes()
+    )
+  );
+  assert_eq!(
+    client.environ().unwrap().get(VarKind::Var, mnes::CHARSET),
+    Some("ISO-8859-1")
+  );
+}
+
+/// Test the LINEMODE MODE and SLC handshakes.
+#[test]
+fn test_linemode_handshake() {
+  let mut client = Parser::new();
+  let mut server = Parser::new();
+  client.options.support_local(opt::LINEMODE);
+  server.options.support_remote(opt::LINEMODE);
+  let request = server._do(opt::LINEMODE).unwrap().to_bytes();
+  let reply = client.receive(&request)[0].clone().to_bytes();
+  server.receive(&reply);
+  assert!(server.linemode_enabled());
+
+  let mode = Mode::EDIT | Mode::TRAPSIG;
+  let request = server.send_linemode_mode(mode).unwrap().to_bytes();
+  assert_eq!(server.linemode().mode, Mode::empty());
+  let events = client.receive(&request);
+  assert_eq!(
+    events[1],
+    TelnetEvents::DataSend(
+      TelnetSubnegotiation::from(LinemodeMessage::Mode(mode | Mode::ACK)).to_bytes()
+    )
+  );
+  assert_eq!(client.linemode().mode, mode);
+  // The acknowledgement isn't answered, and a repeated MODE isn't acknowledged again.
+  assert_eq!(
+    handle_events(server.receive(&events[1].clone().to_bytes())),
+    events![Event::Subnegotiation]
+  );
+  assert_eq!(server.linemode().mode, mode);
+  assert_eq!(
+    handle_events(client.receive(&request)),
+    events![Event::Subnegotiation]
+  );
+
+  let triplets = [
+    SlcTriplet::new(slc::EC, slc::VALUE, 0x7F),
+    SlcTriplet::new(slc::IP, slc::VALUE | slc::FLUSHIN, cmd::IAC),
+  ];
+  let request = server.send_slc(&triplets).unwrap().to_bytes();
+  let events = client.receive(&request);
+  assert_eq!(
+    handle_events(events.clone()),
+    events![Event::Subnegotiation, Event::Send]
+  );
+  let table = &client.linemode().slc;
+  assert_eq!(table.function_for(0x7F), Some(slc::EC));
+  assert_eq!(table.value(slc::IP), Some(cmd::IAC));
+  assert_eq!(table.value(slc::EL), None);
+  assert_eq!(
+    handle_events(server.receive(&events[1].clone().to_bytes())),
+    events![Event::Subnegotiation]
+  );
+
+  // Disabling LINEMODE resets the state.
+  client.receive(&[cmd::IAC, cmd::DONT, opt::LINEMODE]);
+  assert_eq!(client.linemode().mode, Mode::empty());
+  assert_eq!(client.linemode().slc.value(slc::EC), None);
+}
+
+/// Test a client answering the ATCP handshake once the server enabled ATCP.
+#[test]
+fn test_atcp_handshake() {
+  let mut client = Parser::new();
+  client.options.support_remote(opt::ATCP);
+  let hello = atcp::hello("Blightmud", "5.3", &[("room_brief", 1)]);
+  assert_eq!(client.send_atcp_handshake(hello.clone()), None);
+
+  let events = client.receive(&[cmd::IAC, cmd::WILL, opt::ATCP]);
+  assert_eq!(
+    events[0],
+    TelnetEvents::DataSend(Bytes::from_static(&[cmd::IAC, cmd::DO, opt::ATCP]))
+  );
+  assert_eq!(
+    client.send_atcp_handshake(hello.clone()),
+    Some(TelnetEvents::DataSend(
+      TelnetSubnegotiation::new(opt::ATCP, hello).to_bytes()
+    ))
+  );
+
+  let challenge = TelnetSubnegotiation::new(opt::ATCP, Bytes::from_static(b"Auth.Request CH 1234"));
+  let events = client.receive(&challenge.clone().to_bytes());
+  assert_eq!(
+    events,
+    vec![TelnetEvents::Subnegotiation(challenge.clone())]
+  );
+  let request = AtcpMessage::try_from(&challenge).unwrap();
+  assert_eq!(request.auth_challenge(), Some("1234"));
+  let auth = atcp::auth("key", "Blightmud", "5.3");
+  assert_eq!(
+    client.send_atcp_handshake(auth.clone()),
+    Some(TelnetEvents::DataSend(
+      TelnetSubnegotiation::new(opt::ATCP, auth).to_bytes()
+    ))
+  );
+
+  let ping = AtcpMessage::new("Core", "Ping", None);
+  assert!(client.send_atcp(&ping).is_some());
+  let oob = OobMessage::try_from(&TelnetSubnegotiation::from(ping)).unwrap();
+  assert!(client.send_oob(oob).is_some());
+}
+
+/// Test GMCP and ATCP messages being handled through the common out-of-band representation.
+#[test]
+fn test_oob_messages() {
+  let mut instance = Parser::new();
+  instance.options.support_local(opt::GMCP);
+  instance.options.support_local(opt::ATCP);
+  instance.receive(&[cmd::IAC, cmd::DO, opt::GMCP, cmd::IAC, cmd::DO, opt::ATCP]);
+
+  let hello = instance
+    .subnegotiation(
+      opt::ATCP,
+      atcp::hello("Blightmud", "5.3", &[("room_brief", 1)]),
+    )
+    .unwrap();
+  assert_eq!(
+    hello.to_bytes(),
+    TelnetSubnegotiation::new(
+      opt::ATCP,
+      Bytes::from_static(
+        b"hello Blightmud 5.3
+room_brief 1"
+      )
+    )
+    .to_bytes()
+  );
+
+  let data = [
+    &[cmd::IAC, cmd::SB, opt::ATCP][..],
+    b"Room.Num 1234",
+    &[cmd::IAC, cmd::SE, cmd::IAC, cmd::SB, opt::GMCP],
+    b"Room.Info {\"num\":1234}",
+    &[cmd::IAC, cmd::SE],
+  ]
+  .concat();
+  let messages: Vec<OobMessage> = instance
+    .receive(&data)
+    .iter()
+    .filter_map(|ev| match ev {
+      TelnetEvents::Subnegotiation(sub) => OobMessage::try_from(sub).ok(),
+      _ => None,
+    })
+    .collect();
+  assert_eq!(messages.len(), 2);
+  assert_eq!(messages[0].protocol, OobProtocol::Atcp);
+  assert_eq!(messages[0].name(), "Room.Num");
+  assert_eq!(messages[1].protocol, OobProtocol::Gmcp);
+  assert_eq!(messages[1].payload.as_deref(), Some("{\"num\":1234}"));
+
+  let sent = instance.send_oob(messages[0].clone()).unwrap();
+  assert_eq!(
+    sent,
+    instance
+      .send_atcp(&AtcpMessage::new("Room", "Num", Some("1234".to_string())))
+      .unwrap()
+  );
+}
+
+/// Test MXP tokenizing received data split across receives.
+#[test]
+fn test_mxp_tokenizer() {
+  let mut instance = Parser::new();
+  instance.options.support_remote(opt::MXP);
+  let events = instance.receive(&[cmd::IAC, cmd::WILL, opt::MXP]);
+  assert_eq!(
+    events[0].clone().to_bytes(),
+    Bytes::from_static(&[cmd::IAC, cmd::DO, opt::MXP])
+  );
+  assert!(instance.remote_enabled(opt::MXP));
+
+  let mut mxp = MxpTokenizer::new();
+  let mut tokens = Vec::new();
+  for chunk in [&b"\x1b[1z<SEND hr"[..], b"ef=\"n\">north</SEND>\n<SEND>x"] {
+    for event in instance.receive(chunk) {
+      if let TelnetEvents::DataReceive(data) = event {
+        tokens.extend(mxp.feed(&data));
+      }
+    }
+  }
+  assert_eq!(tokens.len(), 5);
+  assert_eq!(tokens[0], MxpToken::Mode(LineMode::Secure));
+  match &tokens[1] {
+    MxpToken::StartTag(tag) => {
+      assert_eq!(tag.name, "SEND");
+      assert_eq!(tag.get("href"), Some("n"));
+    }
+    token => panic!("unexpected token {:?}", token),
+  }
+  assert_eq!(tokens[2], MxpToken::Text(Bytes::from_static(b"north")));
+  assert_eq!(tokens[3], MxpToken::EndTag("SEND".to_string()));
+  // The secure line ended, so the second `SEND` is dropped.
+  assert_eq!(tokens[4], MxpToken::Text(Bytes::from_static(b"\nx")));
+}
+
+/// A handler recording events without allocating them.
+#[derive(Default)]
+struct CountingHandler {
+  data: usize,
+  iacs: usize,
+  negotiations: usize,
+  subnegotiations: Vec<u8>,
+  sent: Vec<Bytes>,
+}
+
+impl TelnetHandler for CountingHandler {
+  fn on_data(&mut self, data: &[u8]) {
+    self.data += data.len();
+  }
+
+  fn on_iac(&mut self, _iac: TelnetIAC) {
+    self.iacs += 1;
+  }
+
+  fn on_negotiation(&mut self, _negotiation: TelnetNegotiation) {
+    self.negotiations += 1;
+  }
+
+  fn on_subnegotiation(&mut self, option: u8, _data: &[u8]) {
+    self.subnegotiations.push(option);
+  }
+
+  fn on_send(&mut self, data: Bytes) {
+    self.sent.push(data);
+  }
+}
+
+/// Test streaming events to a handler, in the same order as `receive` returns them.
+#[test]
+fn test_receive_with() {
+  let data = [
+    &b"Hello"[..],
+    &[cmd::IAC, cmd::GA, cmd::IAC, cmd::WILL, opt::GMCP],
+    &[cmd::IAC, cmd::DO, opt::GMCP, cmd::IAC, cmd::SB, opt::GMCP],
+    b"Core.Hello {}",
+    &[cmd::IAC, cmd::SE],
+    b"world",
+    &[cmd::IAC, cmd::SB, opt::GMCP],
+  ]
+  .concat();
+
+  let mut parser = Parser::new();
+  parser.options.support(opt::GMCP);
+  let mut handler = CountingHandler::default();
+  parser.receive_with(&data, &mut handler);
+  assert_eq!(handler.data, 10);
+  assert_eq!(handler.iacs, 1);
+  assert_eq!(handler.negotiations, 2);
+  assert_eq!(handler.subnegotiations, vec![opt::GMCP]);
+  assert_eq!(
+    handler.sent,
+    vec![
+      Bytes::from_static(&[cmd::IAC, cmd::DO, opt::GMCP]),
+      Bytes::from_static(&[cmd::IAC, cmd::WILL, opt::GMCP]),
+    ]
+  );
+
+  let mut parser = Parser::new();
+  parser.options.support(opt::GMCP);
+  let mut events = Vec::new();
+  parser.receive_with(&data, &mut events);
+  let mut parser = Parser::new();
+  parser.options.support(opt::GMCP);
+  assert_eq!(events, parser.receive(&data));
+
+  // The partial subnegotiation completes in the next receive.
+  let mut replayed = Vec::new();
+  for event in parser.receive(&[b"x", &[cmd::IAC, cmd::SE][..]].concat()) {
+    event.dispatch(&mut replayed);
+  }
+  assert_eq!(
+    replayed,
+    vec![TelnetEvents::Subnegotiation(TelnetSubnegotiation::new(
+      opt::GMCP,
+      Bytes::from_static(b"x")
+    ))]
+  );
+}
+
+/// Test sequences split at every byte being parsed the same as when received at once.
+#[test]
+fn test_receive_byte_at_a_time() {
+  let payload: Vec<u8> = (0..=255).cycle().take(4096).collect();
+  let data = [
+    &b"a"[..],
+    &[cmd::IAC, cmd::IAC, cmd::IAC, cmd::DO, opt::GMCP],
+    &TelnetSubnegotiation::new(opt::GMCP, Bytes::from(payload)).to_bytes(),
+    &[cmd::IAC, cmd::SB, opt::GMCP, cmd::IAC, cmd::SE],
+    b"b",
+  ]
+  .concat();
+
+  let mut parser = Parser::new();
+  parser.options.support(opt::GMCP);
+  let expected = parser.receive(&data);
+  // The escaped IAC is passed on as a single byte, following the data before it.
+  assert_eq!(
+    &expected[..2],
+    &[
+      TelnetEvents::DataReceive(Bytes::from_static(b"a")),
+      TelnetEvents::DataReceive(Bytes::from_static(&[cmd::IAC])),
+    ]
+  );
+  assert!(
+    matches!(&expected[4], TelnetEvents::Subnegotiation(sub) if sub.buffer.len() == 4096 + 16)
+  );
+  assert!(matches!(&expected[5], TelnetEvents::Subnegotiation(sub) if sub.buffer.is_empty()));
+
+  let mut parser = Parser::new();
+  parser.options.support(opt::GMCP);
+  let mut events = Vec::new();
+  for byte in &data {
+    parser.receive_with(&[*byte], &mut events);
+  }
+  // Data is passed on as it arrives rather than joined.
+  let mut received = Vec::new();
+  events.retain(|event| match event {
+    TelnetEvents::DataReceive(data) => {
+      received.extend_from_slice(data);
+      false
+    }
+    _ => true,
+  });
+  assert_eq!(received, vec![b'a', cmd::IAC, b'b']);
+  let expected: Vec<_> = expected
+    .into_iter()
+    .filter(|event| !matches!(event, TelnetEvents::DataReceive(_)))
+    .collect();
+  assert_eq!(events, expected);
+}
+
+/// Test pulling events one at a time from fed data.
+#[test]
+fn test_feed_next_event() {
+  let mut parser = Parser::new();
+  parser.options.support_local(opt::GMCP);
+  parser.feed(
+    &[
+      &b"Hello"[..],
+      &[cmd::IAC, cmd::DO, opt::GMCP, cmd::IAC, cmd::GA],
+    ]
+    .concat(),
+  );
+  assert_eq!(
+    parser.next_event(),
+    Some(TelnetEvents::DataReceive(Bytes::from_static(b"Hello")))
+  );
+
+  // The reply to the negotiation is followed by the negotiation itself.
+  assert_eq!(
+    parser.next_event(),
+    Some(TelnetEvents::DataSend(Bytes::from_static(&[
+      cmd::IAC,
+      cmd::WILL,
+      opt::GMCP
+    ])))
+  );
+  assert!(parser.local_enabled(opt::GMCP));
+
+  // Events still to be pulled come before those of data received later.
+  let events = parser.receive(b"world");
+  assert_eq!(
+    events,
+    vec![
+      TelnetEvents::Negotiation(TelnetNegotiation::new(cmd::DO, opt::GMCP)),
+      TelnetEvents::IAC(TelnetIAC::new(cmd::GA)),
+      TelnetEvents::DataReceive(Bytes::from_static(b"world")),
+    ]
+  );
+  assert_eq!(parser.next_event(), None);
+
+  // A partial subnegotiation is kept until the rest is fed.
+  parser.feed(&[cmd::IAC, cmd::SB, opt::GMCP, b'a']);
+  assert_eq!(parser.events().count(), 0);
+  parser.feed(&[b'b', cmd::IAC, cmd::SE, b'c']);
+  assert_eq!(
+    parser.events().collect::<Vec<_>>(),
+    vec![
+      TelnetEvents::Subnegotiation(TelnetSubnegotiation::new(
+        opt::GMCP,
+        Bytes::from_static(b"ab")
+      )),
+      TelnetEvents::DataReceive(Bytes::from_static(b"c")),
+    ]
+  );
+}
+
+/// Test pulling events from data fed in parts split after an IAC, as when receiving it.
+#[test]
+fn test_feed_split_after_iac() {
+  let parts = [
+    &b"x\xff"[..],
+    &[cmd::IAC, cmd::IAC, b'a', cmd::IAC, cmd::GA],
+  ];
+  let mut pulled = Parser::new();
+  let mut events = Vec::new();
+  for part in parts {
+    pulled.feed(part);
+    events.extend(pulled.events());
+  }
+  let mut received = Parser::new();
+  let expected: Vec<_> = parts
+    .iter()
+    .flat_map(|part| received.receive(part))
+    .collect();
+  assert_eq!(events, expected);
+  assert_eq!(
+    events.last(),
+    Some(&TelnetEvents::IAC(TelnetIAC::new(cmd::GA)))
+  );
+}
+
+/// Test commands being found at every position of a long run of text.
+#[test]
+fn test_long_text_runs() {
+  for position in 0..40 {
+    let text = [b'x'; 40];
+    let data = [
+      &text[..position],
+      &[cmd::IAC, cmd::GA],
+      &text[position..],
+      &[cmd::IAC, cmd::IAC],
+      &text[..position],
+    ]
+    .concat();
+    let mut parser = Parser::new();
+    let events = parser.receive(&data);
+    let mut expected = Vec::new();
+    if position > 0 {
+      expected.push(TelnetEvents::DataReceive(Bytes::from(
+        text[..position].to_vec(),
+      )));
+    }
+    expected.push(TelnetEvents::IAC(TelnetIAC::new(cmd::GA)));
+    expected.push(TelnetEvents::DataReceive(Bytes::from(
+      text[position..].to_vec(),
+    )));
+    expected.push(TelnetEvents::DataReceive(Bytes::from(
+      [&[cmd::IAC][..], &text[..position]].concat(),
+    )));
+    assert_eq!(events, expected, "IAC at {}", position);
+  }
+}
+
+/// Test subnegotiations exceeding the limits of the parser config.
+#[test]
+fn test_subnegotiation_limits() {
+  fn parser(config: ParserConfig) -> Parser {
+    let mut parser = Parser::new();
+    parser.options.support_local(opt::GMCP);
+    parser.receive(&[cmd::IAC, cmd::DO, opt::GMCP]);
+    parser.set_config(ParserConfig {
+      report_protocol_errors: true,
+      ..config
+    });
+    parser
+  }
+  fn gmcp(content: &'static [u8]) -> TelnetEvents {
+    TelnetEvents::Subnegotiation(TelnetSubnegotiation::new(
+      opt::GMCP,
+      Bytes::from_static(content),
+    ))
+  }
+  let overflow = TelnetEvents::ProtocolError(TelnetError::BufferOverflow(opt::GMCP));
+  let x = TelnetEvents::DataReceive(Bytes::from_static(b"x"));
+  let mut long = vec![cmd::IAC, cmd::SB, opt::GMCP];
+  long.extend_from_slice(b"abcdefgh");
+  long.extend_from_slice(&[cmd::IAC, cmd::IAC, b'i', cmd::IAC, cmd::SE]);
+  long.extend_from_slice(b"x");
+
+  // Content up to the limit is kept, and the subnegotiation ends at IAC SE as usual.
+  let config = ParserConfig::with_limit(4, OverflowPolicy::TruncateAndError);
+  let mut instance = parser(config);
+  assert_eq!(
+    instance.receive(&long),
+    vec![overflow.clone(), gmcp(b"abcd"), x.clone()]
+  );
+  // The same holds for content buffered a byte at a time.
+  let events: Vec<TelnetEvents> = long.iter().flat_map(|b| instance.receive(&[*b])).collect();
+  assert_eq!(events, vec![overflow.clone(), gmcp(b"abcd"), x.clone()]);
+
+  let mut instance = parser(ParserConfig::with_limit(4, OverflowPolicy::DiscardUntilSe));
+  assert_eq!(instance.receive(&long), vec![overflow.clone(), x.clone()]);
+  // Subnegotiations within the limits are not affected.
+  let short = [cmd::IAC, cmd::SB, opt::GMCP, b'a', cmd::IAC, cmd::SE];
+  assert_eq!(instance.receive(&short), vec![gmcp(b"a")]);
+
+  // Content is never passed on as data, and the first command ends the subnegotiation.
+  let mut instance = parser(ParserConfig::with_limit(4, OverflowPolicy::Reset));
+  assert_eq!(instance.receive(&long), vec![overflow.clone(), x.clone()]);
+  let mut unended = vec![cmd::IAC, cmd::SB, opt::GMCP];
+  unended.extend_from_slice(b"abcdefgh\r\n");
+  assert_eq!(instance.receive(&unended), vec![overflow.clone()]);
+  assert_eq!(
+    instance.receive(&[cmd::IAC, cmd::GA, b'x']),
+    vec![TelnetEvents::IAC(TelnetIAC::new(cmd::GA)), x.clone()]
+  );
+
+  // Only content kept between receives counts towards `max_buffered_bytes`.
+  let mut instance = parser(ParserConfig {
+    max_buffered_bytes: 4,
+    overflow_policy: OverflowPolicy::DiscardUntilSe,
+    ..ParserConfig::default()
+  });
+  assert_eq!(
+    instance.receive(&long),
+    vec![gmcp(b"abcdefgh\xff\xffi"), x.clone()]
+  );
+  assert_eq!(instance.receive(&long[..9]), vec![overflow]);
+  assert_eq!(instance.buffered_len(), 0);
+  assert_eq!(instance.receive(&long[9..]), vec![x.clone()]);
+
+  // Overflows are only reported once enabled, like other protocol errors.
+  let mut instance = parser(ParserConfig::with_limit(
+    4,
+    OverflowPolicy::TruncateAndError,
+  ));
+  instance.set_config(ParserConfig::with_limit(
+    4,
+    OverflowPolicy::TruncateAndError,
+  ));
+  assert_eq!(instance.receive(&long), vec![gmcp(b"abcd"), x]);
+}
+
+/// Test malformed input being reported once enabled in the parser config.
+#[test]
+fn test_protocol_errors() {
+  let mut instance = Parser::new();
+  instance.options.support_local(opt::GMCP);
+  instance.receive(&[cmd::IAC, cmd::DO, opt::GMCP]);
+  let malformed = [
+    &[cmd::IAC, cmd::SE, b'a'][..],
+    &[
+      cmd::IAC,
+      cmd::SB,
+      opt::GMCP,
+      b'x',
+      cmd::IAC,
+      cmd::NOP,
+      cmd::IAC,
+      cmd::SE,
+    ],
+    &[cmd::IAC, cmd::SB, opt::MSSP, b'x', cmd::IAC, cmd::SE],
+    &[cmd::IAC, 100],
+    &[
+      cmd::IAC,
+      cmd::SB,
+      opt::GMCP,
+      b'y',
+      cmd::IAC,
+      cmd::SB,
+      opt::GMCP,
+      b'z',
+      cmd::IAC,
+      cmd::SE,
+    ],
+    &[cmd::IAC, cmd::SB, cmd::IAC, cmd::SE],
+  ];
+  let events: Vec<TelnetEvents> = malformed
+    .iter()
+    .flat_map(|data| instance.receive(data))
+    .collect();
+  let gmcp = TelnetSubnegotiation::new(opt::GMCP, Bytes::from_static(&[b'x', cmd::IAC, cmd::NOP]));
+  let received = vec![
+    TelnetEvents::DataReceive(Bytes::from_static(b"a")),
+    TelnetEvents::Subnegotiation(gmcp),
+    TelnetEvents::Subnegotiation(TelnetSubnegotiation::new(
+      opt::GMCP,
+      Bytes::from_static(b"z"),
+    )),
+  ];
+  assert_eq!(events, received);
+
+  instance.set_config(ParserConfig {
+    report_protocol_errors: true,
+    ..ParserConfig::default()
+  });
+  let events: Vec<TelnetEvents> = malformed
+    .iter()
+    .flat_map(|data| instance.receive(data))
+    .collect();
+  assert_eq!(
+    events,
+    vec![
+      TelnetEvents::ProtocolError(TelnetError::UnexpectedSe),
+      received[0].clone(),
+      TelnetEvents::ProtocolError(TelnetError::InvalidSubnegotiationCommand(opt::GMCP)),
+      received[1].clone(),
+      TelnetEvents::ProtocolError(TelnetError::DisabledOption(opt::MSSP)),
+      TelnetEvents::ProtocolError(TelnetError::UnknownCommand(100)),
+      TelnetEvents::ProtocolError(TelnetError::TruncatedSubnegotiation(opt::GMCP)),
+      received[2].clone(),
+      TelnetEvents::ProtocolError(TelnetError::TruncatedSubnegotiation(cmd::IAC)),
+      TelnetEvents::ProtocolError(TelnetError::UnexpectedSe),
+    ]
+  );
+  assert_eq!(
+    TelnetError::DisabledOption(opt::MSSP).to_string(),
+    "subnegotiation received for disabled option MSSP"
+  );
+  assert_eq!(
+    TelnetError::TruncatedSubnegotiation(opt::GMCP).to_string(),
+    "subnegotiation for option GMCP cut short"
+  );
+}
+
+/// Test the two byte commands of RFC 854 and RFC 1184 being received as IAC events.
+#[test]
+fn test_two_byte_commands() {
+  let mut instance = Parser::new();
+  let events = instance.receive(
+    &[
+      &b"a"[..],
+      &[cmd::IAC, cmd::AYT],
+      b"b",
+      &[cmd::IAC, cmd::IP, cmd::IAC, cmd::DM],
+      b"c",
+      &[cmd::IAC, cmd::EOF, cmd::IAC, cmd::ABORT],
+    ]
+    .concat(),
+  );
+  let kinds: Vec<Option<TelnetCommand>> = events
+    .iter()
+    .map(|ev| match ev {
+      TelnetEvents::IAC(iac) => Some(iac.kind()),
+      _ => None,
+    })
+    .collect();
+  assert_eq!(
+    kinds,
+    vec![
+      None,
+      Some(TelnetCommand::Ayt),
+      None,
+      Some(TelnetCommand::Ip),
+      Some(TelnetCommand::Dm),
+      None,
+      Some(TelnetCommand::Eof),
+      Some(TelnetCommand::Abort),
+    ]
+  );
+  assert_eq!(
+    events[5],
+    TelnetEvents::DataReceive(Bytes::from_static(b"c"))
+  );
+
+  for code in 0..=u8::MAX {
+    let command = TelnetCommand::from(code);
+    assert_eq!(u8::from(command), code);
+    assert_eq!(
+      matches!(command, TelnetCommand::Unknown(_)),
+      code < cmd::EOF
+    );
+  }
+  assert!(TelnetCommand::Ayt.is_two_byte());
+  assert!(!TelnetCommand::Will.is_two_byte());
+  assert_eq!(
+    TelnetIAC::from(TelnetCommand::Ayt).to_bytes(),
+    Bytes::from_static(&[cmd::IAC, cmd::AYT])
+  );
+}
+
+/// Test the names of commands and options in events.
+#[test]
+fn test_display_names() {
+  let will = TelnetNegotiation::new(cmd::WILL, opt::GMCP);
+  assert_eq!(will.kind(), TelnetCommand::Will);
+  assert_eq!(will.option_kind(), TelnetOption::Gmcp);
+  assert_eq!(will.to_string(), "WILL GMCP");
+  assert_eq!(
+    TelnetNegotiation::new(cmd::DONT, 123).to_string(),
+    "DONT 123"
+  );
+  assert_eq!(TelnetIAC::new(cmd::AYT).to_string(), "AYT");
+  let sub = TelnetSubnegotiation::new(opt::NEWENVIRON, Bytes::from_static(b"\x01"));
+  assert_eq!(sub.option_kind(), TelnetOption::NewEnviron);
+  assert_eq!(sub.to_string(), "SB NEW-ENVIRON");
+
+  for code in 0..=u8::MAX {
+    assert_eq!(u8::from(TelnetOption::from(code)), code);
+  }
+  assert_eq!(TelnetOption::from(opt::MCCP2), TelnetOption::Mccp2);
+  assert_eq!(TelnetOption::from(100), TelnetOption::Unknown(100));
+  let option: u8 = TelnetOption::Mssp.into();
+  assert_eq!(option, opt::MSSP);
+}
+
+/// Test data being discarded up to the Data Mark of a Synch, with commands still processed.
+#[test]
+fn test_synch() {
+  let mut instance = Parser::new();
+  instance.options.support_local(opt::ECHO);
+  let dm = TelnetEvents::IAC(TelnetIAC::new(cmd::DM));
+
+  instance.begin_synch();
+  assert!(instance.is_synching());
+  let events = instance.receive(
+    &[
+      &b"discarded"[..],
+      &[cmd::IAC, cmd::IAC, cmd::IAC, cmd::DO, opt::ECHO],
+      b"also discarded",
+      &[cmd::IAC, cmd::AYT],
+    ]
+    .concat(),
+  );
+  assert_eq!(
+    events,
+    vec![
+      TelnetEvents::DataSend(Bytes::copy_from_slice(&[cmd::IAC, cmd::WILL, opt::ECHO])),
+      TelnetEvents::Negotiation(TelnetNegotiation::new(cmd::DO, opt::ECHO)),
+      TelnetEvents::IAC(TelnetIAC::new(cmd::AYT)),
+    ]
+  );
+  assert!(instance.is_synching());
+
+  // The Data Mark may arrive in a later receive, split from its IAC.
+  assert_eq!(instance.receive(&[b'x', cmd::IAC]), vec![]);
+  assert_eq!(
+    instance.receive(&[&[cmd::DM][..], b"kept"].concat()),
+    vec![
+      dm.clone(),
+      TelnetEvents::DataReceive(Bytes::from_static(b"kept"))
+    ]
+  );
+  assert!(!instance.is_synching());
+
+  // A Data Mark outside of a Synch doesn't discard anything.
+  assert_eq!(
+    instance.receive(&[&b"a"[..], &[cmd::IAC, cmd::DM], b"b"].concat()),
+    vec![
+      TelnetEvents::DataReceive(Bytes::from_static(b"a")),
+      dm,
+      TelnetEvents::DataReceive(Bytes::from_static(b"b")),
+    ]
+  );
+
+  assert_eq!(
+    instance.send_synch(),
+    TelnetEvents::DataSend(Bytes::from_static(&[cmd::IAC, cmd::DM]))
+  );
+}
+
+/// Test MSP triggers being stripped from received data.
+#[test]
+fn test_msp_triggers() {
+  let mut instance = Parser::new();
+  instance.options.support_remote(opt::MSP);
+
+  // Triggers are left alone until MSP is enabled.
+  assert_eq!(
+    instance.receive(b"!!SOUND(a.wav)"),
+    vec![TelnetEvents::DataReceive(Bytes::from_static(
+      b"!!SOUND(a.wav)"
+    ))]
+  );
+  instance.receive(&[cmd::IAC, cmd::WILL, opt::MSP]);
+  assert!(instance.remote_enabled(opt::MSP));
+
+  let mut events = instance.receive(b"You hear thunder.!!SOU");
+  events.extend(instance.receive(b"ND(thunder.wav V=80 L=2 P=60 T=weather)\r\n!!MUS"));
+  events.extend(instance.receive(b"IC(theme.mid C=1 U=http://example.com/)"));
+  let mut thunder = MspTrigger::new(MspKind::Sound, "thunder.wav");
+  thunder.volume = Some(80);
+  thunder.repeats = Some(2);
+  thunder.priority = Some(60);
+  thunder.sound_type = Some("weather".to_string());
+  let mut theme = MspTrigger::new(MspKind::Music, "theme.mid");
+  theme.continue_music = Some(true);
+  theme.url = Some("http://example.com/".to_string());
+  assert_eq!(
+    events,
+    vec![
+      TelnetEvents::DataReceive(Bytes::from_static(b"You hear thunder.")),
+      TelnetEvents::Msp(thunder),
+      TelnetEvents::DataReceive(Bytes::from_static(b"\r\n")),
+      TelnetEvents::Msp(theme),
+    ]
+  );
+
+  // A prompt ending in a possible trigger prefix is released by the command after it.
+  assert_eq!(
+    instance.receive(&[&b"Are you sure!"[..], &[cmd::IAC, cmd::GA]].concat()),
+    vec![
+      TelnetEvents::DataReceive(Bytes::from_static(b"Are you sure!")),
+      TelnetEvents::IAC(TelnetIAC::new(cmd::GA)),
+    ]
+  );
+  assert_eq!(
+    instance.receive(b"Sure?!"),
+    vec![TelnetEvents::DataReceive(Bytes::from_static(b"Sure?"))]
+  );
+  assert_eq!(
+    instance.receive(&[cmd::IAC, cmd::EOR]),
+    vec![
+      TelnetEvents::DataReceive(Bytes::from_static(b"!")),
+      TelnetEvents::IAC(TelnetIAC::new(cmd::EOR)),
+    ]
+  );
+
+  // A partial trigger is released as text before MSP is disabled.
+  assert_eq!(instance.receive(b"!!SO"), vec![]);
+  let events = instance.receive(&[cmd::IAC, cmd::WONT, opt::MSP]);
+  assert_eq!(
+    events.first(),
+    Some(&TelnetEvents::DataReceive(Bytes::from_static(b"!!SO")))
+  );
+  assert!(!instance.remote_enabled(opt::MSP));
+}
+
+/// Test ZMP commands being dispatched through a registry.
+#[test]
+fn test_zmp_registry() {
+  let mut client = Parser::new();
+  client.options.support_remote(opt::ZMP);
+  let mut registry = ZmpRegistry::new("Blightmud", "5.3.1", "A terminal MUD client");
+  registry.register("color.define", |command| {
+    Some(ZmpCommand::new("color.ok", command.args.clone()))
+  });
+  client.set_zmp_registry(Some(registry));
+
+  // The client identifies itself once ZMP is enabled.
+  let events = client.receive(&[cmd::IAC, cmd::WILL, opt::ZMP]);
+  let ident = ZmpCommand::new(
+    zmp::cmd::IDENT,
+    vec!["Blightmud", "5.3.1", "A terminal MUD client"],
+  );
+  assert_eq!(
+    events,
+    vec![
+      TelnetEvents::DataSend(Bytes::copy_from_slice(&[cmd::IAC, cmd::DO, opt::ZMP])),
+      TelnetEvents::Negotiation(TelnetNegotiation::new(cmd::WILL, opt::ZMP)),
+      TelnetEvents::DataSend(TelnetSubnegotiation::from(ident).to_bytes()),
+    ]
+  );
+
+  let check =
+    TelnetSubnegotiation::from(ZmpCommand::new(zmp::cmd::CHECK, vec!["color.define"])).to_bytes();
+  let events = client.receive(&check);
+  assert_eq!(
+    events[1],
+    TelnetEvents::DataSend(
+      TelnetSubnegotiation::from(ZmpCommand::new(zmp::cmd::SUPPORT, vec!["color.define"]))
+        .to_bytes()
+    )
+  );
+
+  let define =
+    TelnetSubnegotiation::from(ZmpCommand::new("color.define", vec!["1", "red"])).to_bytes();
+  let events = client.receive(&define);
+  assert_eq!(
+    events[1],
+    TelnetEvents::DataSend(
+      TelnetSubnegotiation::from(ZmpCommand::new("color.ok", vec!["1", "red"])).to_bytes()
+    )
+  );
+
+  // Commands without a handler are only reported.
+  let unknown = TelnetSubnegotiation::from(ZmpCommand::new("sound.play", vec!["x"])).to_bytes();
+  assert_eq!(
+    handle_events(client.receive(&unknown)),
+    events![Event::Subnegotiation]
+  );
+}
+
+/// Test that a parser, including any ZMP registry, can be moved to and shared with other threads.
+#[test]
+fn test_parser_send_sync() {
+  fn assert_send_sync<T: Send + Sync>() {}
+  assert_send_sync::<Parser>();
+  assert_send_sync::<ZmpRegistry>();
+}
+
+#[cfg(feature = "mccp")]
+mod mccp_tests {
+  use super::*;
+
+  fn compressing_pair() -> (Parser, Parser) {
+    let mut server = Parser::new();
+    let mut client = Parser::new();
+    server.options.support_local(opt::MCCP2);
+    client.options.support_remote(opt::MCCP2);
+    client.set_mccp(true);
+    client.receive(&[cmd::IAC, cmd::WILL, opt::MCCP2]);
+    server._will(opt::MCCP2);
+    server.receive(&[cmd::IAC, cmd::DO, opt::MCCP2]);
+    (server, client)
+  }
+
+  #[test]
+  fn test_mccp2_transparent() {
+    let (mut server, mut client) = compressing_pair();
+
+    let start = server.start_compression(opt::MCCP2).unwrap().to_bytes();
+    assert_eq!(
+      &start[..],
+      &[cmd::IAC, cmd::SB, opt::MCCP2, cmd::IAC, cmd::SE]
+    );
+    assert!(server.is_compressing());
+
+    let text = server.send_text("Hello, world!").to_bytes();
+    assert_ne!(&text[..], b"Hello, world!\r\n");
+    let more = server.send_text("More text").to_bytes();
+
+    let events = client.receive(&[&start[..], &text[..]].concat());
+    assert!(client.is_decompressing());
+    assert_eq!(
+      events,
+      vec![
+        TelnetEvents::Subnegotiation(TelnetSubnegotiation::new(opt::MCCP2, Bytes::new())),
+        TelnetEvents::DataReceive(Bytes::copy_from_slice(b"Hello, world!\r\n")),
+      ]
+    );
+
+    // Split the rest of the compressed stream across two receives.
+    let end = server.end_compression().unwrap().to_bytes();
+    let rest = [&more[..], &end[..], b"plain"].concat();
+    let (first, second) = rest.split_at(3);
+    let mut events = client.receive(first);
+    events.extend(client.receive(second));
+    let received: Vec<u8> = events
+      .into_iter()
+      .flat_map(|ev| match ev {
+        TelnetEvents::DataReceive(data) => data,
+        ev => panic!("unexpected event {:?}", ev),
+      })
+      .collect();
+    assert_eq!(received, b"More text\r\nplain");
+    assert!(!client.is_decompressing());
+    assert!(!server.is_compressing());
+  }
+
+  /// Test enabling decompression while pulling events, before the compressed data is processed.
+  #[test]
+  fn test_mccp2_pull() {
+    let (mut server, mut client) = compressing_pair();
+    client.set_mccp(false);
+
+    let start = server.start_compression(opt::MCCP2).unwrap().to_bytes();
+    let text = server.send_text("Hello, world!").to_bytes();
+    client.feed(&[&b"plain"[..], &start[..], &text[..]].concat());
+
+    assert_eq!(
+      client.next_event(),
+      Some(TelnetEvents::DataReceive(Bytes::from_static(b"plain")))
+    );
+    client.set_mccp(true);
+    let events: Vec<_> = client.events().collect();
+    assert_eq!(
+      events,
+      vec![
+        TelnetEvents::Subnegotiation(TelnetSubnegotiation::new(opt::MCCP2, Bytes::new())),
+        TelnetEvents::DataReceive(Bytes::copy_from_slice(b"Hello, world!\r\n")),
+      ]
+    );
+    assert!(client.is_decompressing());
+  }
+
+  /// Test decompressed data being processed one sequence at a time as well.
+  #[test]
+  fn test_mccp2_pull_decompressed() {
+    let (mut server, mut client) = compressing_pair();
+    client.options.support_local(opt::GMCP);
+
+    let start = server.start_compression(opt::MCCP2).unwrap().to_bytes();
+    let hello = server.send_text("Hello").to_bytes();
+    let negotiation = server.negotiate(cmd::DO, opt::GMCP).to_bytes();
+    let end = server.end_compression().unwrap().to_bytes();
+    client.feed(&[&start[..], &hello, &negotiation, &end, b"plain"].concat());
+
+    assert_eq!(
+      client.next_event(),
+      Some(TelnetEvents::Subnegotiation(TelnetSubnegotiation::new(
+        opt::MCCP2,
+        Bytes::new()
+      )))
+    );
+    assert_eq!(
+      client.next_event(),
+      Some(TelnetEvents::DataReceive(Bytes::from_static(b"Hello\r\n")))
+    );
+    // The negotiation following the text has not been processed yet.
+    assert!(!client.local_enabled(opt::GMCP));
+    assert_eq!(
+      client.next_event(),
+      Some(TelnetEvents::DataSend(Bytes::from_static(&[
+        cmd::IAC,
+        cmd::WILL,
+        opt::GMCP
+      ])))
+    );
+    assert!(client.local_enabled(opt::GMCP));
+    assert_eq!(
+      client.events().collect::<Vec<_>>(),
+      vec![
+        TelnetEvents::Negotiation(TelnetNegotiation::new(cmd::DO, opt::GMCP)),
+        TelnetEvents::DataReceive(Bytes::from_static(b"plain")),
+      ]
+    );
+    assert!(!client.is_decompressing());
+  }
+
+  #[test]
+  fn test_mccp2_corrupt() {
+    let corrupt = [
+      &[cmd::IAC, cmd::SB, opt::MCCP2, cmd::IAC, cmd::SE][..],
+      b"this is not compressed",
+    ]
+    .concat();
+    let (_, mut puller) = compressing_pair();
+    puller.feed(&corrupt);
+    assert_eq!(
+      handle_events(puller.events().collect()),
+      events![Event::Subnegotiation, Event::DecomError]
+    );
+    assert!(puller.is_decompression_broken());
+
+    let (_, mut client) = compressing_pair();
+    let events = client.receive(&corrupt);
+    assert_eq!(
+      handle_events(events),
+      events![Event::Subnegotiation, Event::DecomError]
+    );
+    assert!(!client.is_decompressing());
+    assert!(client.is_decompression_broken());
+
+    // Whatever follows is part of the broken stream, and is discarded.
+    let more = [&b"more"[..], &[cmd::IAC, cmd::GA]].concat();
+    assert_eq!(client.receive(&more), vec![]);
+    client.feed(&more);
+    assert_eq!(client.next_event(), None);
+
+    client.reset_decompression();
+    assert!(!client.is_decompression_broken());
+    assert_eq!(
+      client.receive(b"plain"),
+      vec![TelnetEvents::DataReceive(Bytes::from_static(b"plain"))]
+    );
+  }
+
+  #[test]
+  fn test_mccp2_dont_ends_stream() {
+    let (mut server, mut client) = compressing_pair();
+    let start = server.start_compression(opt::MCCP2).unwrap().to_bytes();
+    client.receive(&start);
+
+    let events = server.receive(&[cmd::IAC, cmd::DONT, opt::MCCP2]);
+    assert!(!server.is_compressing());
+    let sent: Vec<u8> = events
+      .into_iter()
+      .filter(|ev| matches!(ev, TelnetEvents::DataSend(_)))
+      .flat_map(|ev| ev.to_bytes())
+      .collect();
+    // The stream end is followed by an uncompressed WONT.
+    assert!(sent.ends_with(&[cmd::IAC, cmd::WONT, opt::MCCP2]));
+    // The client sees the WONT uncompressed, and agrees to it.
+    assert_eq!(
+      handle_events(client.receive(&sent)),
+      events![Event::Send, Event::Negotiation]
+    );
+    assert!(!client.is_decompressing());
+  }
+}
+
+/// Test escaping IAC bytes in a buffer.
+#[test]
+fn test_escape() {
+  let initial = vec![
+    cmd::IAC,
+    cmd::SB,
+    201,
+    cmd::IAC,
+    205,
+    202,
+    cmd::IAC,
+    cmd::SE,
+  ];
+  let expected = Bytes::copy_from_slice(&[
+    cmd::IAC,
+    cmd::IAC,
+    cmd::SB,
+    201,
+    cmd::IAC,
+    cmd::IAC,
+    205,
+    202,
+    cmd::IAC,
+    cmd::IAC,
+    cmd::SE,
+  ]);
+  assert_eq!(expected, Parser::escape_iac(initial.clone()));
+  assert_eq!(initial, Parser::unescape_iac(expected));
+}
+
+/// Test unescaping IAC bytes in a buffer.
+#[test]
+fn test_unescape() {
+  let initial = vec![
+    cmd::IAC,
+    cmd::IAC,
+    cmd::SB,
+    201,
+    cmd::IAC,
+    cmd::IAC,
+    205,
+    202,
+    cmd::IAC,
+    cmd::IAC,
+    cmd::SE,
+  ];
+  let expected = Bytes::copy_from_slice(&[
+    cmd::IAC,
+    cmd::SB,
+    201,
+    cmd::IAC,
+    205,
+    202,
+    cmd::IAC,
+    cmd::SE,
+  ]);
+  assert_eq!(expected, Parser::unescape_iac(initial.clone()));
+  assert_eq!(initial, Parser::escape_iac(expected));
+}
+
+#[test]
+fn test_escape_roundtrip_bug_one() {
+  // The original libtelnet-rs mishandles this input:
+  let data = vec![cmd::IAC, cmd::IAC, 228];
+  let escaped = Parser::escape_iac(data.clone());
+  let unescaped = Parser::unescape_iac(escaped);
+  assert_eq!(unescaped, data);
+}
+
+#[test]
+fn test_escape_roundtrip_bug_two() {
+  // The original libtelnet-rs mishandles this input:
+  let data = vec![228, cmd::IAC, cmd::IAC];
+  let escaped = Parser::escape_iac(data.clone());
+  let unescaped = Parser::unescape_iac(escaped);
+  assert_eq!(unescaped, data);
+}
+
+#[test]
+fn test_bad_subneg_dbuffer() {
+  // Configure opt 0xFF (IAC) as local supported, and local state enabled.
+  let entry = CompatibilityEntry::new(true, false, true, false);
+  let opts = CompatibilityTable::from_options(&[(cmd::IAC, entry.into_u8())]);
+  // Receive a malformed subnegotiation - this should not panic.
+  Parser::with_support(opts).receive(&[cmd::IAC, cmd::SB, cmd::IAC, cmd::SE]);
+}
+
+#[test]
+fn test_into_bytes() {
+  let bytes = libmudtelnet::events::TelnetIAC::new(cmd::IAC).to_bytes();
+  assert!(!bytes.is_empty())
+}
+
+#[cfg(test)]
+mod compat_tests {
+  use compat::{test_app, test_limited_app, LimitedApplication, TelnetApplication};
+
+  #[test]
+  fn test_parser_diff1() {
+    test_app(&TelnetApplication {
+      options: vec![(255, 254)],
+      received_data: vec![vec![255, 255, 255, 255, 255, 254, 255, 0]],
+    });
+  }
+
+  #[test]
+  fn test_parser_diff2() {
+    test_app(&TelnetApplication {
+      options: vec![],
+      received_data: vec![vec![45, 255, 250, 255]],
+    });
+  }
+
+  #[test]
+  fn test_parser_diff3() {
+    test_app(&TelnetApplication {
+      options: vec![(0, 1)],
+      received_data: vec![vec![255, 253, 0]],
+    })
+  }
+
+  #[test]
+  fn test_parser_diff4() {
+    test_app(&TelnetApplication {
+      options: vec![],
+      received_data: vec![vec![255, 250, 255, 255, 240, 250]],
+    })
+  }
+
+  #[test]
+  fn test_parser_diff5() {
+    test_app(&TelnetApplication {
+      options: vec![],
+      received_data: vec![vec![255, 250, 255, 240, 0]],
+    })
+  }
+
+  #[test]
+  fn test_parser_diff6() {
+    test_app(&TelnetApplication {
+      options: vec![],
+      received_data: vec![vec![240, 255, 250, 255, 240, 0]],
+    })
+  }
+
+  #[test]
+  fn test_parser_diff7() {
+    test_app(&TelnetApplication {
+      options: vec![],
+      received_data: vec![vec![255]],
+    })
+  }
+
+  #[test]
+  fn test_parser_diff8() {
+    test_app(&TelnetApplication {
+      options: vec![],
+      received_data: vec![vec![255, 252, 0]],
+    })
+  }
+
+  #[test]
+  fn test_parser_diff9() {
+    test_app(&TelnetApplication {
+      options: vec![],
+      received_data: vec![vec![254, 255, 255, 255, 254, 0]],
+    })
+  }
+
+  #[test]
+  fn test_parser_diff10() {
+    test_app(&TelnetApplication {
+      options: vec![(255, 254), (1, 0)],
+      received_data: vec![vec![255, 253, 255]],
+    })
+  }
+
+  #[test]
+  fn test_limited_app_split_subnegotiation() {
+    for overflow_policy in 0..3 {
+      test_limited_app(&LimitedApplication {
+        app: TelnetApplication {
+          options: vec![(201, 5)],
+          received_data: vec![vec![255, 250, 201, 1, 2, 3, 4, 5], vec![6, 7, 255, 240]],
+        },
+        max_subnegotiation_len: 3,
+        max_buffered_bytes: 2,
+        overflow_policy,
+      })
+    }
+  }
+
+  #[test]
+  fn test_limited_app_split_after_iac() {
+    for overflow_policy in 0..3 {
+      test_limited_app(&LimitedApplication {
+        app: TelnetApplication {
+          options: vec![(201, 5)],
+          received_data: vec![vec![255, 250, 201, 1, 2, 3, 255], vec![240]],
+        },
+        max_subnegotiation_len: 3,
+        max_buffered_bytes: 2,
+        overflow_policy,
+      })
+    }
+  }
+
+  #[test]
+  fn test_limited_app_split_after_command() {
+    for (max_subnegotiation_len, max_buffered_bytes) in [(2, 4), (4, 2)] {
+      test_limited_app(&LimitedApplication {
+        app: TelnetApplication {
+          options: vec![(201, 5)],
+          received_data: vec![vec![255, 250, 201, 1, 255, 249], vec![255, 240]],
+        },
+        max_subnegotiation_len,
+        max_buffered_bytes,
+        overflow_policy: 0,
+      })
+    }
   }
 }